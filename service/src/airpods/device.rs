@@ -5,13 +5,13 @@
 
 use core::fmt;
 use std::{
-   collections::HashMap,
+   collections::{BTreeSet, HashMap},
    mem,
    sync::{
       Arc, Weak,
       atomic::{AtomicBool, Ordering},
    },
-   time::Duration,
+   time::{Duration, Instant, SystemTime},
 };
 
 use bluer::Address;
@@ -20,7 +20,7 @@ use log::{debug, error, info, warn};
 use serde_json::json;
 use smol_str::{SmolStr, ToSmolStr};
 use tokio::{
-   sync::{RwLock, oneshot},
+   sync::{Mutex, Notify, RwLock, oneshot},
    task::{JoinHandle, JoinSet},
    time,
 };
@@ -29,45 +29,186 @@ use crate::{
    airpods::{
       parser,
       protocol::{
-         BatteryInfo, EarDetectionStatus, FeatureBitmap, FeatureCmd, FeatureId, HDR_ACK_FEATURES,
-         HDR_ACK_HANDSHAKE, HDR_BATTERY_STATE, HDR_EAR_DETECTION, HDR_METADATA, HDR_NOISE_CTL,
-         NoiseControlMode, PKT_HANDSHAKE, PKT_REQUEST_NOTIFY, PKT_SET_FEATURES,
-         build_control_packet,
+         BatteryInfo, BatteryOffsets, Component, EarDetectionSensitivity, EarDetectionStatus,
+         FeatureBitmap, FeatureCmd, FeatureId, HDR_AAP, HDR_ACK_FEATURES, HDR_ACK_HANDSHAKE,
+         HDR_BATTERY_STATE, HDR_CMD_CTL, HDR_EAR_DETECTION, HDR_HEAD_TRACKING, HDR_METADATA,
+         HDR_NOISE_CTL, HoldDuration, IgnoredEarSensors, NoiseControlMode, PKT_HANDSHAKE,
+         PKT_REQUEST_NOTIFY, PKT_SET_FEATURES, PressSpeed, TapAction, build_control_packet,
       },
+      recognition::DeviceModel,
    },
    battery_study::{BatteryStudy, BatteryTracker},
-   bluetooth::l2cap::{self, L2CapReceiver, L2CapSender, Packet},
+   bluetooth::{
+      att,
+      l2cap::{self, L2CapReceiver, L2CapSender, Packet},
+      transport,
+   },
    error::{AirPodsError, Result},
-   event::{AirPodsEvent, EventSender},
+   event::{AirPodsEvent, ConnectionState, DisconnectReason, EventSender},
+   ringbuf::Ring,
+   signal_trend::{self, RssiHistory, SignalTrend},
+   smoothing,
 };
 
+/// Number of errors retained per device.
+const ERROR_HISTORY_SIZE: usize = 16;
+/// Maximum stored length of an error message, in bytes.
+const ERROR_MESSAGE_LEN: usize = 120;
+/// Log target for the audit line emitted for every command sent to a device.
+const AAP_LOG_TARGET: &str = "kairpods::aap";
+/// Time without motion samples after which head tracking counts as stopped.
+const HEAD_TRACKING_TIMEOUT: Duration = Duration::from_secs(3);
+/// Maximum time to wait for the battery report asked for by a refresh.
+const BATTERY_REFRESH_TIMEOUT: Duration = Duration::from_secs(5);
+/// Maximum time to wait for the device to report a noise mode we set.
+const NOISE_CONFIRM_TIMEOUT: Duration = Duration::from_secs(2);
+
 /// Internal state for an active L2CAP connection.
 #[derive(Debug)]
-struct ConnectionState {
+struct ActiveConnection {
    sender: l2cap::L2CapSender,
    jset: JoinSet<()>,
 }
 
-impl Drop for ConnectionState {
+impl Drop for ActiveConnection {
    fn drop(&mut self) {
       self.jset.abort_all();
    }
 }
 
+/// A recorded device error.
+///
+/// Stored inline so it can live in a [`Ring`].
+#[derive(Clone, Copy)]
+pub struct ErrorEntry {
+   timestamp: u64, // Unix timestamp
+   len: u8,
+   message: [u8; ERROR_MESSAGE_LEN],
+}
+
+impl Default for ErrorEntry {
+   fn default() -> Self {
+      Self {
+         timestamp: 0,
+         len: 0,
+         message: [0; ERROR_MESSAGE_LEN],
+      }
+   }
+}
+
+impl fmt::Debug for ErrorEntry {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      write!(f, "[{}] {}", self.timestamp, self.message())
+   }
+}
+
+impl ErrorEntry {
+   /// Creates an entry stamped with the current time, truncating long messages.
+   pub fn new(message: &str) -> Self {
+      let mut len = message.len().min(ERROR_MESSAGE_LEN);
+      while !message.is_char_boundary(len) {
+         len -= 1;
+      }
+      let mut entry = Self {
+         timestamp: SystemTime::UNIX_EPOCH.elapsed().map_or(0, |d| d.as_secs()),
+         len: len as u8,
+         ..Default::default()
+      };
+      entry.message[..len].copy_from_slice(&message.as_bytes()[..len]);
+      entry
+   }
+
+   pub fn message(&self) -> &str {
+      str::from_utf8(&self.message[..self.len as usize]).unwrap_or_default()
+   }
+
+   pub fn to_json(self) -> serde_json::Value {
+      json!({
+         "timestamp": self.timestamp,
+         "message": self.message(),
+      })
+   }
+}
+
+/// Retry policy for AAP command sends.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+   /// Number of retries after the first failed attempt
+   pub retries: u32,
+   /// Delay between attempts
+   pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+   fn default() -> Self {
+      Self {
+         retries: 2,
+         backoff: Duration::from_millis(50),
+      }
+   }
+}
+
+/// What the device sent back while setting up the current connection.
+#[derive(Debug, Default)]
+struct Handshake {
+   ack: Option<Packet>,
+   features_ack: Option<Packet>,
+   /// Opcodes of the AAP frames received since
+   opcodes: BTreeSet<u8>,
+}
+
 /// Internal shared state for an `AirPods` device.
 #[derive(Debug, Default)]
 struct AirPodsInner {
    address: Address,
    address_str: SmolStr,
+   model: Option<DeviceModel>,
    name: parking_lot::Mutex<SmolStr>,
    battery: AtomicCell<Option<BatteryInfo>>,
+   /// Last battery frame as received, for checking the decoder
+   battery_raw: parking_lot::Mutex<Option<Packet>>,
+   handshake: parking_lot::Mutex<Handshake>,
    is_connected: AtomicBool,
+   /// Set once the current connection has reported the device state
+   is_ready: AtomicBool,
+   ready: Notify,
+   /// Signalled on every battery report, changed or not
+   battery_received: Notify,
+   /// Signalled on every noise mode report, changed or not
+   noise_mode_received: Notify,
+   emit_raw_events: AtomicBool,
+   emit_spatial_events: AtomicBool,
+   /// When the last head-tracking sample arrived
+   head_tracking_at: AtomicCell<Option<Instant>>,
+   retry_policy: AtomicCell<RetryPolicy>,
    ear_detection: AtomicCell<Option<EarDetectionStatus>>,
    noise_mode: AtomicCell<Option<NoiseControlMode>>,
+   volume: AtomicCell<Option<u8>>,
+   /// Smoothed signal strength, in dBm
+   rssi: AtomicCell<Option<f64>>,
+   /// Recent raw signal strength readings, for the trend
+   rssi_history: parking_lot::Mutex<RssiHistory>,
+   /// Last Loud Sound Reduction state we set
+   loud_sound_reduction: AtomicCell<Option<bool>>,
    features: FeatureBitmap,
    features_present: FeatureBitmap,
-   conn: RwLock<Option<ConnectionState>>,
+   press_speed: AtomicCell<Option<PressSpeed>>,
+   hold_duration: AtomicCell<Option<HoldDuration>>,
+   /// Calibration applied to reported battery levels
+   battery_offsets: AtomicCell<BatteryOffsets>,
+   /// Ear sensors the media policy disregards
+   ignored_ear_sensors: AtomicCell<IgnoredEarSensors>,
+   connection_state: AtomicCell<ConnectionState>,
+   conn: RwLock<Option<ActiveConnection>>,
+   /// Held across a command's send and the state update that follows it, so
+   /// concurrent clients' commands reach the device one at a time
+   command_lock: Mutex<()>,
+   /// Held while a noise mode change waits for its confirmation, so two
+   /// changes can't take each other's report, without holding up other
+   /// commands meanwhile
+   noise_lock: Mutex<()>,
    battery_tracker: parking_lot::Mutex<BatteryTracker>,
+   errors: parking_lot::Mutex<Ring<ErrorEntry, ERROR_HISTORY_SIZE>>,
 }
 
 /// Represents a connected `AirPods` device.
@@ -134,10 +275,16 @@ impl<T: PartialEq> UpdateOp<T> {
 
 impl AirPods {
    /// Creates a new `AirPods` device instance.
-   pub fn new(address: Address, name: String, battery_study: Option<BatteryStudy>) -> Self {
+   pub fn new(
+      address: Address,
+      name: String,
+      model: Option<DeviceModel>,
+      battery_study: Option<BatteryStudy>,
+   ) -> Self {
       Self(Arc::new(AirPodsInner {
          address,
          address_str: address.to_smolstr(),
+         model,
          name: parking_lot::Mutex::new(name.into()),
          battery_tracker: parking_lot::Mutex::new(BatteryTracker::new(battery_study)),
          ..Default::default()
@@ -154,6 +301,20 @@ impl AirPods {
       &self.0.address_str
    }
 
+   /// Gets the detected model of the Airpod, if known.
+   pub fn model(&self) -> Option<DeviceModel> {
+      self.0.model
+   }
+
+   /// Gets the noise modes the Airpod supports, in display order. Models
+   /// whose capabilities are unknown get [`NoiseControlMode::COMMON`].
+   pub fn noise_modes(&self) -> &'static [NoiseControlMode] {
+      self
+         .model()
+         .and_then(DeviceModel::noise_modes)
+         .unwrap_or(NoiseControlMode::COMMON)
+   }
+
    /// Gets the name of the Airpod.
    pub fn name(&self) -> SmolStr {
       self.0.name.lock().clone()
@@ -168,11 +329,108 @@ impl AirPods {
       UpdateOp::Updated(mem::replace(&mut *lock, name))
    }
 
+   /// Enables or disables raw frame events for the Airpod.
+   pub fn set_emit_raw_events(&self, enabled: bool) {
+      self.0.emit_raw_events.store(enabled, Ordering::Relaxed);
+   }
+
+   /// Enables or disables head-tracking state events for the Airpod.
+   pub fn set_emit_spatial_events(&self, enabled: bool) {
+      self.0.emit_spatial_events.store(enabled, Ordering::Relaxed);
+   }
+
+   /// Sets the calibration added to the Airpod's reported battery levels.
+   pub fn set_battery_offsets(&self, offsets: BatteryOffsets) {
+      self.0.battery_offsets.store(offsets);
+   }
+
+   /// Gets the ear sensors the media policy disregards.
+   pub fn ignored_ear_sensors(&self) -> IgnoredEarSensors {
+      self.0.ignored_ear_sensors.load()
+   }
+
+   /// Sets the ear sensors the media policy disregards.
+   pub fn set_ignored_ear_sensors(&self, ignored: IgnoredEarSensors) {
+      self.0.ignored_ear_sensors.store(ignored);
+   }
+
+   /// Records an error in the Airpod's error history.
+   pub fn record_error(&self, message: &str) {
+      self.0.errors.lock().push(ErrorEntry::new(message));
+   }
+
+   /// Gets the most recent error of the Airpod.
+   pub fn last_error(&self) -> Option<ErrorEntry> {
+      self.0.errors.lock().last().copied()
+   }
+
+   /// Gets the error history of the Airpod, oldest first.
+   pub fn error_log(&self) -> Vec<ErrorEntry> {
+      self.0.errors.lock().iter().copied().collect()
+   }
+
+   /// Sets the retry policy for commands sent to the Airpod.
+   pub fn set_retry_policy(&self, policy: RetryPolicy) {
+      self.0.retry_policy.store(policy);
+   }
+
+   /// Sets how many battery samples are kept for drain-rate estimation.
+   pub fn set_battery_history_len(&self, len: usize) {
+      self.0.battery_tracker.lock().set_history_len(len);
+   }
+
+   /// Gets the recorded battery samples of both buds.
+   pub fn battery_history_json(&self) -> serde_json::Value {
+      self.0.battery_tracker.lock().history_json()
+   }
+
    /// Gets the battery information of the Airpod.
    pub fn battery_info(&self) -> Option<BatteryInfo> {
       self.0.battery.load()
    }
 
+   /// Gets the handshake of the current connection along with what the
+   /// device has shown it supports.
+   ///
+   /// The acknowledgment payloads are not decoded yet, so they are given as
+   /// hex for comparing models.
+   pub fn handshake_json(&self) -> serde_json::Value {
+      let handshake = self.0.handshake.lock();
+      let model = self.model();
+      json!({
+         "handshake_ack": handshake.ack.as_ref().map(hex::encode),
+         "features_ack": handshake.features_ack.as_ref().map(hex::encode),
+         "opcodes": handshake.opcodes,
+         "model": model.map(|m| m.to_str()),
+         "capabilities": {
+            "noise_modes": model
+               .and_then(|m| m.noise_modes())
+               .map(|modes| modes.iter().map(|m| m.to_str()).collect::<Vec<_>>()),
+            "loud_sound_reduction": model.is_some_and(|m| m.supports_loud_sound_reduction()),
+            "features": FeatureId::known()
+               .filter(|&feat| self.supports_feature(feat))
+               .map(|feat| feat.to_str())
+               .collect::<Vec<_>>(),
+         },
+      })
+   }
+
+   /// Gets the last battery frame as hex with the decoder's reading of it.
+   pub fn battery_raw_json(&self) -> Option<serde_json::Value> {
+      let packet = self.0.battery_raw.lock().clone()?;
+      let mut info = json!({ "raw": hex::encode(&packet) });
+      match parser::parse_battery_status(&packet) {
+         Ok(battery) => info["decoded"] = battery.to_json(),
+         Err(e) => info["error"] = json!(e.to_string()),
+      }
+      Some(info)
+   }
+
+   /// Gets which bud is currently primary, `"left"` or `"right"`.
+   pub fn primary_bud(&self) -> Option<&'static str> {
+      self.battery_info().and_then(|b| b.primary_str())
+   }
+
    /// Replaces the battery information of the Airpod.
    pub fn update_battery_info(
       &self,
@@ -186,6 +444,62 @@ impl AirPods {
       self.0.is_connected.load(Ordering::Relaxed)
    }
 
+   /// Checks if the current connection has completed its first state update.
+   pub fn is_ready(&self) -> bool {
+      self.0.is_ready.load(Ordering::Relaxed)
+   }
+
+   /// Forgets the state update of a previous connection.
+   pub fn reset_ready(&self) {
+      self.0.is_ready.store(false, Ordering::Relaxed);
+   }
+
+   fn mark_ready(&self, event_tx: &EventSender) {
+      if !self.0.is_ready.swap(true, Ordering::Relaxed) {
+         self.set_connection_state(ConnectionState::Ready, event_tx);
+         self.0.ready.notify_waiters();
+      }
+   }
+
+   /// Gets how far the device is through connecting.
+   pub fn connection_state(&self) -> ConnectionState {
+      self.0.connection_state.load()
+   }
+
+   /// Updates the connection state, returning whether it changed.
+   pub fn update_connection_state(&self, state: ConnectionState) -> bool {
+      self.0.connection_state.swap(state) != state
+   }
+
+   /// Updates the connection state, emitting an event when it changes.
+   ///
+   /// A handshake that has already produced the device's state is reported
+   /// as ready.
+   pub fn set_connection_state(&self, state: ConnectionState, event_tx: &EventSender) {
+      let state = if state == ConnectionState::Handshaking && self.is_ready() {
+         ConnectionState::Ready
+      } else {
+         state
+      };
+      if self.update_connection_state(state) {
+         debug!("{}: Connection state is now {state}", self.address_str());
+         event_tx.emit(self, AirPodsEvent::ConnectionStateChanged(state));
+      }
+   }
+
+   /// Waits until the handshake has completed and battery state was reported.
+   pub async fn wait_ready(&self) {
+      loop {
+         let notified = self.0.ready.notified();
+         tokio::pin!(notified);
+         notified.as_mut().enable();
+         if self.is_ready() {
+            return;
+         }
+         notified.await;
+      }
+   }
+
    /// Gets the ear detection status of the Airpod.
    pub fn ear_detection(&self) -> Option<EarDetectionStatus> {
       self.0.ear_detection.load()
@@ -212,16 +526,100 @@ impl AirPods {
       UpdateOp::apply_atomic(&self.0.noise_mode, mode.into())
    }
 
+   /// Gets the last reported volume of the Airpod, in percent.
+   pub fn volume(&self) -> Option<u8> {
+      self.0.volume.load()
+   }
+
+   /// Sets the reported volume of the Airpod.
+   pub fn update_volume(&self, volume: impl Into<Option<u8>>) -> UpdateOp<u8> {
+      UpdateOp::apply_atomic(&self.0.volume, volume.into())
+   }
+
+   /// Records a volume read from the media transport, emitting an event if
+   /// it changed.
+   pub fn report_volume(&self, volume: u8, event_tx: &EventSender) {
+      if self.update_volume(volume).is_updated() {
+         event_tx.emit(self, AirPodsEvent::VolumeChanged(volume));
+      }
+   }
+
+   /// Gets the smoothed signal strength of the Airpod, in dBm.
+   pub fn rssi(&self) -> Option<i16> {
+      self.0.rssi.load().map(|rssi| rssi.round() as i16)
+   }
+
+   /// Blends a new signal strength reading into the smoothed value.
+   pub fn update_rssi(&self, rssi: Option<i16>) {
+      let smoothed = rssi
+         .map(|rssi| smoothing::ema(self.0.rssi.load(), f64::from(rssi), smoothing::rssi_alpha()));
+      self.0.rssi.store(smoothed);
+
+      // A gap in readings breaks the trend
+      let mut history = self.0.rssi_history.lock();
+      match rssi {
+         Some(rssi) => history.push(rssi),
+         None => history.clear(),
+      }
+   }
+
+   /// Classifies where the signal strength has been heading lately.
+   pub fn signal_trend(&self) -> Option<SignalTrend> {
+      signal_trend::classify(&self.0.rssi_history.lock(), signal_trend::threshold())
+   }
+
+   /// Whether the Airpod is currently streaming head-tracking samples.
+   pub fn is_head_tracking(&self) -> bool {
+      self.0.head_tracking_at.load().is_some()
+   }
+
+   /// Records a head-tracking sample, returning true if tracking just started.
+   fn mark_head_tracking(&self) -> bool {
+      self.0.head_tracking_at.swap(Some(Instant::now())).is_none()
+   }
+
+   /// Clears the head-tracking state once samples stop, returning true if it
+   /// was active.
+   fn expire_head_tracking(&self, force: bool) -> bool {
+      let last = self.0.head_tracking_at.load();
+      match last {
+         Some(at) if force || at.elapsed() >= HEAD_TRACKING_TIMEOUT => {
+            self.0.head_tracking_at.compare_exchange(last, None).is_ok()
+         },
+         _ => false,
+      }
+   }
+
+   fn emit_spatial_state(&self, event_tx: &EventSender, active: bool) {
+      if self.0.emit_spatial_events.load(Ordering::Relaxed) {
+         event_tx.emit(self, AirPodsEvent::SpatialStateChanged(active));
+      }
+   }
+
    /// Converts the device state to a JSON representation.
    pub fn to_json(&self) -> serde_json::Value {
       let mut info = json!({
           "address": self.address_str().as_str(),
           "name": self.name().as_str(),
           "connected": self.is_connected(),
+          "connection_state": self.connection_state().to_str(),
       });
 
+      if let Some(model) = self.model() {
+         info["model"] = json!(model.to_str());
+         if let Some(modes) = model.noise_modes() {
+            info["supported_noise_modes"] =
+               modes.iter().map(|m| m.to_str()).collect::<Vec<_>>().into();
+         }
+      }
+
       if let Some(battery) = self.battery_info() {
          info["battery"] = battery.to_json();
+         info["primary"] = json!(battery.primary_str());
+      }
+
+      if let Some(cycles) = self.0.battery_tracker.lock().estimated_cycles() {
+         info["estimated_cycles"] = json!(cycles);
       }
 
       // Add battery TTL estimate
@@ -238,15 +636,48 @@ impl AirPods {
          info["ear_detection"] = ear.to_json();
       }
 
+      info["head_tracking"] = json!(self.is_head_tracking());
+
+      if let Some(rssi) = self.rssi() {
+         info["rssi"] = json!(rssi);
+      }
+      if let Some(trend) = self.signal_trend() {
+         info["signal_trend"] = json!(trend.to_str());
+      }
+
+      if let Some(volume) = self.volume() {
+         info["volume"] = json!(volume);
+      }
+
+      if let Some(enabled) = self.loud_sound_reduction() {
+         info["loud_sound_reduction"] = json!(enabled);
+      }
+
+      if let Some(speed) = self.0.press_speed.load() {
+         info["press_speed"] = json!(speed.to_str());
+      }
+
+      if let Some(duration) = self.0.hold_duration.load() {
+         info["hold_duration"] = json!(duration.to_str());
+      }
+
       let features_dict: HashMap<_, _> = self
          .features()
          .into_iter()
          .map(|(k, v)| (k.to_str(), v))
          .collect();
       info["features"] = json!(features_dict);
+      info["last_error"] = self
+         .last_error()
+         .map_or(serde_json::Value::Null, |e| e.to_json());
       info
    }
 
+   /// Gets the Loud Sound Reduction state last set on the Airpod.
+   pub fn loud_sound_reduction(&self) -> Option<bool> {
+      self.0.loud_sound_reduction.load()
+   }
+
    pub fn feature_enabled(&self, feature: FeatureId) -> bool {
       self.0.features.get(feature)
    }
@@ -260,6 +691,27 @@ impl AirPods {
          .collect()
    }
 
+   /// Checks whether the feature is supported, either because the device
+   /// reported it or because the model is expected to have it.
+   pub fn supports_feature(&self, feature: FeatureId) -> bool {
+      self.0.features_present.get(feature)
+         || self.model().is_none_or(|m| m.supports_feature(feature))
+   }
+
+   /// Lists all known features with their enabled and supported state.
+   pub fn features_json(&self) -> serde_json::Value {
+      FeatureId::known()
+         .map(|feat| {
+            json!({
+               "feature": feat.to_str(),
+               "enabled": self.feature_enabled(feat),
+               "supported": self.supports_feature(feat),
+            })
+         })
+         .collect::<Vec<_>>()
+         .into()
+   }
+
    pub fn set_feature_enabled(&self, feature: FeatureId, enabled: bool) -> bool {
       self.0.features_present.set(feature, true);
       self.0.features.set(feature, enabled)
@@ -283,7 +735,7 @@ impl AirPods {
       let jhandle = self.start_packet_processor(receiver, event_tx.clone());
 
       // Store connection state
-      *conn = Some(ConnectionState { sender, jset });
+      *conn = Some(ActiveConnection { sender, jset });
       self.0.is_connected.store(true, Ordering::Relaxed);
 
       // Initialize battery study session
@@ -302,18 +754,23 @@ impl AirPods {
       self.save_battery_study();
 
       self.0.is_connected.store(false, Ordering::Relaxed);
+      self.reset_ready();
       let _ = self.0.conn.write().await.take();
       info!("Disconnected from {}", self.address());
    }
 
-   async fn notify_disconnected(&self, event_tx: &EventSender) {
+   async fn notify_disconnected(&self, event_tx: &EventSender, reason: DisconnectReason) {
       // Save battery study data before disconnecting
       self.save_battery_study();
+      if self.expire_head_tracking(true) {
+         self.emit_spatial_state(event_tx, false);
+      }
 
       self.0.is_connected.store(false, Ordering::Relaxed);
+      self.reset_ready();
       let _ = self.0.conn.write().await.take();
       info!("Disconnected from {}", self.address());
-      event_tx.emit(self, AirPodsEvent::DeviceDisconnected);
+      event_tx.emit(self, AirPodsEvent::DeviceDisconnected(reason));
    }
 
    async fn start_connection(
@@ -327,6 +784,7 @@ impl AirPods {
             .map_err(|_| AirPodsError::ConnectionClosed)
       }
 
+      *self.0.handshake.lock() = Handshake::default();
       let (hs_ack_tx, mut hs_ack_rx) = oneshot::channel();
       let (feat_ack_tx, mut feat_ack_rx) = oneshot::channel();
 
@@ -420,7 +878,13 @@ impl AirPods {
                },
                Err(e) => {
                   if let Some(this) = weak.upgrade() {
-                     this.notify_disconnected(&event_tx).await;
+                     let reason = match e {
+                        AirPodsError::ConnectionLost | AirPodsError::RequestTimeout => {
+                           DisconnectReason::Timeout
+                        },
+                        _ => DisconnectReason::Error,
+                     };
+                     this.notify_disconnected(&event_tx, reason).await;
                   } else {
                      warn!("{addr}: Connection closed: {e:?}");
                   }
@@ -433,65 +897,296 @@ impl AirPods {
       })
    }
 
-   pub async fn set_noise_control(&self, mode: NoiseControlMode) -> Result<()> {
-      let conn = self.0.conn.read().await;
-      if let Some(conn) = conn.as_ref() {
-         let packet = build_control_packet(0x0D, (mode as u32).to_le_bytes());
-         conn.sender.send(&packet).await?;
-         self.0.noise_mode.store(Some(mode));
-         Ok(())
-      } else {
-         Err(AirPodsError::DeviceNotConnected)
+   /// Sets the noise control mode, returning the mode the device reports
+   /// back, which may differ if it rejected or adjusted the request. Without
+   /// a report in time, the requested mode is assumed.
+   pub async fn set_noise_control(&self, mode: NoiseControlMode) -> Result<NoiseControlMode> {
+      if let Some(model) = self.model()
+         && !model.supports_noise_mode(mode)
+      {
+         let supported: Vec<_> = model
+            .noise_modes()
+            .unwrap_or_default()
+            .iter()
+            .map(|m| m.to_str())
+            .collect();
+         return Err(AirPodsError::FeatureNotSupported(format!(
+            "noise mode {mode} on {model} (supported: {})",
+            supported.join(", ")
+         )));
+      }
+
+      let packet = build_control_packet(0x0D, (mode as u32).to_le_bytes());
+      let _noise_guard = self.0.noise_lock.lock().await;
+      let received = self.0.noise_mode_received.notified();
+      tokio::pin!(received);
+      received.as_mut().enable();
+      {
+         let _guard = self.0.command_lock.lock().await;
+         self.send_command("set_noise_control", &packet).await?;
+      }
+
+      if time::timeout(NOISE_CONFIRM_TIMEOUT, received).await.is_ok()
+         && let Some(confirmed) = self.noise_mode()
+      {
+         if confirmed != mode {
+            warn!(
+               "{}: Asked for noise mode {mode}, device applied {confirmed}",
+               self.address()
+            );
+         }
+         return Ok(confirmed);
+      }
+      debug!("{}: No noise mode report, assuming {mode}", self.address());
+      self.0.noise_mode.store(Some(mode));
+      Ok(mode)
+   }
+
+   /// Sets the playback volume of the Airpod, in percent.
+   pub async fn set_volume(&self, percent: u8) -> Result<()> {
+      transport::set_volume(self.address(), percent).await?;
+      self.update_volume(percent);
+      Ok(())
+   }
+
+   /// Toggles Loud Sound Reduction, which is set over ATT rather than AAP.
+   pub async fn set_loud_sound_reduction(&self, enabled: bool) -> Result<()> {
+      if let Some(model) = self.model()
+         && !model.supports_loud_sound_reduction()
+      {
+         return Err(AirPodsError::FeatureNotSupported(format!(
+            "loud sound reduction on {model}"
+         )));
+      }
+      if !self.is_connected() {
+         return Err(AirPodsError::DeviceNotConnected);
       }
+
+      att::write(
+         self.address(),
+         att::HANDLE_LOUD_SOUND_REDUCTION,
+         &[u8::from(enabled)],
+      )
+      .await?;
+      self.0.loud_sound_reduction.store(Some(enabled));
+      Ok(())
+   }
+
+   /// Gets the last ear tip fit test result per bud.
+   ///
+   /// Only the `AirPods` Pro models have the test, which runs from an Apple
+   /// host. No AAP frame carrying its result has been identified on them, so
+   /// every bud reports `unknown` for now; `supported` tells whether a result
+   /// can exist at all, and is `null` for an unrecognised model.
+   pub fn fit_test_json(&self) -> serde_json::Value {
+      json!({
+         "supported": self.model().map(DeviceModel::supports_fit_test),
+         "left": "unknown",
+         "right": "unknown",
+      })
+   }
+
+   /// Resets the device to factory settings.
+   ///
+   /// No AAP soft-reset sequence is known; `AirPods` can only be reset from
+   /// the case button, so this always fails with `FeatureNotSupported`.
+   pub async fn factory_reset(&self) -> Result<()> {
+      Err(AirPodsError::FeatureNotSupported(
+         "factory reset over AAP (hold the case button instead)".to_string(),
+      ))
+   }
+
+   /// Sets how readily the Airpod reports being taken out of the ear.
+   ///
+   /// No AAP command for this is known on any firmware, so this always fails
+   /// with `FeatureNotSupported`.
+   pub async fn set_ear_detection_sensitivity(&self, level: EarDetectionSensitivity) -> Result<()> {
+      Err(AirPodsError::FeatureNotSupported(format!(
+         "ear detection sensitivity ({level})"
+      )))
+   }
+
+   /// Asks the device to report its battery now and waits for the report.
+   ///
+   /// Any battery report counts, so an update the device pushes on its own
+   /// in the meantime completes the refresh early.
+   pub async fn refresh_battery(&self) -> Result<()> {
+      let received = self.0.battery_received.notified();
+      tokio::pin!(received);
+      received.as_mut().enable();
+      {
+         let _guard = self.0.command_lock.lock().await;
+         self
+            .send_command("refresh_battery", PKT_REQUEST_NOTIFY)
+            .await?;
+      }
+      time::timeout(BATTERY_REFRESH_TIMEOUT, received)
+         .await
+         .map_err(|_| AirPodsError::RequestTimeout)
+   }
+
+   /// Silences the Find My sound playing on a component.
+   ///
+   /// The sound is started through Find My rather than AAP and no AAP
+   /// command to stop it is known, so this always fails with
+   /// `FeatureNotSupported`.
+   pub async fn stop_sound(&self, component: Component) -> Result<()> {
+      Err(AirPodsError::FeatureNotSupported(format!(
+         "stopping the Find My sound on {component} over AAP"
+      )))
    }
 
    pub async fn passthrough(&self, packet: &[u8]) -> Result<()> {
+      let _guard = self.0.command_lock.lock().await;
+      self.send_command("passthrough", packet).await
+   }
+
+   /// Sends a command packet, recording it under the [`AAP_LOG_TARGET`] log target.
+   async fn send_command(&self, action: &str, packet: &[u8]) -> Result<()> {
+      let result = self.send_packet(packet).await;
+      debug!(
+         target: AAP_LOG_TARGET,
+         "action={action} address={} bytes={} result={}",
+         self.address(),
+         hex::encode(packet),
+         match &result {
+            Ok(()) => "ok".to_string(),
+            // Quoted so the message stays a single value
+            Err(e) => format!("{:?}", e.to_string()),
+         }
+      );
+      result
+   }
+
+   /// Sends a packet over the active connection, retrying transient write failures.
+   async fn send_packet(&self, packet: &[u8]) -> Result<()> {
       let conn = self.0.conn.read().await;
-      if let Some(conn) = conn.as_ref() {
-         conn.sender.send(packet).await?;
-         Ok(())
-      } else {
-         Err(AirPodsError::DeviceNotConnected)
+      let Some(conn) = conn.as_ref() else {
+         return Err(AirPodsError::DeviceNotConnected);
+      };
+
+      let policy = self.0.retry_policy.load();
+      let mut attempt = 0;
+      loop {
+         match conn.sender.send(packet).await {
+            // Write timeouts are already long; only retry immediate I/O failures
+            Err(e @ AirPodsError::Io(_)) if attempt < policy.retries => {
+               attempt += 1;
+               debug!(
+                  "{}: Send failed ({e}), retry {attempt}/{} in {:?}",
+                  self.address(),
+                  policy.retries,
+                  policy.backoff
+               );
+               time::sleep(policy.backoff).await;
+            },
+            result => {
+               if let Err(e) = &result {
+                  self.record_error(&format!("Send failed: {e}"));
+               }
+               return result;
+            },
+         }
       }
    }
 
    pub async fn set_feature(&self, feature: FeatureId, enabled: bool) -> Result<()> {
-      let conn = self.0.conn.read().await;
-      if let Some(conn) = conn.as_ref() {
-         let packet = if enabled {
-            FeatureCmd::Enable.build(feature.id())
-         } else {
-            FeatureCmd::Disable.build(feature.id())
-         };
-         conn.sender.send(&packet).await?;
-         self.set_feature_enabled(feature, enabled);
-         Ok(())
-      } else {
-         Err(AirPodsError::DeviceNotConnected)
+      if !self.supports_feature(feature) {
+         let model = self.model().map_or("this device", |m| m.to_str());
+         return Err(AirPodsError::FeatureNotSupported(format!(
+            "{feature} on {model}"
+         )));
       }
+
+      let packet = if enabled {
+         FeatureCmd::Enable.build(feature.id())
+      } else {
+         FeatureCmd::Disable.build(feature.id())
+      };
+      let _guard = self.0.command_lock.lock().await;
+      self.send_command("set_feature", &packet).await?;
+      self.set_feature_enabled(feature, enabled);
+      Ok(())
+   }
+
+   /// Binds an action to a number of stem taps.
+   ///
+   /// No capture of the AAP frame for this is available: the single- and
+   /// double-click IDs are known, but not the values they take, and no ID is
+   /// known for triple taps. Rather than send guessed values to the device,
+   /// this always fails with `FeatureNotSupported` until the encoding is
+   /// confirmed.
+   pub async fn set_tap_action(&self, taps: u8, action: TapAction) -> Result<()> {
+      Err(AirPodsError::FeatureNotSupported(format!(
+         "binding {action} to {taps} tap(s) over AAP (no verified encoding)"
+      )))
+   }
+
+   /// Sets how fast presses must follow each other to count as a multi-press.
+   pub async fn set_press_speed(&self, speed: PressSpeed) -> Result<()> {
+      let _guard = self.0.command_lock.lock().await;
+      self.send_command("set_press_speed", &speed.build()).await?;
+      self.0.press_speed.store(Some(speed));
+      Ok(())
+   }
+
+   /// Sets how long a stem must be held to count as press-and-hold.
+   pub async fn set_hold_duration(&self, duration: HoldDuration) -> Result<()> {
+      let _guard = self.0.command_lock.lock().await;
+      self
+         .send_command("set_hold_duration", &duration.build())
+         .await?;
+      self.0.hold_duration.store(Some(duration));
+      Ok(())
    }
 
    fn process_packet(&self, address: Address, packet: Packet, event_tx: &EventSender) {
+      if self.0.emit_raw_events.load(Ordering::Relaxed) {
+         event_tx.emit(
+            self,
+            AirPodsEvent::RawFrame(parser::describe_frame(&packet)),
+         );
+      }
+
+      if packet.starts_with(HDR_AAP)
+         && let Some(&opcode) = packet.get(HDR_AAP.len())
+      {
+         self.0.handshake.lock().opcodes.insert(opcode);
+      }
+
       // Battery status
       if packet.starts_with(HDR_BATTERY_STATE) {
+         *self.0.battery_raw.lock() = Some(packet.clone());
          match parser::parse_battery_status(&packet) {
             Ok(battery) => {
+               self.0.battery_received.notify_waiters();
+               let battery = self.0.battery_offsets.load().apply(battery);
                debug!(
                   "Battery updated for {}: L:{}% R:{}% C:{}%",
                   address, battery.left.level, battery.right.level, battery.case.level
                );
 
+               let prev_primary = self.battery_info().and_then(|b| b.primary);
+               if let (Some(prev), Some(primary)) = (prev_primary, battery.primary)
+                  && prev != primary
+               {
+                  info!("{address}: Primary bud switched from {prev} to {primary}");
+               }
+
                // Send event if battery changed
                if self.update_battery_info(battery).is_updated() {
-                  self
-                     .0
-                     .battery_tracker
-                     .lock()
-                     .record_battery_drop(battery.left, battery.right);
+                  let mut tracker = self.0.battery_tracker.lock();
+                  tracker.record_battery_drop(battery.left, battery.right);
+                  tracker.record_charge_progress(address, battery.left, battery.right);
+                  drop(tracker);
                   event_tx.emit(self, AirPodsEvent::BatteryUpdated(battery));
                }
+               self.mark_ready(event_tx);
+            },
+            Err(e) => {
+               warn!("Failed to parse battery: {e}");
+               self.record_error(&format!("Failed to parse battery: {e}"));
             },
-            Err(e) => warn!("Failed to parse battery: {e}"),
          }
       }
       // Noise control mode
@@ -502,8 +1197,12 @@ impl AirPods {
                if self.update_noise_mode(mode).is_updated() {
                   event_tx.emit(self, AirPodsEvent::NoiseControlChanged(mode));
                }
+               self.0.noise_mode_received.notify_waiters();
+            },
+            Err(e) => {
+               warn!("Failed to parse noise mode: {e}");
+               self.record_error(&format!("Failed to parse noise mode: {e}"));
             },
-            Err(e) => warn!("Failed to parse noise mode: {e}"),
          }
       }
       // Ear detection
@@ -521,7 +1220,17 @@ impl AirPods {
                   event_tx.emit(self, AirPodsEvent::EarDetectionChanged(status));
                }
             },
-            Err(e) => warn!("Failed to parse ear detection: {e}"),
+            Err(e) => {
+               warn!("Failed to parse ear detection: {e}");
+               self.record_error(&format!("Failed to parse ear detection: {e}"));
+            },
+         }
+      }
+      // Head-tracking samples; only their presence is tracked
+      else if packet.starts_with(HDR_HEAD_TRACKING) {
+         if self.mark_head_tracking() {
+            debug!("Head tracking started on {address}");
+            self.emit_spatial_state(event_tx, true);
          }
       }
       // Metadata packets
@@ -539,13 +1248,12 @@ impl AirPods {
       // Other packets
       else if packet.starts_with(HDR_ACK_HANDSHAKE) {
          debug!("Received handshake ACK from {address}");
+         self.0.handshake.lock().ack = Some(packet);
       } else if packet.starts_with(HDR_ACK_FEATURES) {
          debug!("Received features ACK from {address}");
-      } else if let Some((cmd, op)) = FeatureCmd::parse(&packet) {
-         debug!("Received feature command from {address}: {cmd} {op:?}");
-         if matches!(op, FeatureCmd::Enable | FeatureCmd::Disable) {
-            self.set_feature_enabled(cmd, matches!(op, FeatureCmd::Enable));
-         }
+         self.0.handshake.lock().features_ack = Some(packet);
+      } else if packet.starts_with(HDR_CMD_CTL) {
+         self.process_control(address, &packet, event_tx);
       } else {
          let data = if packet.len() < 16 {
             hex::encode(&packet)
@@ -566,6 +1274,32 @@ impl AirPods {
       }
    }
 
+   /// Handles a control frame. Press timings are kept as such, and their
+   /// values also go into the feature state like every other control, so
+   /// `features` doesn't go stale for them.
+   fn process_control(&self, address: Address, packet: &[u8], event_tx: &EventSender) {
+      if let Some(speed) = PressSpeed::parse(packet) {
+         debug!("Received press speed from {address}: {speed}");
+         self.0.press_speed.store(Some(speed));
+      } else if let Some(duration) = HoldDuration::parse(packet) {
+         debug!("Received hold duration from {address}: {duration}");
+         self.0.hold_duration.store(Some(duration));
+      }
+
+      let Some((cmd, op)) = FeatureCmd::parse(packet) else {
+         return;
+      };
+      debug!("Received feature command from {address}: {cmd} {op:?}");
+      if matches!(op, FeatureCmd::Enable | FeatureCmd::Disable) {
+         let enabled = matches!(op, FeatureCmd::Enable);
+         // Echoes of our own commands match the stored state; anything
+         // else was changed on the device or by another host
+         if self.set_feature_enabled(cmd, enabled) != enabled {
+            event_tx.emit(self, AirPodsEvent::FeatureChanged(cmd, enabled));
+         }
+      }
+   }
+
    /// Estimates battery time-to-live in minutes based on current levels and drain rate.
    pub fn estimate_battery_ttl(&self) -> Option<u32> {
       const DEFAULT_DRAIN_RATE: f64 = 16.9; // 16.9%/hr
@@ -622,7 +1356,12 @@ impl AirPods {
    }
 
    /// Performs all periodic tasks for the device.
-   pub fn tick(&self) {
+   pub fn tick(&self, event_tx: &EventSender) {
+      if self.expire_head_tracking(false) {
+         debug!("Head tracking stopped on {}", self.address());
+         self.emit_spatial_state(event_tx, false);
+      }
+
       if self.is_connected() {
          if self.should_save_battery_study(5) {
             debug!("Performing periodic battery save for {}", self.address());
@@ -635,3 +1374,216 @@ impl AirPods {
       }
    }
 }
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::{bluetooth::l2cap::L2CapSender, event::RecordingBus};
+
+   fn test_device() -> AirPods {
+      AirPods::new(
+         "AA:BB:CC:DD:EE:FF".parse().unwrap(),
+         "Test AirPods".to_string(),
+         None,
+         None,
+      )
+   }
+
+   /// A device with an open connection whose writes are collected instead of
+   /// sent, failing the first `failures` of them.
+   async fn connected_test_device(
+      failures: usize,
+   ) -> (AirPods, tokio::sync::mpsc::UnboundedReceiver<Packet>) {
+      let device = test_device();
+      let (sender, sent) = L2CapSender::loopback(failures);
+      *device.0.conn.write().await = Some(ActiveConnection {
+         sender,
+         jset: JoinSet::new(),
+      });
+      (device, sent)
+   }
+
+   #[tokio::test]
+   async fn concurrent_commands_do_not_interleave() {
+      // The first write fails, so the first command backs off and retries
+      let (device, mut sent) = connected_test_device(1).await;
+      device.set_retry_policy(RetryPolicy {
+         retries: 1,
+         backoff: Duration::from_millis(50),
+      });
+
+      let first = tokio::spawn({
+         let device = device.clone();
+         async move { device.set_noise_control(NoiseControlMode::Active).await }
+      });
+      tokio::task::yield_now().await;
+      let second = tokio::spawn({
+         let device = device.clone();
+         async move { device.set_noise_control(NoiseControlMode::Off).await }
+      });
+
+      // The second command waits out the first one's retry and confirmation
+      let event_tx: EventSender = Arc::new(RecordingBus::default());
+      let active = build_control_packet(0x0D, (NoiseControlMode::Active as u32).to_le_bytes());
+      let off = build_control_packet(0x0D, (NoiseControlMode::Off as u32).to_le_bytes());
+      for expected in [active, off] {
+         let packet = sent.recv().await.unwrap();
+         assert_eq!(packet.as_slice(), expected.as_slice());
+         // The device echoes the mode it applied
+         device.process_packet(device.address(), packet, &event_tx);
+      }
+      assert_eq!(first.await.unwrap().unwrap(), NoiseControlMode::Active);
+      assert_eq!(second.await.unwrap().unwrap(), NoiseControlMode::Off);
+      assert_eq!(device.noise_mode(), Some(NoiseControlMode::Off));
+   }
+
+   #[test]
+   fn noise_modes_follow_model() {
+      let address = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+      let pro2 = AirPods::new(address, String::new(), Some(DeviceModel::AirPodsPro2), None);
+      assert_eq!(pro2.noise_modes().last(), Some(&NoiseControlMode::Adaptive));
+      let basic = AirPods::new(address, String::new(), Some(DeviceModel::AirPods3), None);
+      assert_eq!(basic.noise_modes(), &[NoiseControlMode::Off]);
+      let unknown = AirPods::new(address, String::new(), None, None);
+      assert_eq!(unknown.noise_modes(), NoiseControlMode::COMMON);
+   }
+
+   #[test]
+   fn fit_test_support_follows_model() {
+      let address = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+      let pro = AirPods::new(address, String::new(), Some(DeviceModel::AirPodsPro), None);
+      assert_eq!(pro.fit_test_json()["supported"], true);
+      assert_eq!(pro.fit_test_json()["left"], "unknown");
+      let max = AirPods::new(address, String::new(), Some(DeviceModel::AirPodsMax), None);
+      assert_eq!(max.fit_test_json()["supported"], false);
+      let unknown = AirPods::new(address, String::new(), None, None);
+      assert!(unknown.fit_test_json()["supported"].is_null());
+   }
+
+   #[tokio::test]
+   async fn noise_control_returns_confirmed_mode() {
+      let (device, mut sent) = connected_test_device(0).await;
+
+      let set = tokio::spawn({
+         let device = device.clone();
+         async move { device.set_noise_control(NoiseControlMode::Adaptive).await }
+      });
+      sent.recv().await.unwrap();
+
+      // The device keeps transparency instead
+      let event_tx: EventSender = Arc::new(RecordingBus::default());
+      let report =
+         build_control_packet(0x0D, (NoiseControlMode::Transparency as u32).to_le_bytes());
+      device.process_packet(device.address(), report, &event_tx);
+      assert_eq!(set.await.unwrap().unwrap(), NoiseControlMode::Transparency);
+      assert_eq!(device.noise_mode(), Some(NoiseControlMode::Transparency));
+   }
+
+   #[tokio::test]
+   async fn commands_do_not_wait_for_noise_confirmation() {
+      let (device, mut sent) = connected_test_device(0).await;
+
+      let set = tokio::spawn({
+         let device = device.clone();
+         async move { device.set_noise_control(NoiseControlMode::Active).await }
+      });
+      sent.recv().await.unwrap();
+
+      // The device hasn't confirmed the mode yet, which doesn't hold this up
+      time::timeout(
+         Duration::from_millis(500),
+         device.set_feature(FeatureId::SINGLE_CLICK_MODE, true),
+      )
+      .await
+      .expect("set_feature waited for the noise mode report")
+      .unwrap();
+      sent.recv().await.unwrap();
+      assert!(!set.is_finished());
+
+      let event_tx: EventSender = Arc::new(RecordingBus::default());
+      let report = build_control_packet(0x0D, (NoiseControlMode::Active as u32).to_le_bytes());
+      device.process_packet(device.address(), report, &event_tx);
+      assert_eq!(set.await.unwrap().unwrap(), NoiseControlMode::Active);
+   }
+
+   #[test]
+   fn control_frames_also_update_features() {
+      let device = test_device();
+      let bus = Arc::new(RecordingBus::default());
+      let event_tx: EventSender = bus.clone();
+
+      let frame = build_control_packet(FeatureId::SINGLE_CLICK_MODE.id(), 2u32.to_le_bytes());
+      device.process_packet(device.address(), frame, &event_tx);
+      assert!(!device.feature_enabled(FeatureId::SINGLE_CLICK_MODE));
+      assert!(
+         device
+            .features()
+            .contains(&(FeatureId::SINGLE_CLICK_MODE, false))
+      );
+
+      let frame = PressSpeed::Slower.build();
+      device.process_packet(device.address(), frame, &event_tx);
+      assert_eq!(device.0.press_speed.load(), Some(PressSpeed::Slower));
+      assert!(device.feature_enabled(FeatureId::DOUBLE_CLICK_INTERVAL));
+   }
+
+   #[tokio::test]
+   async fn battery_refresh_waits_for_report() {
+      let (device, mut sent) = connected_test_device(0).await;
+
+      let refresh = tokio::spawn({
+         let device = device.clone();
+         async move { device.refresh_battery().await }
+      });
+      assert_eq!(sent.recv().await.unwrap().as_slice(), PKT_REQUEST_NOTIFY);
+      assert!(!refresh.is_finished());
+
+      // A report with no components still answers the request
+      let event_tx: EventSender = Arc::new(RecordingBus::default());
+      let report = HDR_BATTERY_STATE.iter().copied().chain([0]).collect();
+      device.process_packet(device.address(), report, &event_tx);
+      refresh.await.unwrap().unwrap();
+   }
+
+   #[test]
+   fn handshake_becomes_ready_once() {
+      let device = test_device();
+      let bus = Arc::new(RecordingBus::default());
+      let event_tx: EventSender = bus.clone();
+
+      device.set_connection_state(ConnectionState::Handshaking, &event_tx);
+      device.mark_ready(&event_tx);
+      // The session is reported as established after the first state update
+      device.set_connection_state(ConnectionState::Handshaking, &event_tx);
+      assert_eq!(device.connection_state(), ConnectionState::Ready);
+
+      let states: Vec<_> = bus
+         .take()
+         .into_iter()
+         .map(|(_, event)| match event {
+            AirPodsEvent::ConnectionStateChanged(state) => state,
+            other => panic!("unexpected event {other:?}"),
+         })
+         .collect();
+      assert_eq!(
+         states,
+         [ConnectionState::Handshaking, ConnectionState::Ready]
+      );
+   }
+
+   #[test]
+   fn error_log_keeps_a_full_history() {
+      let device = test_device();
+      for i in 0..ERROR_HISTORY_SIZE {
+         device.record_error(&format!("error {i}"));
+      }
+
+      let log = device.error_log();
+      assert_eq!(log.len(), ERROR_HISTORY_SIZE);
+      assert_eq!(log[0].message(), "error 0");
+      assert_eq!(
+         log.last().map(ErrorEntry::message),
+         device.last_error().as_ref().map(ErrorEntry::message)
+      );
+   }
+}
@@ -6,13 +6,16 @@
 use std::str;
 
 use log::{debug, warn};
+use serde_json::json;
 use smol_str::SmolStr;
 
 use crate::{
    airpods::protocol::{
-      BatteryInfo, BatteryState, BatteryStatus, Component, EarDetectionStatus, HDR_BATTERY_STATE,
-      HDR_EAR_DETECTION, HDR_METADATA, NoiseControlMode,
+      BatteryInfo, BatteryState, BatteryStatus, BudState, Component, EarDetectionStatus,
+      FeatureCmd, HDR_ACK_FEATURES, HDR_ACK_HANDSHAKE, HDR_BATTERY_STATE, HDR_EAR_DETECTION,
+      HDR_METADATA, HDR_NOISE_CTL, HoldDuration, NoiseControlMode, PressSpeed,
    },
+   bluetooth::l2cap::L2CAP_MTU,
    error::Result,
 };
 
@@ -33,6 +36,10 @@ pub enum ProtoError {
    #[error("Invalid battery count: {count} (must be 0-3)")]
    InvalidBatteryCount { count: u8 },
 
+   /// Packet does not fit in a single L2CAP frame
+   #[error("Packet too long: at most {max} bytes allowed, got {actual}")]
+   PacketTooLong { max: usize, actual: usize },
+
    /// Packet size doesn't match expected size based on content
    #[error("Packet size mismatch: expected {expected} bytes, got {actual} bytes")]
    PacketSizeMismatch { expected: usize, actual: usize },
@@ -50,6 +57,36 @@ pub enum ProtoError {
    InvalidFormat { reason: &'static str },
 }
 
+/// Checks that an outgoing frame looks like a well-formed AAP packet.
+///
+/// Frames start with a `xx 00 04 00` header (`00` for the handshake, `04`
+/// otherwise) followed by a little-endian opcode, and must fit in the MTU.
+pub fn validate_frame(data: &[u8]) -> Result<(), ProtoError> {
+   if data.len() < 6 {
+      return Err(ProtoError::PacketTooShort {
+         expected: 6,
+         actual: data.len(),
+      });
+   }
+   if data.len() > L2CAP_MTU {
+      return Err(ProtoError::PacketTooLong {
+         max: L2CAP_MTU,
+         actual: data.len(),
+      });
+   }
+   if !matches!(data[..4], [0x00 | 0x04, 0x00, 0x04, 0x00]) {
+      return Err(ProtoError::InvalidFormat {
+         reason: "expected a 00/04 00 04 00 header",
+      });
+   }
+   if data[4] == 0 || data[5] != 0 {
+      return Err(ProtoError::InvalidFormat {
+         reason: "opcode out of range",
+      });
+   }
+   Ok(())
+}
+
 /// Parses a battery status packet from `AirPods`.
 ///
 /// The packet format contains battery information for up to 3 components
@@ -151,13 +188,12 @@ pub fn parse_battery_status(data: &[u8]) -> Result<BatteryInfo> {
             Component::Headphone => battery_info.headphone = battery_state,
          }
 
-         /*if matches!(component, Component::Left | Component::Right) {
-            if battery_info.primary_pod.is_none() {
-               battery_info.primary_pod = Some(component);
-            } else {
-               battery_info.secondary_pod = Some(component);
-            }
-         }*/
+         // The primary bud is listed before the secondary one
+         if matches!(component, Component::Left | Component::Right)
+            && battery_info.primary.is_none()
+         {
+            battery_info.primary = Some(component);
+         }
       }
    }
    debug!("Battery parsed - {battery_info}");
@@ -200,9 +236,10 @@ pub fn parse_ear_detection(data: &[u8]) -> Result<EarDetectionStatus> {
          .into(),
       );
    }
-   let left_out = data[6] == 0x01;
-   let right_out = data[7] == 0x01;
-   Ok(EarDetectionStatus::new(!left_out, !right_out))
+   // Unknown placements are treated as out of ear
+   let left = BudState::from_repr(data[6]).unwrap_or(BudState::Out);
+   let right = BudState::from_repr(data[7]).unwrap_or(BudState::Out);
+   Ok(EarDetectionStatus::from_states(left, right))
 }
 
 #[derive(Debug, Default)]
@@ -247,3 +284,58 @@ pub fn parse_metadata(data: &[u8]) -> Result<Metadata> {
 
    Ok(Metadata { name_candidate })
 }
+
+/// Decodes any frame into a structured JSON description.
+///
+/// Used for raw event streaming; unknown frames are reported with their
+/// opcode and payload so they can still be inspected.
+pub fn describe_frame(data: &[u8]) -> serde_json::Value {
+   let opcode = data.get(4).copied();
+   let (kind, decoded) = if data.starts_with(HDR_BATTERY_STATE) {
+      (
+         "battery",
+         parse_battery_status(data).ok().map(BatteryInfo::to_json),
+      )
+   } else if data.starts_with(HDR_NOISE_CTL) {
+      (
+         "noise_control",
+         parse_noise_mode(data).ok().map(|m| json!(m.to_str())),
+      )
+   } else if data.starts_with(HDR_EAR_DETECTION) {
+      (
+         "ear_detection",
+         parse_ear_detection(data)
+            .ok()
+            .map(EarDetectionStatus::to_json),
+      )
+   } else if data.starts_with(HDR_METADATA) {
+      (
+         "metadata",
+         parse_metadata(data)
+            .ok()
+            .map(|m| json!({ "name_candidate": m.name_candidate.as_deref() })),
+      )
+   } else if data.starts_with(HDR_ACK_HANDSHAKE) {
+      ("handshake_ack", None)
+   } else if data.starts_with(HDR_ACK_FEATURES) {
+      ("features_ack", None)
+   } else if let Some(speed) = PressSpeed::parse(data) {
+      ("press_speed", Some(json!(speed.to_str())))
+   } else if let Some(duration) = HoldDuration::parse(data) {
+      ("hold_duration", Some(json!(duration.to_str())))
+   } else if let Some((feature, cmd)) = FeatureCmd::parse(data) {
+      (
+         "feature",
+         Some(json!({ "feature": feature.to_str(), "cmd": format!("{cmd:?}") })),
+      )
+   } else {
+      ("unknown", None)
+   };
+
+   json!({
+      "type": kind,
+      "opcode": opcode,
+      "decoded": decoded,
+      "raw": hex::encode(data),
+   })
+}
@@ -29,6 +29,8 @@ pub const PKT_REQUEST_NOTIFY: &[u8] = &[
 ];
 
 // Parsing headers
+/// Common prefix of AAP frames; the opcode follows it
+pub const HDR_AAP: &[u8] = b"\x04\x00\x04\x00";
 pub const HDR_BATTERY_STATE: &[u8] = b"\x04\x00\x04\x00\x04\x00";
 pub const HDR_NOISE_CTL: &[u8] = b"\x04\x00\x04\x00\x09\x00\x0D";
 pub const HDR_CMD_CTL: &[u8] = b"\x04\x00\x04\x00\x09\x00";
@@ -38,6 +40,8 @@ pub const HDR_ACK_HANDSHAKE: &[u8] = b"\x01\x00\x04\x00";
 pub const HDR_ACK_FEATURES: &[u8] = b"\x04\x00\x04\x00\x2b";
 pub const HDR_METADATA: &[u8] = b"\x04\x00\x04\x00\x1d";
 pub const HDR_EAR_DETECTION: &[u8] = b"\x04\x00\x04\x00\x06\x00";
+/// Motion sensor samples, streamed while head tracking is engaged
+pub const HDR_HEAD_TRACKING: &[u8] = b"\x04\x00\x04\x00\x17\x00";
 
 /// Represents different components of `AirPods`.
 #[repr(u8)]
@@ -114,6 +118,9 @@ pub enum NoiseControlMode {
 }
 
 impl NoiseControlMode {
+   /// Modes offered by every model with noise control, in display order
+   pub const COMMON: &'static [Self] = &[Self::Off, Self::Active, Self::Transparency];
+
    pub fn to_str(self) -> &'static str {
       self.into()
    }
@@ -233,6 +240,13 @@ pub const KNOWN_FEATURES: &[(u8, &str)] = &[
 #[repr(transparent)]
 pub struct FeatureId(u8);
 
+impl FeatureId {
+   /// Iterates over all features with a known name.
+   pub fn known() -> impl Iterator<Item = Self> {
+      KNOWN_FEATURES.iter().map(|&(repr, _)| Self(repr))
+   }
+}
+
 impl FromStr for FeatureId {
    type Err = strum::ParseError;
 
@@ -373,6 +387,126 @@ impl fmt::Display for FeatureId {
    }
 }
 
+/// Actions that can be bound to stem taps.
+///
+/// No capture of the AAP frame that binds them is available, so these are
+/// names only and carry no wire encoding; see [`AirPods::set_tap_action`].
+///
+/// [`AirPods::set_tap_action`]: crate::airpods::device::AirPods::set_tap_action
+#[derive(
+   Debug,
+   Clone,
+   Copy,
+   PartialEq,
+   Eq,
+   Serialize,
+   Deserialize,
+   strum::Display,
+   strum::EnumString,
+   strum::IntoStaticStr,
+)]
+pub enum TapAction {
+   #[strum(serialize = "none")]
+   None,
+   #[strum(serialize = "play")]
+   Play,
+   #[strum(serialize = "next")]
+   Next,
+   #[strum(serialize = "previous")]
+   Previous,
+}
+
+impl TapAction {
+   pub fn to_str(self) -> &'static str {
+      self.into()
+   }
+}
+
+/// Ear-detection sensitivity levels.
+#[derive(
+   Debug, Clone, Copy, PartialEq, Eq, strum::Display, strum::EnumString, strum::IntoStaticStr,
+)]
+#[strum(serialize_all = "snake_case")]
+pub enum EarDetectionSensitivity {
+   Low,
+   Medium,
+   High,
+}
+
+/// How fast presses must follow each other to count as a multi-press.
+#[derive(
+   Debug,
+   Clone,
+   Copy,
+   PartialEq,
+   Eq,
+   strum::FromRepr,
+   strum::Display,
+   strum::EnumString,
+   strum::IntoStaticStr,
+)]
+#[strum(serialize_all = "snake_case")]
+#[repr(u32)]
+pub enum PressSpeed {
+   Default = 0x00,
+   Slower = 0x01,
+   Slowest = 0x02,
+}
+
+impl PressSpeed {
+   pub fn to_str(self) -> &'static str {
+      self.into()
+   }
+
+   pub fn build(self) -> Packet {
+      build_control_packet(
+         FeatureId::DOUBLE_CLICK_INTERVAL.id(),
+         (self as u32).to_le_bytes(),
+      )
+   }
+
+   pub fn parse(data: &[u8]) -> Option<Self> {
+      Self::from_repr(parse_control_value(data, FeatureId::DOUBLE_CLICK_INTERVAL)?)
+   }
+}
+
+/// How long a stem must be held to count as press-and-hold.
+#[derive(
+   Debug,
+   Clone,
+   Copy,
+   PartialEq,
+   Eq,
+   strum::FromRepr,
+   strum::Display,
+   strum::EnumString,
+   strum::IntoStaticStr,
+)]
+#[strum(serialize_all = "snake_case")]
+#[repr(u32)]
+pub enum HoldDuration {
+   Default = 0x00,
+   Shorter = 0x01,
+   Shortest = 0x02,
+}
+
+impl HoldDuration {
+   pub fn to_str(self) -> &'static str {
+      self.into()
+   }
+
+   pub fn build(self) -> Packet {
+      build_control_packet(
+         FeatureId::CLICK_HOLD_INTERVAL.id(),
+         (self as u32).to_le_bytes(),
+      )
+   }
+
+   pub fn parse(data: &[u8]) -> Option<Self> {
+      Self::from_repr(parse_control_value(data, FeatureId::CLICK_HOLD_INTERVAL)?)
+   }
+}
+
 /// Battery state for a single `AirPods` component.
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct BatteryState {
@@ -412,6 +546,16 @@ impl BatteryState {
          serde_json::Value::Null
       }
    }
+
+   /// Like [`Self::to_json`], but always populated with a `present` flag.
+   pub fn to_status_json(self) -> serde_json::Value {
+      let present = self.is_available();
+      json!({
+         "level": if present { i32::from(self.level) } else { -1 },
+         "charging": present && self.is_charging(),
+         "present": present,
+      })
+   }
 }
 
 /// Complete battery information for all `AirPods` components.
@@ -421,6 +565,9 @@ pub struct BatteryInfo {
    pub right: BatteryState,
    pub case: BatteryState,
    pub headphone: BatteryState,
+   /// The bud relaying to the host, if reported
+   #[serde(default)]
+   pub primary: Option<Component>,
 }
 
 impl fmt::Display for BatteryInfo {
@@ -440,6 +587,16 @@ impl BatteryInfo {
          right: BatteryState::new(),
          case: BatteryState::new(),
          headphone: BatteryState::new(),
+         primary: None,
+      }
+   }
+
+   /// Name of the primary bud, `"left"` or `"right"`.
+   pub const fn primary_str(&self) -> Option<&'static str> {
+      match self.primary {
+         Some(Component::Left) => Some("left"),
+         Some(Component::Right) => Some("right"),
+         _ => None,
       }
    }
 
@@ -461,6 +618,75 @@ impl BatteryInfo {
    }
 }
 
+/// Per-component calibration added to reported battery levels, in percent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BatteryOffsets {
+   pub left: i8,
+   pub right: i8,
+   pub case: i8,
+   pub headphone: i8,
+}
+
+impl BatteryOffsets {
+   /// Offset of the component named `left`, `right`, `case` or `headphone`.
+   pub fn get_mut(&mut self, component: &str) -> Option<&mut i8> {
+      match component {
+         "left" => Some(&mut self.left),
+         "right" => Some(&mut self.right),
+         "case" => Some(&mut self.case),
+         "headphone" => Some(&mut self.headphone),
+         _ => None,
+      }
+   }
+
+   /// Adds the offsets to the available components, clamped to 0–100.
+   pub fn apply(self, mut battery: BatteryInfo) -> BatteryInfo {
+      for (state, offset) in [
+         (&mut battery.left, self.left),
+         (&mut battery.right, self.right),
+         (&mut battery.case, self.case),
+         (&mut battery.headphone, self.headphone),
+      ] {
+         if state.is_available() && offset != 0 {
+            state.level = state.level.saturating_add_signed(offset).min(100);
+         }
+      }
+      battery
+   }
+}
+
+/// Placement of a single bud as reported by ear detection.
+#[derive(
+   Debug,
+   Clone,
+   Copy,
+   PartialEq,
+   Eq,
+   Serialize,
+   Deserialize,
+   strum::FromRepr,
+   strum::Display,
+   strum::EnumString,
+   strum::IntoStaticStr,
+)]
+#[repr(u8)]
+#[strum(serialize_all = "snake_case")]
+pub enum BudState {
+   InEar = 0x00,
+   /// Out of both ear and case, e.g. lying on a desk
+   Out = 0x01,
+   InCase = 0x02,
+   /// Out of ear but held in hand
+   Held = 0x03,
+}
+
+impl BudState {
+   pub fn to_str(self) -> &'static str {
+      self.into()
+   }
+}
+
 /// Ear detection status for left and right `AirPods`.
 #[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
 #[repr(transparent)]
@@ -469,19 +695,49 @@ pub struct EarDetectionStatus(NonZeroU8);
 impl EarDetectionStatus {
    pub const LEFT: u8 = 1 << 0;
    pub const RIGHT: u8 = 1 << 1;
+   pub const LEFT_IN_CASE: u8 = 1 << 2;
+   pub const RIGHT_IN_CASE: u8 = 1 << 3;
+   pub const LEFT_HELD: u8 = 1 << 4;
+   pub const RIGHT_HELD: u8 = 1 << 5;
    pub const VALID: u8 = 0x80;
 
-   pub const fn new(left_in_ear: bool, right_in_ear: bool) -> Self {
+   pub const fn from_states(left: BudState, right: BudState) -> Self {
       let mut flags = Self::VALID;
-      if left_in_ear {
-         flags |= Self::LEFT;
+      match left {
+         BudState::InEar => flags |= Self::LEFT,
+         BudState::InCase => flags |= Self::LEFT_IN_CASE,
+         BudState::Held => flags |= Self::LEFT_HELD,
+         BudState::Out => {},
       }
-      if right_in_ear {
-         flags |= Self::RIGHT;
+      match right {
+         BudState::InEar => flags |= Self::RIGHT,
+         BudState::InCase => flags |= Self::RIGHT_IN_CASE,
+         BudState::Held => flags |= Self::RIGHT_HELD,
+         BudState::Out => {},
       }
       Self(NonZeroU8::new(flags).expect("(x|valid) != 0"))
    }
 
+   const fn state(&self, in_ear: u8, in_case: u8, held: u8) -> BudState {
+      let flags = self.0.get();
+      if flags & in_ear != 0 {
+         BudState::InEar
+      } else if flags & in_case != 0 {
+         BudState::InCase
+      } else if flags & held != 0 {
+         BudState::Held
+      } else {
+         BudState::Out
+      }
+   }
+
+   pub const fn left_state(&self) -> BudState {
+      self.state(Self::LEFT, Self::LEFT_IN_CASE, Self::LEFT_HELD)
+   }
+   pub const fn right_state(&self) -> BudState {
+      self.state(Self::RIGHT, Self::RIGHT_IN_CASE, Self::RIGHT_HELD)
+   }
+
    pub const fn is_left_in_ear(&self) -> bool {
       self.0.get() & Self::LEFT != 0
    }
@@ -489,14 +745,73 @@ impl EarDetectionStatus {
       self.0.get() & Self::RIGHT != 0
    }
 
+   pub const fn is_left_held(&self) -> bool {
+      self.0.get() & Self::LEFT_HELD != 0
+   }
+   pub const fn is_right_held(&self) -> bool {
+      self.0.get() & Self::RIGHT_HELD != 0
+   }
+
+   pub const fn is_left_in_case(&self) -> bool {
+      matches!(self.left_state(), BudState::InCase)
+   }
+   pub const fn is_right_in_case(&self) -> bool {
+      matches!(self.right_state(), BudState::InCase)
+   }
+
    pub fn to_json(self) -> serde_json::Value {
       json!({
           "left_in_ear": self.is_left_in_ear(),
           "right_in_ear": self.is_right_in_ear(),
+          "left_held": self.is_left_held(),
+          "right_held": self.is_right_held(),
+          "left_state": self.left_state().to_str(),
+          "right_state": self.right_state().to_str(),
       })
    }
 }
 
+/// Ear sensors to disregard, e.g. a failing one that keeps reporting removal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IgnoredEarSensors {
+   pub ignore_left: bool,
+   pub ignore_right: bool,
+}
+
+impl IgnoredEarSensors {
+   /// Flag of the bud named `left` or `right`.
+   pub fn get_mut(&mut self, bud: &str) -> Option<&mut bool> {
+      match bud {
+         "left" => Some(&mut self.ignore_left),
+         "right" => Some(&mut self.ignore_right),
+         _ => None,
+      }
+   }
+
+   /// Reports the ignored buds as in ear, whatever their sensors say.
+   pub const fn apply(self, status: EarDetectionStatus) -> EarDetectionStatus {
+      let left = if self.ignore_left {
+         BudState::InEar
+      } else {
+         status.left_state()
+      };
+      let right = if self.ignore_right {
+         BudState::InEar
+      } else {
+         status.right_state()
+      };
+      EarDetectionStatus::from_states(left, right)
+   }
+}
+
+/// Extracts the value of a control packet for the given setting.
+fn parse_control_value(data: &[u8], feature: FeatureId) -> Option<u32> {
+   let rest = data.strip_prefix(HDR_CMD_CTL)?;
+   let rest = rest.strip_prefix(&[feature.id()])?;
+   Some(u32::from_le_bytes(rest.try_into().ok()?))
+}
+
 /// Builds a control packet for sending commands to `AirPods`.
 pub fn build_control_packet(cmd: u8, data: [u8; 4]) -> Packet {
    HDR_CMD_CTL
@@ -4,8 +4,11 @@
 //! based on various criteria such as modalias, manufacturer data,
 //! services, and name/alias patterns.
 
+use serde_json::json;
 use uuid::Uuid;
 
+use crate::airpods::protocol::{FeatureId, NoiseControlMode};
+
 /// Patterns to match `AirPods` devices (case-insensitive)
 const AIRPOD_PATTERNS: &[&str] = &["airpods", "beats", "powerbeats"];
 // Note: "earpods" are wired earphones, not Bluetooth AirPods
@@ -35,6 +38,98 @@ const AIRPOD_PIDS: &[u32] = &[
    0x2024, // AirPods Pro (1st gen)
 ];
 
+/// Known `AirPods` models, keyed by product ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display, strum::IntoStaticStr)]
+pub enum DeviceModel {
+   #[strum(serialize = "Beats")]
+   Beats,
+   #[strum(serialize = "AirPods (2nd gen)")]
+   AirPods2,
+   #[strum(serialize = "AirPods (3rd gen)")]
+   AirPods3,
+   #[strum(serialize = "Beats Solo Pro")]
+   BeatsSoloPro,
+   #[strum(serialize = "PowerBeats Pro")]
+   PowerBeatsPro,
+   #[strum(serialize = "AirPods Max")]
+   AirPodsMax,
+   #[strum(serialize = "AirPods Pro (2nd gen)")]
+   AirPodsPro2,
+   #[strum(serialize = "AirPods Pro (1st gen)")]
+   AirPodsPro,
+}
+
+impl DeviceModel {
+   pub const fn from_product_id(pid: u32) -> Option<Self> {
+      match pid {
+         0x2002 => Some(Self::Beats),
+         0x200E => Some(Self::AirPods2),
+         0x200A => Some(Self::AirPods3),
+         0x200F => Some(Self::BeatsSoloPro),
+         0x2012 => Some(Self::PowerBeatsPro),
+         0x2013 => Some(Self::AirPodsMax),
+         0x2014 => Some(Self::AirPodsPro2),
+         0x2024 => Some(Self::AirPodsPro),
+         _ => None,
+      }
+   }
+
+   pub fn to_str(self) -> &'static str {
+      self.into()
+   }
+
+   /// Noise control modes the model supports, or `None` if unknown.
+   pub const fn noise_modes(self) -> Option<&'static [NoiseControlMode]> {
+      use NoiseControlMode::{Active, Adaptive, Off, Transparency};
+      match self {
+         // Shared PID, capabilities vary between products
+         Self::Beats => None,
+         Self::AirPods2 | Self::AirPods3 | Self::PowerBeatsPro => Some(&[Off]),
+         Self::BeatsSoloPro | Self::AirPodsMax | Self::AirPodsPro => {
+            Some(&[Off, Active, Transparency])
+         },
+         Self::AirPodsPro2 => Some(&[Off, Active, Transparency, Adaptive]),
+      }
+   }
+
+   pub fn supports_noise_mode(self, mode: NoiseControlMode) -> bool {
+      self.noise_modes().is_none_or(|modes| modes.contains(&mode))
+   }
+
+   /// Checks whether the model is expected to support a feature.
+   ///
+   /// Unknown features are assumed supported; this only rules out the ones
+   /// tied to hardware the model lacks.
+   pub fn supports_feature(self, feature: FeatureId) -> bool {
+      match feature {
+         FeatureId::CROWN_ROTATION_DIRECTION => matches!(self, Self::AirPodsMax | Self::Beats),
+         FeatureId::CONVERSATIONAL
+         | FeatureId::ADAPTIVE_VOLUME
+         | FeatureId::AUTO_ANC_STRENGTH
+         | FeatureId::VOLUME_SWIPE
+         | FeatureId::HPS_GAIN_SWIPE
+         | FeatureId::HEARING_AID_SETTINGS
+         | FeatureId::HEARING_ASSIST => matches!(self, Self::AirPodsPro2 | Self::Beats),
+         FeatureId::NOISE_CONTROL
+         | FeatureId::LISTENING_MODE_CONFIGS
+         | FeatureId::ONE_BUD_ANC
+         | FeatureId::ALLOW_OFF => self.supports_noise_mode(NoiseControlMode::Active),
+         _ => true,
+      }
+   }
+
+   /// Checks whether the model has Loud Sound Reduction.
+   pub const fn supports_loud_sound_reduction(self) -> bool {
+      matches!(self, Self::AirPodsPro2)
+   }
+
+   /// Checks whether the model has the Ear Tip Fit Test, i.e. uses
+   /// silicone ear tips.
+   pub const fn supports_fit_test(self) -> bool {
+      matches!(self, Self::AirPodsPro | Self::AirPodsPro2)
+   }
+}
+
 /// Apple service UUIDs - Note: Not always advertised by AirPods
 static APPLE_SERVICES: [Uuid; 3] = [
    Uuid::from_u128(0x0000fd6f_0000_1000_8000_00805f9b34fb), // Find My
@@ -43,7 +138,7 @@ static APPLE_SERVICES: [Uuid; 3] = [
 ];
 
 /// Check if device is AirPods based on manufacturer data
-fn check_manufacturer_data(data: &[u8]) -> bool {
+pub fn check_manufacturer_data(data: &[u8]) -> bool {
    // Apple TLV format: [0] type, [1] len, [2..5] ?, [6] product_id, ...
    if data.len() > PID_OFFSET && data[0] == PP_TYPE {
       let product_id = data[PID_OFFSET];
@@ -52,6 +147,85 @@ fn check_manufacturer_data(data: &[u8]) -> bool {
    false
 }
 
+/// Gets the Apple manufacturer-specific advertisement payload of the device.
+pub async fn apple_manufacturer_data(dev: &bluer::Device) -> Option<Vec<u8>> {
+   dev.manufacturer_data()
+      .await
+      .ok()
+      .flatten()?
+      .remove(&APPLE_CID)
+}
+
+/// Decodes the battery and lid summary of a proximity-pairing advertisement.
+///
+/// Layout (reverse engineered): `[5]` status, whose `0x20` bit is clear when
+/// the left bud is primary; `[6]` bud battery nibbles; `[7]` charging flags
+/// (high nibble) and case battery (low nibble); `[8]` lid open counter.
+/// Battery nibbles are tens of percent, with `0xF` meaning unknown.
+pub fn decode_proximity_pairing(data: &[u8]) -> Option<serde_json::Value> {
+   if data.len() < 9 || data[0] != PP_TYPE {
+      return None;
+   }
+   let level = |nibble: u8| (nibble <= 10).then(|| u32::from(nibble) * 10);
+
+   let left_primary = data[5] & 0x20 == 0;
+   let (left, right) = if left_primary {
+      (data[6] >> 4, data[6] & 0x0F)
+   } else {
+      (data[6] & 0x0F, data[6] >> 4)
+   };
+   let charging = data[7] >> 4;
+   let (left_charging, right_charging) = if left_primary {
+      (charging & 0b0010 != 0, charging & 0b0001 != 0)
+   } else {
+      (charging & 0b0001 != 0, charging & 0b0010 != 0)
+   };
+
+   Some(json!({
+      "left": { "level": level(left), "charging": left_charging },
+      "right": { "level": level(right), "charging": right_charging },
+      "case": { "level": level(data[7] & 0x0F), "charging": charging & 0b0100 != 0 },
+      "lid_open_count": data[8],
+   }))
+}
+
+/// Gets the lid open counter of a proximity-pairing advertisement, which
+/// changes each time the case is opened.
+pub fn lid_open_count(data: &[u8]) -> Option<u8> {
+   (data.len() >= 9 && data[0] == PP_TYPE).then(|| data[8])
+}
+
+/// Detects the device model from its modalias or manufacturer data.
+pub async fn detect_model(dev: &bluer::Device) -> Option<DeviceModel> {
+   if let Ok(Some(modalias)) = dev.modalias().await
+      && modalias.vendor == APPLE_VID
+      && let Some(model) = DeviceModel::from_product_id(modalias.product)
+   {
+      return Some(model);
+   }
+
+   if let Ok(Some(mfg_data)) = dev.manufacturer_data().await
+      && let Some(data) = mfg_data.get(&APPLE_CID)
+      && data.len() > PID_OFFSET
+      && data[0] == PP_TYPE
+   {
+      let product_id = data[PID_OFFSET];
+      return AIRPOD_PIDS
+         .iter()
+         .find(|&&x| (x & 0xFF) as u8 == product_id)
+         .and_then(|&pid| DeviceModel::from_product_id(pid));
+   }
+   None
+}
+
+/// Checks whether the device advertises Apple proximity-pairing data for `AirPods`.
+pub async fn advertises_airpods(dev: &bluer::Device) -> bool {
+   matches!(
+      dev.manufacturer_data().await,
+      Ok(Some(mfg_data)) if mfg_data.get(&APPLE_CID).is_some_and(|d| check_manufacturer_data(d))
+   )
+}
+
 pub async fn is_device_airpods(dev: &bluer::Device) -> bool {
    // 1. Check modalias (most reliable for connected devices)
    if let Ok(Some(modalias)) = dev.modalias().await
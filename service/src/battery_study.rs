@@ -12,10 +12,14 @@ use std::{
 };
 
 use bluer::Address;
-use heed::{Database, Env, EnvOpenOptions, types::SerdeBincode};
+use heed::{
+   Database, Env, EnvOpenOptions,
+   types::{SerdeBincode, SerdeJson},
+};
 use log::{debug, info};
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use smol_str::SmolStr;
 use strum::IntoEnumIterator;
 use thiserror::Error;
@@ -23,7 +27,8 @@ use thiserror::Error;
 use crate::{
    airpods::protocol::{BatteryInfo, BatteryState, NoiseControlMap, NoiseControlMode},
    error::Result,
-   ringbuf::Ring,
+   ringbuf::DynRing,
+   smoothing,
 };
 
 /// Errors that can occur in battery study operations.
@@ -48,7 +53,7 @@ pub enum Error {
    StudyNotFound,
 }
 
-/// Ring buffer for tracking battery history.
+/// Default number of battery samples retained per bud.
 const BATTERY_HISTORY_SIZE: usize = 32;
 /// Minimum number of samples to save a battery study
 const MIN_SAMPLES_TO_SAVE: usize = 3;
@@ -85,29 +90,66 @@ impl SecondsSinceInit {
    }
 }
 
-#[derive(Default, Debug, Clone, Copy)]
+/// A battery level reading.
+#[derive(Debug, Clone, Copy)]
+struct BatterySample {
+   /// Monotonic time, used for rate computations so clock adjustments
+   /// cannot skew them
+   at: SecondsSinceInit,
+   /// Wall-clock Unix timestamp, for display only
+   wall: u64,
+   level: u8,
+}
+
+#[derive(Debug, Clone)]
 struct BatteryHistory {
-   samples: Ring<(SecondsSinceInit, u8), BATTERY_HISTORY_SIZE>, // (seconds since init, level)
+   samples: DynRing<BatterySample>,
+}
+
+impl Default for BatteryHistory {
+   fn default() -> Self {
+      Self {
+         samples: DynRing::with_capacity(BATTERY_HISTORY_SIZE),
+      }
+   }
 }
 
 impl BatteryHistory {
    fn push(&mut self, timestamp: Instant, level: u8) {
-      self.samples.push((timestamp.into(), level));
+      self.samples.push(BatterySample {
+         at: timestamp.into(),
+         wall: unix_time_of(timestamp),
+         level,
+      });
    }
 
    fn iter(&self) -> impl ExactSizeIterator<Item = (SecondsSinceInit, u8)> + Clone + '_ {
-      self.samples.iter().map(|&(t, l)| (t, l))
+      self.samples.iter().map(|s| (s.at, s.level))
    }
 
-   const fn len(&self) -> usize {
+   fn to_json(&self) -> serde_json::Value {
+      self
+         .samples
+         .iter()
+         .map(|s| {
+            json!({
+               "level": s.level,
+               "monotonic": s.at.0,
+               "timestamp": s.wall,
+            })
+         })
+         .collect()
+   }
+
+   fn len(&self) -> usize {
       self.samples.len()
    }
 
-   const fn is_empty(&self) -> bool {
+   fn is_empty(&self) -> bool {
       self.samples.is_empty()
    }
 
-   const fn clear(&mut self) {
+   fn clear(&mut self) {
       self.samples.clear();
    }
 
@@ -115,7 +157,7 @@ impl BatteryHistory {
       if self.is_empty() {
          None
       } else {
-         self.samples.last().map(|&(_, l)| l)
+         self.samples.last().map(|s| s.level)
       }
    }
 
@@ -123,12 +165,15 @@ impl BatteryHistory {
       self.iter().next().map(|(t, _)| t.into())
    }
 
-   fn record_battery_drop(&mut self, level: u8, timestamp: Instant) {
+   /// Records a battery level if it dropped, returning the drop in percent.
+   fn record_battery_drop(&mut self, level: u8, timestamp: Instant) -> u8 {
       // Not charging, record battery level
+      let mut dropped = 0;
       if let Some(last_level) = self.last_level() {
          if level >= last_level {
-            return;
+            return 0;
          }
+         dropped = last_level - level;
          let elapsed = timestamp.duration_since(*BASE_TIME).as_secs_f64();
          debug!(
             "Battery dropped from {last_level} to {level} (sample #{}, elapsed: {:.1}s)",
@@ -139,6 +184,7 @@ impl BatteryHistory {
          debug!("Recording initial battery level: {level} (first sample)");
       }
       self.push(timestamp, level);
+      dropped
    }
 
    /// Calculates battery drain rate from the samples. Returns `(rate, alpha)`
@@ -152,7 +198,7 @@ impl BatteryHistory {
          return None;
       }
 
-      let samples: heapless::Vec<_, BATTERY_HISTORY_SIZE> = self
+      let samples: Vec<_> = self
          .iter()
          .filter(|(timestamp, _)| max_age.is_none_or(|s| timestamp.instant() >= s))
          .collect();
@@ -192,12 +238,24 @@ fn unix_now() -> u64 {
    SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs()
 }
 
+/// Converts a monotonic instant to a Unix timestamp using the current wall clock.
+fn unix_time_of(instant: Instant) -> u64 {
+   let now = Instant::now();
+   let wall = SystemTime::now() - now.saturating_duration_since(instant)
+      + instant.saturating_duration_since(now);
+   wall
+      .duration_since(SystemTime::UNIX_EPOCH)
+      .map_or(0, |d| d.as_secs())
+}
+
 /// Database layout for battery study data
 #[derive(Debug)]
 struct Db {
    env: Env,
    /// MAC address -> `DeviceStudy`
    devices: Database<KeyCodec, SerdeBincode<DeviceStudy>>,
+   /// MAC address -> `DeviceStats`
+   stats: Database<KeyCodec, SerdeJson<DeviceStats>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -210,6 +268,28 @@ pub struct DeviceStudy {
    pub drain_rates: NoiseControlMap<DrainRateStats>,
 }
 
+/// Long-term per-device counters.
+///
+/// Stored as JSON rather than bincode so fields can be added with defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceStats {
+   #[serde(default)]
+   pub first_recorded: u64, // Unix timestamp
+   #[serde(default)]
+   pub full_charges: u32, // Charging-to-full transitions observed
+   #[serde(default)]
+   pub discharged: [u32; 2], // Accumulated drop of the (left, right) bud, in percent
+}
+
+impl DeviceStats {
+   /// Approximate full charge cycles, counting 100% of accumulated discharge
+   /// on the more-used bud as one cycle.
+   pub fn estimated_cycles(&self) -> f64 {
+      let discharged = self.discharged[0].max(self.discharged[1]);
+      (f64::from(discharged) / 10.0).round() / 10.0
+   }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DrainRateStats {
    pub rate: f64,         // Percent per hour
@@ -233,7 +313,7 @@ impl BatteryStudy {
       let env = unsafe {
          EnvOpenOptions::new()
             .map_size(10 * 1024 * 1024) // 10MB should be plenty
-            .max_dbs(1)
+            .max_dbs(2)
             .open(&path)
             .map_err(Error::OpenEnvironment)?
       };
@@ -243,11 +323,18 @@ impl BatteryStudy {
       let devices = env
          .create_database(&mut wtxn, Some("devices"))
          .map_err(Error::DatabaseOperation)?;
+      let stats = env
+         .create_database(&mut wtxn, Some("stats"))
+         .map_err(Error::DatabaseOperation)?;
 
       wtxn.commit().map_err(Error::Transaction)?;
 
       Ok(Self {
-         db: Arc::new(Db { env, devices }),
+         db: Arc::new(Db {
+            env,
+            devices,
+            stats,
+         }),
       })
    }
 
@@ -387,6 +474,97 @@ impl BatteryStudy {
       }
    }
 
+   /// Update the long-term counters of a device, stamping when it was first seen
+   fn update_stats(
+      &self,
+      address: Address,
+      f: impl FnOnce(&mut DeviceStats),
+   ) -> Result<DeviceStats> {
+      let mut wtxn = self.db.env.write_txn().map_err(Error::Transaction)?;
+
+      let mut stats = self
+         .db
+         .stats
+         .get(&wtxn, &address)
+         .map_err(Error::DatabaseOperation)?
+         .unwrap_or_default();
+      if stats.first_recorded == 0 {
+         stats.first_recorded = unix_now();
+      }
+      f(&mut stats);
+
+      self
+         .db
+         .stats
+         .put(&mut wtxn, &address, &stats)
+         .map_err(Error::DatabaseOperation)?;
+
+      wtxn.commit().map_err(Error::Transaction)?;
+      Ok(stats)
+   }
+
+   /// Record that a device finished charging to full
+   pub fn record_full_charge(&self, address: Address) -> Result<()> {
+      self.update_stats(address, |stats| stats.full_charges += 1)?;
+      Ok(())
+   }
+
+   /// Add discharge of the (left, right) bud, in percent, returning the new totals
+   pub fn add_discharge(&self, address: Address, discharged: [u32; 2]) -> Result<DeviceStats> {
+      self.update_stats(address, |stats| {
+         stats.discharged[0] += discharged[0];
+         stats.discharged[1] += discharged[1];
+      })
+   }
+
+   /// Summarize the study of a device as JSON
+   pub fn summary(&self, address: Address) -> Result<Option<serde_json::Value>> {
+      let rtxn = self.db.env.read_txn().map_err(Error::Transaction)?;
+
+      let Some(study) = self
+         .db
+         .devices
+         .get(&rtxn, &address)
+         .map_err(Error::DatabaseOperation)?
+      else {
+         return Ok(None);
+      };
+      let stats = self
+         .db
+         .stats
+         .get(&rtxn, &address)
+         .map_err(Error::DatabaseOperation)?
+         .unwrap_or_default();
+
+      // Sample-weighted mean across noise modes
+      let mut drain_rates = serde_json::Map::new();
+      let (mut weighted, mut samples) = (0.0, 0u32);
+      for mode in NoiseControlMode::iter() {
+         if let Some(rate) = study.drain_rates.get(mode) {
+            weighted += rate.rate * f64::from(rate.samples);
+            samples += rate.samples;
+            drain_rates.insert(
+               mode.to_str().to_string(),
+               json!({ "rate": rate.rate, "samples": rate.samples }),
+            );
+         }
+      }
+      let average_drain_rate = (samples > 0).then(|| weighted / f64::from(samples));
+
+      Ok(Some(json!({
+         "address": address.to_string(),
+         "device_name": study.device_name,
+         "total_sessions": study.total_sessions,
+         "total_samples": study.total_samples,
+         "first_recorded": (stats.first_recorded > 0).then_some(stats.first_recorded),
+         "last_updated": study.last_updated,
+         "average_drain_rate": average_drain_rate,
+         "drain_rates": drain_rates,
+         "charge_cycles": stats.full_charges,
+         "estimated_cycles": stats.estimated_cycles(),
+      })))
+   }
+
    /// Increment session count for a device
    pub fn increment_session_count(&self, address: Address) -> Result<()> {
       let mut wtxn = self.db.env.write_txn().map_err(Error::Transaction)?;
@@ -419,6 +597,12 @@ pub struct BatteryTracker {
    left_history: BatteryHistory,
    right_history: BatteryHistory,
    last_ttl_estimate: Option<u32>,
+   /// Charging is in progress and has not reached full yet
+   charging_to_full: bool,
+   /// Persisted long-term counters, if the study is available
+   stats: Option<DeviceStats>,
+   /// Discharge of the (left, right) bud not yet saved to the study, in percent
+   pending_discharge: [u32; 2],
    study: Option<BatteryStudy>,
    // Cache for historical drain rates to reduce DB queries
    historical_cache: Mutex<NoiseControlMap<(f64, f64, Instant)>>, // (rate, confidence, last_updated)
@@ -433,13 +617,23 @@ impl BatteryTracker {
       }
    }
 
+   /// Changes how many samples are retained per bud, dropping the oldest if needed.
+   pub fn set_history_len(&mut self, len: usize) {
+      self.left_history.samples.set_capacity(len);
+      self.right_history.samples.set_capacity(len);
+   }
+
    /// Initializes a new battery study session for a device.
-   pub fn init_session(&self, address: Address, device_name: &SmolStr) {
+   pub fn init_session(&mut self, address: Address, device_name: &SmolStr) {
       if let Some(study) = &self.study {
          debug!("Initializing battery study session for {address} ({device_name})");
          if let Err(e) = study.increment_session_count(address) {
             debug!("Failed to increment session count: {e}");
          }
+         match study.update_stats(address, |_| {}) {
+            Ok(stats) => self.stats = Some(stats),
+            Err(e) => debug!("Failed to update device stats: {e}"),
+         }
          match study.get_or_create_study(address, device_name.clone()) {
             Ok(device_study) => {
                debug!(
@@ -462,23 +656,66 @@ impl BatteryTracker {
    /// Records battery levels for both buds, tracking drops for drain rate calculation.
    pub fn record_battery_drop(&mut self, l: BatteryState, r: BatteryState) {
       let now = Instant::now();
+      let [left_discharged, right_discharged] = &mut self.pending_discharge;
 
       [
-         ("left", l, &mut self.left_history),
-         ("right", r, &mut self.right_history),
+         ("left", l, &mut self.left_history, left_discharged),
+         ("right", r, &mut self.right_history, right_discharged),
       ]
       .into_iter()
-      .filter(|(_, state, _)| state.is_available())
-      .for_each(|(name, state, history)| {
+      .filter(|(_, state, _, _)| state.is_available())
+      .for_each(|(name, state, history, discharged)| {
          if state.is_charging() && history.last_level().is_some() {
             debug!("{name} bud started charging, clearing battery history");
             history.clear();
          } else if !state.is_charging() {
-            history.record_battery_drop(state.level, now);
+            *discharged += u32::from(history.record_battery_drop(state.level, now));
          }
       });
    }
 
+   /// Retained samples of both buds, oldest first.
+   ///
+   /// `monotonic` is seconds since service start and is what drain rates are
+   /// computed from; `timestamp` is the Unix time of the sample.
+   pub fn history_json(&self) -> serde_json::Value {
+      json!({
+         "left": self.left_history.to_json(),
+         "right": self.right_history.to_json(),
+      })
+   }
+
+   /// Approximate charge cycles since tracking started, including unsaved discharge.
+   pub fn estimated_cycles(&self) -> Option<f64> {
+      let mut stats = self.stats.clone()?;
+      stats.discharged[0] += self.pending_discharge[0];
+      stats.discharged[1] += self.pending_discharge[1];
+      Some(stats.estimated_cycles())
+   }
+
+   /// Counts a completed charge once every available bud reaches 100% while charging.
+   pub fn record_charge_progress(&mut self, address: Address, l: BatteryState, r: BatteryState) {
+      let buds = [l, r];
+      let available = || buds.iter().filter(|s| s.is_available());
+      if available().count() == 0 {
+         return;
+      }
+
+      if available().all(|s| s.level >= 100) {
+         if self.charging_to_full {
+            self.charging_to_full = false;
+            debug!("{address}: Charged to full");
+            if let Some(study) = &self.study
+               && let Err(e) = study.record_full_charge(address)
+            {
+               debug!("Failed to record full charge: {e}");
+            }
+         }
+      } else {
+         self.charging_to_full = available().any(|s| s.is_charging());
+      }
+   }
+
    /// Estimates battery time-to-live, optionally trying multiple noise modes if none specified.
    pub fn estimate_ttl(
       &mut self,
@@ -567,6 +804,11 @@ impl BatteryTracker {
       let new_minutes = (hours_remaining * 60.0) as u32;
 
       if new_minutes > 0 && new_minutes < 24 * 60 {
+         let alpha = match smoothing::battery_alpha() {
+            0.0 => alpha,
+            configured => configured,
+         };
+
          // Apply hysteresis to avoid jumpy estimates
          let smoothed_minutes = if let Some(last_estimate) = prev_estimate {
             let smoothed =
@@ -782,6 +1024,17 @@ impl BatteryTracker {
    /// Saves aggregated battery drain data to the study database.
    pub fn save_to_study(&mut self, address: Address, noise_mode: NoiseControlMode) {
       if let Some(ref study) = self.study {
+         // Persist discharge for cycle estimation
+         if self.pending_discharge != [0; 2] {
+            match study.add_discharge(address, self.pending_discharge) {
+               Ok(stats) => {
+                  self.stats = Some(stats);
+                  self.pending_discharge = [0; 2];
+               },
+               Err(e) => debug!("Failed to save discharge: {e}"),
+            }
+         }
+
          // Calculate drain rate from current session
          if let Some((drain_rate, _alpha, sample_count)) = self.calculate_local_drain_rate()
             && sample_count >= 4
@@ -912,6 +1165,57 @@ mod tests {
       Ok(())
    }
 
+   #[test]
+   fn test_summary_counts_full_charges() -> Result<()> {
+      let (manager, _dir) = create_test_db()?;
+      assert!(manager.summary(TEST_ADDRESS)?.is_none());
+
+      manager.get_or_create_study(TEST_ADDRESS, SmolStr::new_static("Test AirPods"))?;
+      manager.update_drain_rate(TEST_ADDRESS, NoiseControlMode::Active, 10.0, 1)?;
+      manager.update_drain_rate(TEST_ADDRESS, NoiseControlMode::Off, 20.0, 3)?;
+
+      let mut tracker = BatteryTracker::new(Some(manager.clone()));
+      for (level, charging) in [
+         (60, true),
+         (100, true),
+         (100, true),
+         (90, false),
+         (100, true),
+      ] {
+         let state = mock_state(level, charging);
+         tracker.record_charge_progress(TEST_ADDRESS, state, state);
+      }
+
+      let summary = manager.summary(TEST_ADDRESS)?.unwrap();
+      assert_eq!(summary["total_samples"], 4);
+      assert_eq!(summary["average_drain_rate"], 17.5);
+      assert_eq!(summary["charge_cycles"], 1);
+      assert!(summary["first_recorded"].as_u64().is_some());
+
+      Ok(())
+   }
+
+   #[test]
+   fn test_estimated_cycles_persist() -> Result<()> {
+      let (manager, _dir) = create_test_db()?;
+      let name = SmolStr::new_static("Test AirPods");
+
+      let mut tracker = BatteryTracker::new(Some(manager.clone()));
+      tracker.init_session(TEST_ADDRESS, &name);
+      for level in (50..=100).rev() {
+         tracker.record_battery_drop(mock_state(level, false), mock_state(level, false));
+      }
+      assert_eq!(tracker.estimated_cycles(), Some(0.5));
+      tracker.save_to_study(TEST_ADDRESS, NoiseControlMode::Off);
+
+      // A new session picks up the persisted discharge
+      let mut tracker = BatteryTracker::new(Some(manager));
+      tracker.init_session(TEST_ADDRESS, &name);
+      assert_eq!(tracker.estimated_cycles(), Some(0.5));
+
+      Ok(())
+   }
+
    #[test]
    fn test_update_drain_rate() -> Result<()> {
       let (manager, _dir) = create_test_db()?;
@@ -980,6 +1284,24 @@ mod tests {
       assert_eq!(samples[0].1, 52);
    }
 
+   #[test]
+   fn test_battery_history_json_timestamps() {
+      let mut history = BatteryHistory::default();
+      let base_time = *BASE_TIME;
+      history.push(base_time, 90);
+      history.push(base_time + Duration::from_secs(120), 89);
+
+      let json = history.to_json();
+      let samples = json.as_array().unwrap();
+      assert_eq!(samples.len(), 2);
+      assert_eq!(samples[1]["level"], 89);
+
+      let monotonic = |i: usize| samples[i]["monotonic"].as_u64().unwrap();
+      let timestamp = |i: usize| samples[i]["timestamp"].as_u64().unwrap();
+      assert_eq!(monotonic(1) - monotonic(0), 120);
+      assert!((119..=121).contains(&(timestamp(1) - timestamp(0))));
+   }
+
    #[test]
    fn test_battery_tracker_ttl_when_charging() {
       let mut tracker = BatteryTracker::new(None);
@@ -999,6 +1321,7 @@ mod tests {
             status: BatteryStatus::Normal,
          },
          headphone: BatteryState::new(),
+         primary: None,
       };
 
       // Should return None when charging
@@ -1054,6 +1377,7 @@ mod tests {
             status: BatteryStatus::Normal,
          },
          headphone: BatteryState::new(),
+         primary: None,
       };
 
       // Should return None with insufficient data
@@ -1100,6 +1424,7 @@ mod tests {
             status: BatteryStatus::Normal,
          },
          headphone: BatteryState::new(),
+         primary: None,
       };
 
       // Should be false with no samples
@@ -0,0 +1,64 @@
+//! Minimal ATT client for `AirPods` settings kept outside AAP.
+//!
+//! A few settings, such as Loud Sound Reduction, are not AAP control
+//! commands but attributes read and written over the ATT channel.
+
+use std::time::Duration;
+
+use bluer::Address;
+use log::debug;
+use tokio::{task::JoinSet, time};
+
+use crate::{
+   bluetooth::l2cap::{self, Hooks, Packet},
+   error::{AirPodsError, Result},
+};
+
+/// PSM of the ATT channel over BR/EDR
+const PSM_ATT: u16 = 0x001F;
+/// Timeout for a request's response
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const OP_ERROR_RSP: u8 = 0x01;
+const OP_WRITE_REQ: u8 = 0x12;
+const OP_WRITE_RSP: u8 = 0x13;
+
+/// Attribute handle of the Loud Sound Reduction toggle
+pub const HANDLE_LOUD_SOUND_REDUCTION: u16 = 0x1B;
+
+/// Writes an attribute value, waiting for the device to confirm it.
+pub async fn write(address: Address, handle: u16, value: &[u8]) -> Result<()> {
+   let mut pdu = vec![OP_WRITE_REQ];
+   pdu.extend_from_slice(&handle.to_le_bytes());
+   pdu.extend_from_slice(value);
+   request(address, handle, &pdu, OP_WRITE_RSP).await?;
+   Ok(())
+}
+
+/// Sends a request on a fresh ATT channel and returns the matching response.
+async fn request(address: Address, handle: u16, pdu: &[u8], response: u8) -> Result<Packet> {
+   // Dropping the set closes the channel once the request is done
+   let mut jset = JoinSet::new();
+   let (mut receiver, sender) =
+      l2cap::connect(&mut jset, Hooks::new(), address, Some(PSM_ATT)).await?;
+   sender.send(pdu).await?;
+
+   time::timeout(RESPONSE_TIMEOUT, async {
+      loop {
+         let packet = receiver.recv().await?;
+         match packet.first() {
+            Some(&op) if op == response => return Ok(packet),
+            Some(&OP_ERROR_RSP) if packet.len() >= 5 && packet[1] == pdu[0] => {
+               return Err(AirPodsError::Att {
+                  handle,
+                  code: packet[4],
+               });
+            },
+            // Notifications and unrelated PDUs
+            _ => debug!("{address}: Ignoring ATT PDU {}", hex::encode(&packet)),
+         }
+      }
+   })
+   .await
+   .map_err(|_| AirPodsError::RequestTimeout)?
+}
@@ -24,7 +24,7 @@ pub type Packet = SmallVec<[u8; 32]>;
 /// PSM (Protocol Service Multiplexer) for `AirPods` control channel
 const PSM_CONTROL: u16 = 0x1001;
 /// Maximum transmission unit for L2CAP packets
-const L2CAP_MTU: usize = 672;
+pub const L2CAP_MTU: usize = 672;
 /// Timeout for write operations
 const WRITE_TIMEOUT: Duration = Duration::from_secs(25);
 /// Timeout for connection attempts
@@ -87,6 +87,28 @@ impl L2CapSender {
    }
 }
 
+#[cfg(test)]
+impl L2CapSender {
+   /// Creates a sender whose writes are collected instead of sent, failing
+   /// the first `failures` of them with an I/O error.
+   pub fn loopback(mut failures: usize) -> (Self, mpsc::UnboundedReceiver<Packet>) {
+      let (tx, mut rx) = mpsc::channel(128);
+      let (sent_tx, sent_rx) = mpsc::unbounded_channel();
+      tokio::spawn(async move {
+         while let Some(Command::Send { data, then }) = rx.recv().await {
+            if failures > 0 {
+               failures -= 1;
+               let _ = then.send(Err(AirPodsError::Io(std::io::ErrorKind::BrokenPipe.into())));
+            } else {
+               let _ = sent_tx.send(data);
+               let _ = then.send(Ok(()));
+            }
+         }
+      });
+      (Self { tx }, sent_rx)
+   }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum HookDisposition {
    Discard,
@@ -5,10 +5,13 @@
 
 use std::{
    collections::{HashMap, HashSet},
+   sync::Arc,
    time::Duration,
 };
 
-use bluer::{Adapter, AdapterEvent, Address, Session};
+use bluer::{
+   Adapter, AdapterEvent, AdapterProperty, Address, DiscoveryFilter, DiscoveryTransport, Session,
+};
 use futures::stream::StreamExt;
 use log::{debug, error, info, warn};
 use smol_str::SmolStr;
@@ -16,15 +19,23 @@ use tokio::{
    select,
    sync::{mpsc, oneshot},
    task::JoinHandle,
-   time::{self, MissedTickBehavior},
+   time::{self, Instant, MissedTickBehavior},
 };
 
 use crate::{
-   airpods::{self, device::AirPods},
+   airpods::{
+      self,
+      device::{AirPods, RetryPolicy},
+      protocol::{FeatureId, NoiseControlMode},
+      recognition::DeviceModel,
+   },
    battery_study::BatteryStudy,
-   config::Config,
+   bluetooth::transport,
+   config::{Config, DeviceSort},
+   device_state::{DeviceSettings, DeviceState},
    error::{AirPodsError, Result},
-   event::{AirPodsEvent, EventSender},
+   event::{AirPodsEvent, ConnectionState, DisconnectReason, EventSender},
+   media_control,
 };
 use rand::Rng;
 
@@ -32,14 +43,20 @@ use rand::Rng;
 const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
 /// Interval to check for new adapters
 const ADAPTER_CHECK_INTERVAL: Duration = Duration::from_secs(10);
-/// Delay before retrying adapter operations after failure
-const ADAPTER_RECOVERY_DELAY: Duration = Duration::from_secs(5);
 /// Maximum time to wait for AAP connection
 const AAP_CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
 /// Maximum AAP connection retry delay
 const MAX_AAP_RETRY_DELAY: Duration = Duration::from_secs(120);
 /// Device tick interval
 const DEVICE_TICK_INTERVAL: Duration = Duration::from_secs(10);
+/// Pause between attempts to find a Bluetooth adapter at startup
+const ADAPTER_SETTLE_DELAY: Duration = Duration::from_secs(1);
+/// Pause between tearing down and re-establishing an AAP session
+const AAP_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+/// Maximum time to wait for device state before restoring settings
+const RESTORE_SETTINGS_TIMEOUT: Duration = Duration::from_secs(5);
+/// Fraction of the volume kept while ducking for an opened case
+const CASE_OPEN_DUCK_FACTOR: f64 = 0.3;
 /// Channel buffer size
 const CHANNEL_BUFFER_SIZE: usize = 1000;
 
@@ -48,6 +65,7 @@ const CHANNEL_BUFFER_SIZE: usize = 1000;
 #[derive(Debug, Clone, PartialEq)]
 enum AdapterState {
    Active,
+   PoweredOff,
    Lost,
    Failed(String),
 }
@@ -56,6 +74,8 @@ struct AdapterInfo {
    adapter: Adapter,
    state: AdapterState,
    monitor_handle: Option<JoinHandle<()>>,
+   /// Discovery session watching for case lid changes
+   case_watch_handle: Option<JoinHandle<()>>,
    retry_count: u32,
    name: SmolStr,
 }
@@ -85,6 +105,22 @@ struct ManagedDevice {
    aap_retry_count: u32,
    last_aap_error: Option<String>,
    aap_handle: Option<JoinHandle<()>>,
+   /// When the current AAP session was established
+   connected_at: Option<Instant>,
+   /// When the device last connected, disconnected or advertised
+   last_seen: Instant,
+}
+
+impl ManagedDevice {
+   /// Publishes the connection state implied by the link and session states.
+   fn update_connection_state(&self, event_tx: &EventSender) {
+      let state = match (self.bluetooth_state, self.aap_state) {
+         (BluetoothState::Disconnected, _) => ConnectionState::Disconnected,
+         (_, AAPState::Connecting | AAPState::Connected) => ConnectionState::Handshaking,
+         _ => ConnectionState::BluetoothConnected,
+      };
+      self.device.set_connection_state(state, event_tx);
+   }
 }
 
 // === Commands ===
@@ -94,7 +130,8 @@ enum ManagerCommand {
    // Adapter events
    AdapterAvailable(SmolStr, Adapter),
    AdapterLost(SmolStr),
-   AdapterError(SmolStr, String), // adapter_name, error
+   AdapterError(SmolStr, String),      // adapter_name, error
+   AdapterPowerChanged(SmolStr, bool), // adapter_name, powered
 
    // Device events
    DeviceDiscovered(Address, SmolStr), // address, adapter_name
@@ -103,6 +140,8 @@ enum ManagerCommand {
    AAPConnected(Address),
    AAPDisconnected(Address, bool), // address, is_error
    DeviceLost(Address),
+   AdvertisementChanged(Address, Vec<u8>), // address, Apple manufacturer data
+   VolumeChanged(Address, u8),             // address, percent
 
    // User commands
    EstablishAAP(Address, Option<oneshot::Sender<Result<()>>>),
@@ -110,6 +149,10 @@ enum ManagerCommand {
    GetDeviceState(Address, oneshot::Sender<Option<AirPods>>),
    GetAllDeviceStates(oneshot::Sender<Vec<AirPods>>),
    CountDevices(oneshot::Sender<u32>),
+   GetActiveAdapters(oneshot::Sender<Vec<Adapter>>),
+   GetAdvertisement(Address, oneshot::Sender<Option<Vec<u8>>>),
+   GetConnectionUptime(Address, oneshot::Sender<Result<Duration>>),
+   Shutdown(oneshot::Sender<()>),
 }
 
 // === Main Manager ===
@@ -118,8 +161,10 @@ enum ManagerCommand {
 ///
 /// This type provides a high-level interface for managing `AirPods` devices
 /// across all available Bluetooth adapters.
+#[derive(Clone)]
 pub struct BluetoothManager {
    inbox: mpsc::Sender<ManagerCommand>,
+   device_state: Arc<DeviceState>,
 }
 
 impl BluetoothManager {
@@ -127,14 +172,36 @@ impl BluetoothManager {
       event_tx: EventSender,
       config: Config,
       battery_study: Option<BatteryStudy>,
+      device_state: Arc<DeviceState>,
    ) -> Result<Self> {
+      if config.startup_delay_ms > 0 {
+         let delay = Duration::from_millis(config.startup_delay_ms);
+         info!("Waiting {delay:?} before starting the Bluetooth manager");
+         time::sleep(delay).await;
+      }
+      let session = acquire_session(config.adapter_retry_count).await?;
+
       let (command_tx, command_rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
       tokio::spawn(
-         ManagerActor::new(config, event_tx, command_rx, battery_study)
-            .await
-            .run(),
+         ManagerActor::new(
+            config,
+            event_tx,
+            command_rx,
+            battery_study,
+            device_state.clone(),
+            session,
+         )
+         .run(),
       );
-      Ok(Self { inbox: command_tx })
+      Ok(Self {
+         inbox: command_tx,
+         device_state,
+      })
+   }
+
+   /// Runtime device state, shared with the manager.
+   pub fn device_state(&self) -> &DeviceState {
+      &self.device_state
    }
 
    pub async fn establish_aap(&self, address: Address) -> Result<()> {
@@ -157,6 +224,13 @@ impl BluetoothManager {
       rx.await.map_err(|_| AirPodsError::ManagerShutdown)?
    }
 
+   /// Tears down the AAP session and establishes a fresh one.
+   pub async fn reconnect_aap(&self, address: Address) -> Result<()> {
+      self.disconnect_aap(address).await?;
+      time::sleep(AAP_RECONNECT_DELAY).await;
+      self.establish_aap(address).await
+   }
+
    pub async fn get_device(&self, address: Address) -> Result<AirPods> {
       let (tx, rx) = oneshot::channel();
       self
@@ -184,6 +258,91 @@ impl BluetoothManager {
       rx.await.unwrap_or_default()
    }
 
+   /// Actively scans for advertising `AirPods` for the given duration.
+   ///
+   /// Discovery is stopped once the scan completes.
+   pub async fn scan(&self, timeout: Duration) -> Result<Vec<(Address, Option<DeviceModel>)>> {
+      let (tx, rx) = oneshot::channel();
+      self
+         .inbox
+         .send(ManagerCommand::GetActiveAdapters(tx))
+         .await
+         .map_err(|_| AirPodsError::ManagerShutdown)?;
+      let adapter = rx
+         .await
+         .map_err(|_| AirPodsError::ManagerShutdown)?
+         .into_iter()
+         .next()
+         .ok_or(AirPodsError::AdapterNotAvailable)?;
+
+      // The same filter as the case lid watch, which shares the adapter
+      adapter.set_discovery_filter(le_discovery_filter()).await?;
+
+      info!("Scanning for AirPods on {} for {timeout:?}", adapter.name());
+      let events = adapter.discover_devices().await?;
+      futures::pin_mut!(events);
+
+      let mut found = Vec::new();
+      let deadline = time::sleep(timeout);
+      tokio::pin!(deadline);
+      loop {
+         select! {
+            () = &mut deadline => break,
+            event = events.next() => {
+               let Some(event) = event else {
+                  break;
+               };
+               if let AdapterEvent::DeviceAdded(addr) = event
+                  && !found.iter().any(|(a, _)| *a == addr)
+                  && let Ok(device) = adapter.device(addr)
+                  && airpods::recognition::advertises_airpods(&device).await
+               {
+                  let model = airpods::recognition::detect_model(&device).await;
+                  debug!("Scan found AirPods at {addr} (model: {model:?})");
+                  found.push((addr, model));
+               }
+            }
+         }
+      }
+
+      // Dropping the event stream stops discovery
+      info!("Scan finished, found {} device(s)", found.len());
+      Ok(found)
+   }
+
+   /// Gets the most recent Apple manufacturer advertisement seen from the device.
+   pub async fn get_advertisement(&self, address: Address) -> Result<Vec<u8>> {
+      let (tx, rx) = oneshot::channel();
+      self
+         .inbox
+         .send(ManagerCommand::GetAdvertisement(address, tx))
+         .await
+         .map_err(|_| AirPodsError::ManagerShutdown)?;
+      rx.await
+         .map_err(|_| AirPodsError::ManagerShutdown)?
+         .ok_or(AirPodsError::DeviceNotFound(address))
+   }
+
+   /// Gets how long the device's current AAP session has been up.
+   pub async fn connection_uptime(&self, address: Address) -> Result<Duration> {
+      let (tx, rx) = oneshot::channel();
+      self
+         .inbox
+         .send(ManagerCommand::GetConnectionUptime(address, tx))
+         .await
+         .map_err(|_| AirPodsError::ManagerShutdown)?;
+      rx.await.map_err(|_| AirPodsError::ManagerShutdown)?
+   }
+
+   /// Disconnects all devices and stops the manager, returning once done.
+   pub async fn shutdown(&self) {
+      let (tx, rx) = oneshot::channel();
+      if self.inbox.send(ManagerCommand::Shutdown(tx)).await.is_ok() {
+         let _ = rx.await;
+      }
+   }
+
+   /// Counts the devices with an active AAP connection.
    pub async fn count_devices(&self) -> u32 {
       let (tx, rx) = oneshot::channel();
       if self
@@ -208,24 +367,27 @@ struct ManagerActor {
    loopback_tx: mpsc::Sender<ManagerCommand>,
    session: Session,
    battery_study: Option<BatteryStudy>,
+   device_state: Arc<DeviceState>,
 
    // State
    adapters: HashMap<SmolStr, AdapterInfo>,
    devices: HashMap<Address, ManagedDevice>,
    aap_connecting: HashSet<Address>, // Prevent duplicate AAP connections
+   advertisements: HashMap<Address, Vec<u8>>, // Latest Apple manufacturer data
+   volume_watch_handle: Option<JoinHandle<()>>,
+   /// Told once cleanup finishes, when shutdown was requested
+   shutdown_reply: Option<oneshot::Sender<()>>,
 }
 
 impl ManagerActor {
-   async fn new(
+   fn new(
       config: Config,
       event_tx: EventSender,
       command_rx: mpsc::Receiver<ManagerCommand>,
       battery_study: Option<BatteryStudy>,
+      device_state: Arc<DeviceState>,
+      session: Session,
    ) -> Self {
-      let session = Session::new()
-         .await
-         .expect("Failed to create Bluetooth session");
-
       let (loopback_tx, loopback_rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
       Self {
          config,
@@ -235,9 +397,13 @@ impl ManagerActor {
          loopback_tx,
          session,
          battery_study,
+         device_state,
          adapters: HashMap::new(),
          devices: HashMap::new(),
          aap_connecting: HashSet::new(),
+         advertisements: HashMap::new(),
+         volume_watch_handle: None,
+         shutdown_reply: None,
       }
    }
 
@@ -246,6 +412,7 @@ impl ManagerActor {
 
       // Initialize adapters
       self.initialize_adapters().await;
+      self.volume_watch_handle = Some(Self::start_volume_watch(self.loopback_tx.clone()));
 
       // Start periodic checks
       let mut health_check_interval = time::interval(HEALTH_CHECK_INTERVAL);
@@ -292,6 +459,9 @@ impl ManagerActor {
 
       // Cleanup
       self.cleanup().await;
+      if let Some(reply) = self.shutdown_reply.take() {
+         let _ = reply.send(());
+      }
    }
 
    async fn initialize_adapters(&mut self) {
@@ -317,37 +487,33 @@ impl ManagerActor {
          Ok(adapter) => {
             info!("Initializing adapter: {name}");
 
-            // Ensure adapter is powered on
-            if let Ok(powered) = adapter.is_powered().await
-               && !powered
-            {
-               if let Err(e) = adapter.set_powered(true).await {
-                  warn!("Failed to power on adapter {name}: {e}");
-                  // Schedule retry
-                  let loopback = self.loopback_tx.clone();
-                  let name_clone = name.clone();
-                  let adapter_clone = adapter.clone();
-                  tokio::spawn(async move {
-                     time::sleep(ADAPTER_RECOVERY_DELAY).await;
-                     let _ = loopback
-                        .send(ManagerCommand::AdapterAvailable(name_clone, adapter_clone))
-                        .await;
-                  });
-                  return;
-               }
-               info!("Powered on adapter: {name}");
-            }
+            // Don't force the adapter on; wait for the user to enable Bluetooth
+            let powered = adapter.is_powered().await.unwrap_or(false);
+            let state = if powered {
+               AdapterState::Active
+            } else {
+               info!("Adapter {name} is powered off, waiting for Bluetooth to be enabled");
+               AdapterState::PoweredOff
+            };
 
             // Start monitoring this adapter
             self.adapters.insert(
                name.clone(),
                AdapterInfo {
-                  state: AdapterState::Active,
+                  state,
                   monitor_handle: Some(Self::start_adapter_monitor(
                      self.loopback_tx.clone(),
                      name.clone(),
                      adapter.clone(),
                   )),
+                  case_watch_handle: self.config.case_open_duck.then(|| {
+                     Self::start_case_watch(
+                        self.loopback_tx.clone(),
+                        name.clone(),
+                        adapter.clone(),
+                        known_addresses(&self.config),
+                     )
+                  }),
                   adapter,
                   retry_count: 0,
                   name: name.clone(),
@@ -355,7 +521,9 @@ impl ManagerActor {
             );
 
             // Check for already connected devices
-            self.check_connected_devices(&name).await;
+            if powered {
+               self.check_connected_devices(&name).await;
+            }
          },
          Err(e) => {
             warn!("Failed to initialize adapter {name}: {e}");
@@ -394,6 +562,12 @@ impl ManagerActor {
                   debug!("Device removed on {name}: {addr}");
                   let _ = loopback.send(ManagerCommand::DeviceLost(addr)).await;
                },
+               AdapterEvent::PropertyChanged(AdapterProperty::Powered(powered)) => {
+                  debug!("Adapter {name} powered: {powered}");
+                  let _ = loopback
+                     .send(ManagerCommand::AdapterPowerChanged(name.clone(), powered))
+                     .await;
+               },
                // Note: bluer doesn't provide DeviceConnected/Disconnected events
                // We'll detect connection changes through periodic scanning
                _ => {},
@@ -407,6 +581,116 @@ impl ManagerActor {
       })
    }
 
+   /// Forwards media transport volume changes, such as from stem swipes,
+   /// which come without any AAP notification.
+   fn start_volume_watch(loopback: mpsc::Sender<ManagerCommand>) -> JoinHandle<()> {
+      tokio::spawn(async move {
+         let (tx, mut rx) = mpsc::channel(16);
+         let watch = transport::watch_volume(tx);
+         tokio::pin!(watch);
+         loop {
+            select! {
+               result = &mut watch => {
+                  if let Err(e) = result {
+                     warn!("Failed to watch media transport volume: {e}");
+                  }
+                  break;
+               }
+               Some((addr, volume)) = rx.recv() => {
+                  let _ = loopback.send(ManagerCommand::VolumeChanged(addr, volume)).await;
+               }
+            }
+         }
+      })
+   }
+
+   /// Keeps a passive LE discovery session open so `BlueZ` reports
+   /// advertisement changes, such as a case lid being opened.
+   ///
+   /// Every nearby device advertises, so only lid counter changes of known
+   /// or paired `AirPods` are passed on to the actor.
+   fn start_case_watch(
+      loopback: mpsc::Sender<ManagerCommand>,
+      name: SmolStr,
+      adapter: Adapter,
+      known: HashSet<Address>,
+   ) -> JoinHandle<()> {
+      tokio::spawn(async move {
+         if let Err(e) = adapter.set_discovery_filter(le_discovery_filter()).await {
+            warn!("Failed to set discovery filter on {name}: {e}");
+         }
+         let events = match adapter.discover_devices_with_changes().await {
+            Ok(events) => events,
+            Err(e) => {
+               warn!("Failed to start case lid watch on {name}: {e}");
+               return;
+            },
+         };
+         futures::pin_mut!(events);
+         debug!("Watching for case lid changes on {name}");
+
+         // Last lid counter passed on per device, and Apple devices that
+         // aren't AirPods
+         let mut lid_counts = HashMap::new();
+         let mut ignored = HashSet::new();
+         while let Some(event) = events.next().await {
+            let AdapterEvent::DeviceAdded(addr) = event else {
+               continue;
+            };
+            if ignored.contains(&addr) {
+               continue;
+            }
+            let Ok(device) = adapter.device(addr) else {
+               continue;
+            };
+            let Some(data) = airpods::recognition::apple_manufacturer_data(&device).await else {
+               continue;
+            };
+            if !airpods::recognition::check_manufacturer_data(&data) {
+               ignored.insert(addr);
+               continue;
+            }
+            let Some(count) = airpods::recognition::lid_open_count(&data) else {
+               continue;
+            };
+            if lid_counts.get(&addr) == Some(&count)
+               || !known.contains(&addr) && !device.is_paired().await.unwrap_or(false)
+            {
+               continue;
+            }
+            lid_counts.insert(addr, count);
+            let _ = loopback
+               .send(ManagerCommand::AdvertisementChanged(addr, data))
+               .await;
+         }
+      })
+   }
+
+   /// Ducks media briefly when the case of a paired, unconnected device is
+   /// opened, as seen from its lid open counter changing.
+   fn handle_advertisement_changed(&mut self, addr: Address, data: Vec<u8>) {
+      if !self.is_allowed_device(addr) {
+         return;
+      }
+      if let Some(tracked) = self.devices.get_mut(&addr) {
+         tracked.last_seen = Instant::now();
+      }
+      let Some(count) = airpods::recognition::lid_open_count(&data) else {
+         return;
+      };
+      let previous = self
+         .advertisements
+         .insert(addr, data)
+         .and_then(|prev| airpods::recognition::lid_open_count(&prev));
+      if previous.is_none_or(|prev| prev == count) || self.has_aap_connection(addr) {
+         return;
+      }
+
+      info!("Case lid of {addr} opened, ducking media");
+      let duration = Duration::from_millis(self.config.case_open_duck_ms);
+      tokio::spawn(media_control::duck_playing(CASE_OPEN_DUCK_FACTOR, duration));
+   }
+
    async fn check_connected_devices(&self, adapter_name: &SmolStr) {
       let Some(adapter_info) = self.adapters.get(adapter_name) else {
          return;
@@ -431,6 +715,12 @@ impl ManagerActor {
       }
    }
 
+   /// Checks whether the daemon may talk to the device, which with
+   /// `known_devices_only` requires it to be listed in `known_devices`.
+   fn is_allowed_device(&self, addr: Address) -> bool {
+      !self.config.known_devices_only || self.config.is_known_device(&addr.to_string()).is_some()
+   }
+
    async fn is_airpods_device(&self, device: &bluer::Device) -> bool {
       // Check known addresses
       let addr = device.address();
@@ -451,6 +741,9 @@ impl ManagerActor {
          ManagerCommand::AdapterError(name, error) => {
             self.handle_adapter_error(&name, error);
          },
+         ManagerCommand::AdapterPowerChanged(name, powered) => {
+            self.handle_adapter_power_changed(name, powered).await;
+         },
          ManagerCommand::DeviceDiscovered(addr, adapter_name) => {
             self.handle_device_discovered(addr, adapter_name).await;
          },
@@ -469,6 +762,14 @@ impl ManagerActor {
          ManagerCommand::DeviceLost(addr) => {
             self.handle_device_lost(addr);
          },
+         ManagerCommand::AdvertisementChanged(addr, data) => {
+            self.handle_advertisement_changed(addr, data);
+         },
+         ManagerCommand::VolumeChanged(addr, volume) => {
+            if let Some(device) = self.devices.get(&addr) {
+               device.device.report_volume(volume, &self.event_tx);
+            }
+         },
          ManagerCommand::EstablishAAP(addr, reply) => {
             let result = self.establish_aap_connection(addr).await;
             if let Some(reply) = reply {
@@ -486,13 +787,46 @@ impl ManagerActor {
             let _ = reply.send(state);
          },
          ManagerCommand::GetAllDeviceStates(reply) => {
-            let states = self.devices.values().map(|d| d.device.clone()).collect();
+            let mut states: Vec<_> = self.devices.values().map(|d| d.device.clone()).collect();
+            sort_devices(&mut states, self.config.device_sort);
             let _ = reply.send(states);
          },
          ManagerCommand::CountDevices(reply) => {
-            let count = self.devices.len() as u32;
+            let count = self
+               .devices
+               .values()
+               .filter(|d| d.device.is_connected())
+               .count() as u32;
             let _ = reply.send(count);
          },
+         ManagerCommand::GetAdvertisement(addr, reply) => {
+            let advertisement = self.refresh_advertisement(addr).await;
+            let _ = reply.send(advertisement);
+         },
+         ManagerCommand::GetConnectionUptime(addr, reply) => {
+            let uptime = match self.devices.get(&addr) {
+               None => Err(AirPodsError::DeviceNotFound(addr)),
+               Some(device) => match device.connected_at {
+                  Some(at) if device.aap_state == AAPState::Connected => Ok(at.elapsed()),
+                  _ => Err(AirPodsError::DeviceNotConnected),
+               },
+            };
+            let _ = reply.send(uptime);
+         },
+         ManagerCommand::GetActiveAdapters(reply) => {
+            let adapters = self
+               .adapters
+               .values()
+               .filter(|info| info.state == AdapterState::Active)
+               .map(|info| info.adapter.clone())
+               .collect();
+            let _ = reply.send(adapters);
+         },
+         ManagerCommand::Shutdown(reply) => {
+            info!("Bluetooth manager shutting down on request");
+            self.shutdown_reply = Some(reply);
+            return false;
+         },
       }
       true
    }
@@ -501,8 +835,8 @@ impl ManagerActor {
       info!("Adapter available: {name}");
 
       if let Some(info) = self.adapters.get_mut(&name) {
+         let powered = adapter.is_powered().await.unwrap_or(false);
          info.adapter = adapter;
-         info.state = AdapterState::Active;
          info.retry_count = 0; // Reset retry count on success
 
          // Restart monitor if needed
@@ -513,6 +847,21 @@ impl ManagerActor {
                info.adapter.clone(),
             ));
          }
+         if self.config.case_open_duck && info.case_watch_handle.is_none() {
+            info.case_watch_handle = Some(Self::start_case_watch(
+               self.loopback_tx.clone(),
+               name.clone(),
+               info.adapter.clone(),
+               known_addresses(&self.config),
+            ));
+         }
+
+         if !powered {
+            info!("Adapter {name} is powered off, waiting for Bluetooth to be enabled");
+            info.state = AdapterState::PoweredOff;
+            return;
+         }
+         info.state = AdapterState::Active;
 
          // Re-check connected devices and trigger reconnects
          self.check_connected_devices(&name).await;
@@ -548,11 +897,16 @@ impl ManagerActor {
          if let Some(handle) = info.monitor_handle.take() {
             handle.abort();
          }
+         if let Some(handle) = info.case_watch_handle.take() {
+            handle.abort();
+         }
 
          // Mark all AAP connections on this adapter as failed
          for device in self.devices.values_mut() {
             if device.adapter_name == name {
                device.aap_state = AAPState::Failed("Adapter lost");
+               device.update_connection_state(&self.event_tx);
+               device.device.record_error("Adapter lost");
                // Abort AAP handle if it exists
                if let Some(handle) = device.aap_handle.take() {
                   handle.abort();
@@ -599,11 +953,35 @@ impl ManagerActor {
       }
    }
 
+   async fn handle_adapter_power_changed(&mut self, name: SmolStr, powered: bool) {
+      let Some(info) = self.adapters.get_mut(&name) else {
+         return;
+      };
+
+      match (&info.state, powered) {
+         (AdapterState::PoweredOff, true) => {
+            info!("Adapter {name} powered on, starting device discovery");
+            info.state = AdapterState::Active;
+            self.check_connected_devices(&name).await;
+         },
+         (AdapterState::Active, false) => {
+            // Connection health checks will pick up the dropped devices
+            info!("Adapter {name} powered off, waiting for Bluetooth to be enabled");
+            info.state = AdapterState::PoweredOff;
+         },
+         _ => {},
+      }
+   }
+
    async fn handle_device_discovered(&mut self, addr: Address, adapter_name: SmolStr) {
       // Check if we already know about this device
       if self.devices.contains_key(&addr) {
          return;
       }
+      if !self.is_allowed_device(addr) {
+         debug!("Ignoring {addr}, which is not a known device");
+         return;
+      }
 
       // Verify it's an AirPods device
       let Some(adapter_info) = self.adapters.get(&adapter_name) else {
@@ -618,6 +996,10 @@ impl ManagerActor {
          return;
       }
 
+      if let Some(data) = airpods::recognition::apple_manufacturer_data(&device).await {
+         self.advertisements.insert(addr, data);
+      }
+
       // Only proceed if already connected by bluetoothd
       if !device.is_connected().await.unwrap_or(false) {
          debug!("Discovered AirPods at {addr} but not connected by system");
@@ -630,10 +1012,20 @@ impl ManagerActor {
          .ok()
          .flatten()
          .unwrap_or_else(|| addr.to_string());
-      info!("Found connected AirPods: {name} ({addr})");
+      let model = airpods::recognition::detect_model(&device).await;
+      info!("Found connected AirPods: {name} ({addr}, model: {model:?})");
 
       // Create managed device
-      let airpods = AirPods::new(addr, name, self.battery_study.clone());
+      let airpods = AirPods::new(addr, name, model, self.battery_study.clone());
+      airpods.set_emit_raw_events(self.config.emit_raw_events);
+      airpods.set_emit_spatial_events(self.config.emit_spatial_events);
+      airpods.set_battery_history_len(self.config.battery_history_len);
+      airpods.set_battery_offsets(self.device_state.battery_offsets(addr).await);
+      airpods.set_ignored_ear_sensors(self.device_state.ignored_ear_sensors(addr).await);
+      airpods.set_retry_policy(RetryPolicy {
+         retries: self.config.command_retry_count,
+         backoff: Duration::from_millis(self.config.command_retry_delay_ms),
+      });
       let managed = ManagedDevice {
          device: airpods,
          bluetooth_state: BluetoothState::Connected,
@@ -642,8 +1034,12 @@ impl ManagerActor {
          aap_retry_count: 0,
          last_aap_error: None,
          aap_handle: None,
+         connected_at: None,
+         last_seen: Instant::now(),
       };
 
+      managed.update_connection_state(&self.event_tx);
+      self.evict_stale_device();
       self.devices.insert(addr, managed);
 
       // Establish AAP connection for already-connected device
@@ -654,6 +1050,8 @@ impl ManagerActor {
       // Check if this is an AirPods device
       let is_airpods = if let Some(device) = self.devices.get_mut(&addr) {
          device.bluetooth_state = BluetoothState::Connected;
+         device.last_seen = Instant::now();
+         device.update_connection_state(&self.event_tx);
          true
       } else {
          // Check if this is a newly connected AirPods
@@ -680,17 +1078,23 @@ impl ManagerActor {
 
    fn handle_bluetooth_disconnected(&mut self, addr: Address) {
       if let Some(device) = self.devices.get_mut(&addr) {
+         let reason = match self.adapters.get(&device.adapter_name) {
+            Some(info) if info.state == AdapterState::PoweredOff => DisconnectReason::BluetoothOff,
+            _ => DisconnectReason::Timeout,
+         };
          device.bluetooth_state = BluetoothState::Disconnected;
+         device.last_seen = Instant::now();
 
          // Clean up AAP connection
          if let Some(handle) = device.aap_handle.take() {
             handle.abort();
          }
          device.aap_state = AAPState::Disconnected;
+         device.update_connection_state(&self.event_tx);
 
          self
             .event_tx
-            .emit(&device.device, AirPodsEvent::DeviceDisconnected);
+            .emit(&device.device, AirPodsEvent::DeviceDisconnected(reason));
       }
 
       self.aap_connecting.remove(&addr);
@@ -699,12 +1103,24 @@ impl ManagerActor {
    fn handle_aap_connected(&mut self, addr: Address) {
       if let Some(device) = self.devices.get_mut(&addr) {
          device.aap_state = AAPState::Connected;
+         device.update_connection_state(&self.event_tx);
+         device.connected_at = Some(Instant::now());
          device.aap_retry_count = 0;
          device.last_aap_error = None;
 
          self
             .event_tx
             .emit(&device.device, AirPodsEvent::DeviceConnected);
+
+         if self.config.restore_settings_on_connect {
+            let device_state = self.device_state.clone();
+            let device = device.device.clone();
+            tokio::spawn(async move {
+               if let Some(settings) = device_state.device_settings(addr).await {
+                  restore_settings(device, settings).await;
+               }
+            });
+         }
       }
 
       self.aap_connecting.remove(&addr);
@@ -712,7 +1128,22 @@ impl ManagerActor {
 
    fn handle_aap_disconnected(&mut self, addr: Address, is_error: bool) {
       if let Some(device) = self.devices.get_mut(&addr) {
-         if is_error && device.bluetooth_state == BluetoothState::Connected {
+         let max_attempts = self.config.max_reconnect_attempts;
+         if is_error
+            && device.bluetooth_state == BluetoothState::Connected
+            && max_attempts > 0
+            && device.aap_retry_count >= max_attempts
+         {
+            // Leave it to the user to reconnect, which starts a fresh count
+            warn!("Giving up on AAP connection to {addr} after {max_attempts} attempt(s)");
+            device.aap_state = AAPState::Failed("Gave up reconnecting");
+            device.aap_retry_count = 0;
+            device.device.record_error("Gave up reconnecting");
+            self.event_tx.emit(
+               &device.device,
+               AirPodsEvent::DeviceDisconnected(DisconnectReason::GaveUp),
+            );
+         } else if is_error && device.bluetooth_state == BluetoothState::Connected {
             // Only retry AAP if Bluetooth is still connected
             device.aap_state = AAPState::WaitingToReconnect;
             device.aap_retry_count += 1;
@@ -732,6 +1163,7 @@ impl ManagerActor {
             device.aap_state = AAPState::Disconnected;
             device.aap_retry_count = 0;
          }
+         device.update_connection_state(&self.event_tx);
       }
 
       self.aap_connecting.remove(&addr);
@@ -739,14 +1171,56 @@ impl ManagerActor {
 
    fn handle_device_lost(&mut self, addr: Address) {
       if let Some(device) = self.devices.remove(&addr) {
-         self
-            .event_tx
-            .emit(&device.device, AirPodsEvent::DeviceDisconnected);
+         device
+            .device
+            .set_connection_state(ConnectionState::Disconnected, &self.event_tx);
+         self.event_tx.emit(
+            &device.device,
+            AirPodsEvent::DeviceDisconnected(DisconnectReason::User),
+         );
       }
       self.aap_connecting.remove(&addr);
    }
 
+   /// Makes room for a new device once `max_tracked_devices` is reached by
+   /// forgetting the disconnected device seen least recently. Connected
+   /// devices are never evicted, so the cap may be exceeded while they last.
+   fn evict_stale_device(&mut self) {
+      let max = self.config.max_tracked_devices;
+      if max == 0 || self.devices.len() < max {
+         return;
+      }
+      let Some(addr) = self
+         .devices
+         .iter()
+         .filter(|(_, d)| d.bluetooth_state == BluetoothState::Disconnected)
+         .min_by_key(|(_, d)| d.last_seen)
+         .map(|(addr, _)| *addr)
+      else {
+         warn!(
+            "Tracking {} devices, above max_tracked_devices = {max}, but all are connected",
+            self.devices.len()
+         );
+         return;
+      };
+      if let Some(mut device) = self.devices.remove(&addr)
+         && let Some(handle) = device.aap_handle.take()
+      {
+         handle.abort();
+      }
+      self.advertisements.remove(&addr);
+      self.aap_connecting.remove(&addr);
+      info!(
+         "Evicted {addr}, the least recently seen disconnected device, to stay within \
+          max_tracked_devices = {max}"
+      );
+   }
+
    async fn establish_aap_connection(&mut self, addr: Address) -> Result<()> {
+      if !self.is_allowed_device(addr) {
+         return Err(AirPodsError::DeviceNotKnown(addr));
+      }
+
       // Check if already connecting
       if self.aap_connecting.contains(&addr) {
          return Err(AirPodsError::AlreadyConnecting);
@@ -782,6 +1256,7 @@ impl ManagerActor {
 
       // Spawn AAP connection task
       let airpods = device.device.clone();
+      airpods.reset_ready();
       let event_tx = self.event_tx.clone();
       let loopback = self.loopback_tx.clone();
 
@@ -789,10 +1264,12 @@ impl ManagerActor {
          let err = match time::timeout(AAP_CONNECTION_TIMEOUT, airpods.connect(&event_tx)).await {
             Ok(Err(e)) => {
                warn!("Failed to establish AAP connection to {addr}: {e}");
+               airpods.record_error(&format!("Failed to establish AAP connection: {e}"));
                Some(e)
             },
             Err(_) => {
                warn!("AAP connection to {addr} timed out");
+               airpods.record_error("AAP connection timed out");
                Some(AirPodsError::RequestTimeout)
             },
             Ok(Ok(jhandle)) => {
@@ -808,6 +1285,7 @@ impl ManagerActor {
 
                if let Some(err) = &err {
                   warn!("AAP connection to {addr} terminated: {err:?}");
+                  airpods.record_error(&format!("AAP connection terminated: {err}"));
                } else {
                   info!("AAP connection to {addr} closed cleanly");
                }
@@ -828,6 +1306,7 @@ impl ManagerActor {
       // Mark as connecting only after spawn succeeds
       self.aap_connecting.insert(addr);
       device.aap_state = AAPState::Connecting;
+      device.update_connection_state(&self.event_tx);
 
       Ok(())
    }
@@ -845,11 +1324,13 @@ impl ManagerActor {
 
       device.aap_state = AAPState::Disconnected;
       device.device.disconnect().await;
+      device.update_connection_state(&self.event_tx);
 
       self.aap_connecting.remove(&addr);
-      self
-         .event_tx
-         .emit(&device.device, AirPodsEvent::DeviceDisconnected);
+      self.event_tx.emit(
+         &device.device,
+         AirPodsEvent::DeviceDisconnected(DisconnectReason::User),
+      );
 
       Ok(())
    }
@@ -858,8 +1339,15 @@ impl ManagerActor {
       use tokio::time::timeout;
       info!("Cleaning up Bluetooth manager");
 
+      if let Some(handle) = self.volume_watch_handle.take() {
+         handle.abort();
+      }
+
       // Abort adapter monitors with timeout
       for info in self.adapters.values_mut() {
+         if let Some(handle) = info.case_watch_handle.take() {
+            handle.abort();
+         }
          if let Some(handle) = info.monitor_handle.take() {
             handle.abort();
             // Give it a moment to finish
@@ -926,6 +1414,20 @@ impl ManagerActor {
       }
    }
 
+   /// Reads the device's current advertisement from `BlueZ`, falling back
+   /// to the last one cached.
+   async fn refresh_advertisement(&mut self, addr: Address) -> Option<Vec<u8>> {
+      for adapter_info in self.adapters.values() {
+         if let Ok(device) = adapter_info.adapter.device(addr)
+            && let Some(data) = airpods::recognition::apple_manufacturer_data(&device).await
+         {
+            self.advertisements.insert(addr, data);
+            break;
+         }
+      }
+      self.advertisements.get(&addr).cloned()
+   }
+
    fn has_aap_connection(&self, addr: Address) -> bool {
       self
          .devices
@@ -935,7 +1437,7 @@ impl ManagerActor {
 
    fn tick_all_devices(&self) {
       for device in self.devices.values() {
-         device.device.tick();
+         device.device.tick(&self.event_tx);
       }
    }
 
@@ -945,6 +1447,9 @@ impl ManagerActor {
             && let Ok(bluer_device) = adapter_info.adapter.device(*addr)
          {
             let is_connected = bluer_device.is_connected().await.unwrap_or(false);
+            device
+               .device
+               .update_rssi(bluer_device.rssi().await.ok().flatten());
 
             match (device.bluetooth_state, is_connected) {
                (BluetoothState::Connected, false) => {
@@ -966,6 +1471,95 @@ impl ManagerActor {
    }
 }
 
+/// LE discovery filter shared by scans and the case lid watch.
+///
+/// Discovery filters apply to the whole adapter for this client, so both
+/// must set the same one or the later would override the other.
+fn le_discovery_filter() -> DiscoveryFilter {
+   DiscoveryFilter {
+      transport: DiscoveryTransport::Le,
+      duplicate_data: true,
+      ..Default::default()
+   }
+}
+
+/// Addresses listed in `known_devices`.
+fn known_addresses(config: &Config) -> HashSet<Address> {
+   config
+      .known_devices
+      .iter()
+      .filter_map(|device| device.address.parse().ok())
+      .collect()
+}
+
+/// Opens a `BlueZ` session, retrying while the stack or adapter is still
+/// initializing at boot.
+///
+/// If no adapter shows up within the attempts, the manager starts anyway and
+/// picks adapters up as they appear.
+async fn acquire_session(retries: u32) -> Result<Session> {
+   let attempts = retries.saturating_add(1);
+   let mut attempt = 1;
+   loop {
+      let error = match Session::new().await {
+         Ok(session) => match session.adapter_names().await {
+            Ok(names) if !names.is_empty() => {
+               debug!("Found Bluetooth adapter(s) {names:?} on attempt {attempt}/{attempts}");
+               return Ok(session);
+            },
+            Ok(_) if attempt >= attempts => {
+               warn!("No Bluetooth adapter found, waiting for one to appear");
+               return Ok(session);
+            },
+            Ok(_) => AirPodsError::AdapterNotFound,
+            Err(e) => e.into(),
+         },
+         Err(e) if attempt >= attempts => return Err(e.into()),
+         Err(e) => e.into(),
+      };
+      info!(
+         "Bluetooth not ready (attempt {attempt}/{attempts}): {error}, retrying in {ADAPTER_SETTLE_DELAY:?}"
+      );
+      time::sleep(ADAPTER_SETTLE_DELAY).await;
+      attempt += 1;
+   }
+}
+
+/// Re-applies the settings last chosen for a device after it connects.
+async fn restore_settings(device: AirPods, settings: DeviceSettings) {
+   let addr = device.address();
+   if time::timeout(RESTORE_SETTINGS_TIMEOUT, device.wait_ready())
+      .await
+      .is_err()
+   {
+      debug!("{addr}: No state reported yet, restoring settings anyway");
+   }
+
+   let mut restored = Vec::new();
+   if let Some(mode) = &settings.noise_mode {
+      match mode.parse::<NoiseControlMode>() {
+         Ok(mode) => match device.set_noise_control(mode).await {
+            Ok(applied) => restored.push(format!("noise_mode={applied}")),
+            Err(e) => warn!("{addr}: Failed to restore noise mode {mode}: {e}"),
+         },
+         Err(_) => warn!("{addr}: Ignoring invalid saved noise mode {mode:?}"),
+      }
+   }
+   for (feature, &enabled) in &settings.features {
+      match feature.parse::<FeatureId>() {
+         Ok(feature) => match device.set_feature(feature, enabled).await {
+            Ok(()) => restored.push(format!("{feature}={enabled}")),
+            Err(e) => warn!("{addr}: Failed to restore feature {feature}: {e}"),
+         },
+         Err(_) => warn!("{addr}: Ignoring invalid saved feature {feature:?}"),
+      }
+   }
+
+   if !restored.is_empty() {
+      info!("{addr}: Restored settings: {}", restored.join(", "));
+   }
+}
+
 fn calc_retry_delay(retry_count: u32) -> Duration {
    let base_delay = Duration::from_secs(2);
    let exponential = base_delay * (1 << retry_count.min(4));
@@ -973,3 +1567,14 @@ fn calc_retry_delay(retry_count: u32) -> Duration {
    let jitter = rand::thread_rng().gen_range(0..1000);
    delay + Duration::from_millis(jitter)
 }
+
+/// Orders devices so listings stay stable between calls.
+fn sort_devices(devices: &mut [AirPods], key: DeviceSort) {
+   match key {
+      DeviceSort::Address => devices.sort_by_key(AirPods::address),
+      DeviceSort::Name => devices.sort_by_cached_key(|d| (d.name().to_lowercase(), d.address())),
+      DeviceSort::Connection => {
+         devices.sort_by_cached_key(|d| (!d.is_connected(), d.name().to_lowercase(), d.address()))
+      },
+   }
+}
@@ -1,7 +1,10 @@
 //! Bluetooth communication layer for `AirPods`.
 //!
 //! This module provides Bluetooth connectivity including L2CAP socket
-//! management and device discovery/connection handling.
+//! management, device discovery/connection handling, ATT attribute access
+//! and media transport access.
 
+pub mod att;
 pub mod l2cap;
 pub mod manager;
+pub mod transport;
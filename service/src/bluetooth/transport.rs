@@ -0,0 +1,185 @@
+//! A2DP media transport access for `AirPods` volume.
+//!
+//! AAP carries no volume command: stem swipes adjust the AVRCP absolute
+//! volume, which `BlueZ` exposes on `org.bluez.MediaTransport1` as 0-127.
+//! Calls share one system bus connection, and changes are watched through
+//! `PropertiesChanged` rather than polled.
+
+use std::collections::HashMap;
+
+use bluer::Address;
+use futures::StreamExt;
+use log::debug;
+use tokio::{
+   select,
+   sync::{OnceCell, mpsc},
+};
+use zbus::{
+   Connection, MatchRule, MessageStream,
+   fdo::ObjectManagerProxy,
+   message,
+   zvariant::{OwnedObjectPath, OwnedValue, Value},
+};
+
+use crate::error::{AirPodsError, Result};
+
+const BLUEZ_SERVICE: &str = "org.bluez";
+const TRANSPORT_INTERFACE: &str = "org.bluez.MediaTransport1";
+/// Maximum AVRCP absolute volume
+const AVRCP_MAX_VOLUME: u16 = 127;
+
+static SYSTEM_BUS: OnceCell<Connection> = OnceCell::const_new();
+
+/// Gets the system bus connection, opening it on first use.
+async fn system_bus() -> Result<&'static Connection> {
+   Ok(SYSTEM_BUS.get_or_try_init(Connection::system).await?)
+}
+
+async fn object_manager(connection: &Connection) -> Result<ObjectManagerProxy<'static>> {
+   Ok(ObjectManagerProxy::builder(connection)
+      .destination(BLUEZ_SERVICE)?
+      .path("/")?
+      .build()
+      .await?)
+}
+
+/// Finds the media transport of the device, if it is streaming audio.
+async fn find_transport(
+   connection: &Connection,
+   address: Address,
+) -> Result<Option<OwnedObjectPath>> {
+   let device_segment = format!("/dev_{}/", address.to_string().replace(':', "_"));
+   let objects = object_manager(connection)
+      .await?
+      .get_managed_objects()
+      .await?;
+   Ok(objects.into_iter().find_map(|(path, interfaces)| {
+      let is_transport = interfaces
+         .keys()
+         .any(|iface| iface.as_str() == TRANSPORT_INTERFACE);
+      (is_transport && path.as_str().contains(&device_segment)).then_some(path)
+   }))
+}
+
+/// Converts an AVRCP volume to percent.
+fn to_percent(value: &Value<'_>) -> Option<u8> {
+   let raw = u16::try_from(value).ok()?;
+   Some((u32::from(raw.min(AVRCP_MAX_VOLUME)) * 100 / u32::from(AVRCP_MAX_VOLUME)) as u8)
+}
+
+/// Gets the device a transport object path belongs to, e.g.
+/// `/org/bluez/hci0/dev_AA_BB_CC_DD_EE_FF/sep1/fd0`.
+fn transport_address(path: &str) -> Option<Address> {
+   let segment = path.split('/').find_map(|seg| seg.strip_prefix("dev_"))?;
+   segment.replace('_', ":").parse().ok()
+}
+
+/// Watches the `Volume` of every media transport and sends the device's
+/// volume in percent whenever a transport appears or its volume changes,
+/// until the receiver is dropped.
+pub async fn watch_volume(tx: mpsc::Sender<(Address, u8)>) -> Result<()> {
+   let connection = system_bus().await?;
+   let rule = MatchRule::builder()
+      .msg_type(message::Type::Signal)
+      .interface("org.freedesktop.DBus.Properties")?
+      .member("PropertiesChanged")?
+      .path_namespace("/org/bluez")?
+      .arg(0, TRANSPORT_INTERFACE)?
+      .build();
+   let mut changes = MessageStream::for_match_rule(rule, connection, None).await?;
+   let manager = object_manager(connection).await?;
+   let mut added = manager.receive_interfaces_added().await?;
+
+   // Transports already streaming when the watch starts
+   for (path, interfaces) in manager.get_managed_objects().await? {
+      let volume = interfaces
+         .iter()
+         .find(|(iface, _)| iface.as_str() == TRANSPORT_INTERFACE)
+         .and_then(|(_, props)| props.get("Volume"))
+         .and_then(|value| to_percent(value));
+      if let (Some(address), Some(volume)) = (transport_address(path.as_str()), volume)
+         && tx.send((address, volume)).await.is_err()
+      {
+         return Ok(());
+      }
+   }
+
+   loop {
+      let (path, volume) = select! {
+         Some(message) = changes.next() => {
+            let Ok(message) = message else {
+               continue;
+            };
+            let Some(path) = message.header().path().map(ToString::to_string) else {
+               continue;
+            };
+            let Ok((_, changed, _)) = message
+               .body()
+               .deserialize::<(String, HashMap<String, OwnedValue>, Vec<String>)>()
+            else {
+               continue;
+            };
+            (path, changed.get("Volume").and_then(|value| to_percent(value)))
+         },
+         Some(signal) = added.next() => {
+            let Ok(args) = signal.args() else {
+               continue;
+            };
+            let volume = args
+               .interfaces_and_properties()
+               .iter()
+               .find(|(iface, _)| iface.as_str() == TRANSPORT_INTERFACE)
+               .and_then(|(_, props)| props.get("Volume"))
+               .and_then(to_percent);
+            (args.object_path().to_string(), volume)
+         },
+         else => break,
+      };
+      let (Some(address), Some(volume)) = (transport_address(&path), volume) else {
+         continue;
+      };
+      debug!("{address}: Transport volume is {volume}%");
+      if tx.send((address, volume)).await.is_err() {
+         break;
+      }
+   }
+   Ok(())
+}
+
+/// Sets the volume of the device in percent (0-100).
+pub async fn set_volume(address: Address, percent: u8) -> Result<()> {
+   let connection = system_bus().await?;
+   let Some(path) = find_transport(connection, address).await? else {
+      return Err(AirPodsError::FeatureNotSupported(
+         "volume control without an active audio stream".to_string(),
+      ));
+   };
+
+   let raw = (u32::from(percent.min(100)) * u32::from(AVRCP_MAX_VOLUME)).div_ceil(100) as u16;
+   connection
+      .call_method(
+         Some(BLUEZ_SERVICE),
+         &path,
+         Some("org.freedesktop.DBus.Properties"),
+         "Set",
+         &(TRANSPORT_INTERFACE, "Volume", Value::U16(raw)),
+      )
+      .await?;
+   Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn transport_paths_map_to_devices() {
+      assert_eq!(
+         transport_address("/org/bluez/hci0/dev_AA_BB_CC_DD_EE_FF/sep1/fd0"),
+         Some("AA:BB:CC:DD:EE:FF".parse().unwrap())
+      );
+      assert_eq!(transport_address("/org/bluez/hci0"), None);
+      assert_eq!(to_percent(&Value::U16(127)), Some(100));
+      assert_eq!(to_percent(&Value::U16(64)), Some(50));
+   }
+}
@@ -3,12 +3,20 @@
 //! This module handles loading and saving configuration from disk,
 //! including known devices and connection parameters.
 
-use std::{env, fs, path::PathBuf};
+use std::{env, fs, net::SocketAddr, path::PathBuf};
 
+use log::warn;
 use serde::{Deserialize, Serialize};
 use smol_str::SmolStr;
 
-use crate::error::{AirPodsError, Result};
+use crate::{
+   error::{AirPodsError, Result},
+   event::EventKind,
+   media_control::{self, MediaTarget, PauseOn, ResumeMode},
+   notifications::NotificationEvent,
+   quiet_hours::QuietHours,
+   signal_trend, smoothing,
+};
 
 /// Main configuration structure for the service.
 #[derive(Serialize, Deserialize, Clone)]
@@ -16,20 +24,177 @@ pub struct Config {
    #[serde(default)]
    pub known_devices: Vec<KnownDevice>,
 
+   #[serde(default)]
+   pub known_devices_only: bool,
+
+   #[serde(default = "default_max_tracked_devices")]
+   pub max_tracked_devices: usize,
+
    #[serde(default = "default_poll_interval")]
    pub poll_interval: u64,
 
+   #[serde(default)]
+   pub startup_delay_ms: u64,
+
+   #[serde(default = "default_adapter_retry_count")]
+   pub adapter_retry_count: u32,
+
    #[serde(default = "default_retry_count")]
    pub connection_retry_count: u32,
 
    #[serde(default = "default_reconnect_delay")]
    pub reconnect_delay_sec: u64,
 
+   #[serde(default)]
+   pub max_reconnect_attempts: u32,
+
    #[serde(default = "default_notification_retries")]
    pub notification_retries: u32,
 
    #[serde(default)]
    pub log_filter: Option<SmolStr>,
+
+   #[serde(default)]
+   pub wait_for_bus_name: bool,
+
+   #[serde(default)]
+   pub replace_running_instance: bool,
+
+   #[serde(default)]
+   pub socket_path: Option<PathBuf>,
+
+   #[serde(default)]
+   pub metrics_addr: Option<SocketAddr>,
+
+   #[serde(default)]
+   pub emit_raw_events: bool,
+
+   #[serde(default)]
+   pub emit_spatial_events: bool,
+
+   #[serde(default)]
+   pub signal_filter: Option<Vec<EventKind>>,
+
+   #[serde(default)]
+   pub case_open_duck: bool,
+
+   #[serde(default = "default_case_open_duck_ms")]
+   pub case_open_duck_ms: u64,
+
+   #[serde(default = "default_disconnect_pause_grace")]
+   pub disconnect_pause_grace_sec: u64,
+
+   #[serde(default)]
+   pub tiered_ear_policy: bool,
+
+   #[serde(default = "default_one_out_duck_factor")]
+   pub one_out_duck_factor: f64,
+
+   #[serde(default = "default_true")]
+   pub resume_on_reinsert: bool,
+
+   #[serde(default)]
+   pub media_target: MediaTarget,
+
+   #[serde(default)]
+   pub resume_mode: ResumeMode,
+
+   #[serde(default)]
+   pub media_allowed_players: Vec<String>,
+
+   #[serde(default)]
+   pub single_bud_mode: bool,
+
+   #[serde(default)]
+   pub ignore_held: bool,
+
+   #[serde(default)]
+   pub pause_only_when_worn: bool,
+
+   #[serde(default)]
+   pub pause_on: PauseOn,
+
+   #[serde(default)]
+   pub skip_idle_players: bool,
+
+   #[serde(default)]
+   pub media_control_device: String,
+
+   #[serde(default)]
+   pub suppress_during_calls: bool,
+
+   #[serde(default)]
+   pub noise_control_follows_media: bool,
+
+   #[serde(default = "default_playing_noise_mode")]
+   pub playing_noise_mode: SmolStr,
+
+   #[serde(default = "default_paused_noise_mode")]
+   pub paused_noise_mode: SmolStr,
+
+   #[serde(default = "default_call_apps")]
+   pub call_apps: Vec<String>,
+
+   #[serde(default)]
+   pub restore_settings_on_connect: bool,
+
+   #[serde(default = "default_battery_history_len")]
+   pub battery_history_len: usize,
+
+   #[serde(default = "default_devices_changed_interval")]
+   pub devices_changed_interval_ms: u64,
+
+   #[serde(default = "default_startup_quiet_period")]
+   pub startup_quiet_period_ms: u64,
+
+   #[serde(default = "default_command_retry_count")]
+   pub command_retry_count: u32,
+
+   #[serde(default = "default_command_retry_delay")]
+   pub command_retry_delay_ms: u64,
+
+   #[serde(default)]
+   pub desktop_notifications: bool,
+
+   #[serde(default = "default_notification_events")]
+   pub notification_events: Vec<NotificationEvent>,
+
+   #[serde(default = "default_low_battery_level")]
+   pub low_battery_level: u8,
+
+   #[serde(default = "default_notification_interval")]
+   pub notification_interval_sec: u64,
+
+   #[serde(default)]
+   pub quiet_hours: Option<QuietHours>,
+
+   #[serde(default)]
+   pub device_sort: DeviceSort,
+
+   #[serde(default = "default_rssi_smoothing_alpha")]
+   pub rssi_smoothing_alpha: f64,
+
+   #[serde(default)]
+   pub battery_smoothing_alpha: f64,
+
+   #[serde(default = "default_signal_trend_threshold")]
+   pub signal_trend_threshold: f64,
+
+   #[serde(default = "default_event_rate_limit_ms")]
+   pub event_rate_limit_ms: u64,
+}
+
+/// Order of devices in device listings.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceSort {
+   /// By Bluetooth address
+   #[default]
+   Address,
+   /// By name, case-insensitively
+   Name,
+   /// Connected devices first, then by name
+   Connection,
 }
 
 /// Represents a known `AirPods` device.
@@ -47,6 +212,10 @@ const fn default_retry_count() -> u32 {
    10
 }
 
+const fn default_adapter_retry_count() -> u32 {
+   5
+}
+
 const fn default_notification_retries() -> u32 {
    3
 }
@@ -55,22 +224,151 @@ const fn default_reconnect_delay() -> u64 {
    10
 }
 
+const fn default_disconnect_pause_grace() -> u64 {
+   5
+}
+
+const fn default_max_tracked_devices() -> usize {
+   16
+}
+
+const fn default_battery_history_len() -> usize {
+   32
+}
+
+const fn default_devices_changed_interval() -> u64 {
+   250
+}
+
+const fn default_case_open_duck_ms() -> u64 {
+   1500
+}
+
+const fn default_startup_quiet_period() -> u64 {
+   2000
+}
+
+const fn default_command_retry_count() -> u32 {
+   2
+}
+
+const fn default_command_retry_delay() -> u64 {
+   50
+}
+
+fn default_playing_noise_mode() -> SmolStr {
+   SmolStr::new_static("anc")
+}
+
+fn default_paused_noise_mode() -> SmolStr {
+   SmolStr::new_static("transparency")
+}
+
+fn default_call_apps() -> Vec<String> {
+   ["zoom", "teams", "skype", "webex", "jitsi"]
+      .map(String::from)
+      .to_vec()
+}
+
+fn default_notification_events() -> Vec<NotificationEvent> {
+   NotificationEvent::ALL.to_vec()
+}
+
+const fn default_low_battery_level() -> u8 {
+   20
+}
+
+const fn default_notification_interval() -> u64 {
+   60
+}
+
+const fn default_one_out_duck_factor() -> f64 {
+   media_control::DEFAULT_ONE_OUT_DUCK_FACTOR
+}
+
+const fn default_rssi_smoothing_alpha() -> f64 {
+   smoothing::DEFAULT_RSSI_ALPHA
+}
+
+const fn default_signal_trend_threshold() -> f64 {
+   signal_trend::DEFAULT_THRESHOLD
+}
+
+const fn default_event_rate_limit_ms() -> u64 {
+   100
+}
+
+const fn default_true() -> bool {
+   true
+}
+
 impl Default for Config {
    fn default() -> Self {
       Self {
          known_devices: vec![],
+         known_devices_only: false,
+         max_tracked_devices: default_max_tracked_devices(),
          poll_interval: default_poll_interval(),
+         startup_delay_ms: 0,
+         adapter_retry_count: default_adapter_retry_count(),
          connection_retry_count: default_retry_count(),
          reconnect_delay_sec: default_reconnect_delay(),
+         max_reconnect_attempts: 0,
          notification_retries: default_notification_retries(),
          log_filter: None,
+         wait_for_bus_name: false,
+         replace_running_instance: false,
+         socket_path: None,
+         metrics_addr: None,
+         emit_raw_events: false,
+         emit_spatial_events: false,
+         signal_filter: None,
+         case_open_duck: false,
+         case_open_duck_ms: default_case_open_duck_ms(),
+         disconnect_pause_grace_sec: default_disconnect_pause_grace(),
+         tiered_ear_policy: false,
+         one_out_duck_factor: default_one_out_duck_factor(),
+         resume_on_reinsert: true,
+         media_target: MediaTarget::default(),
+         resume_mode: ResumeMode::default(),
+         media_allowed_players: vec![],
+         single_bud_mode: false,
+         ignore_held: false,
+         pause_only_when_worn: false,
+         pause_on: PauseOn::default(),
+         skip_idle_players: false,
+         media_control_device: String::new(),
+         suppress_during_calls: false,
+         noise_control_follows_media: false,
+         playing_noise_mode: default_playing_noise_mode(),
+         paused_noise_mode: default_paused_noise_mode(),
+         call_apps: default_call_apps(),
+         restore_settings_on_connect: false,
+         battery_history_len: default_battery_history_len(),
+         devices_changed_interval_ms: default_devices_changed_interval(),
+         startup_quiet_period_ms: default_startup_quiet_period(),
+         command_retry_count: default_command_retry_count(),
+         command_retry_delay_ms: default_command_retry_delay(),
+         desktop_notifications: false,
+         notification_events: default_notification_events(),
+         low_battery_level: default_low_battery_level(),
+         notification_interval_sec: default_notification_interval(),
+         quiet_hours: None,
+         device_sort: DeviceSort::default(),
+         rssi_smoothing_alpha: default_rssi_smoothing_alpha(),
+         battery_smoothing_alpha: 0.0,
+         signal_trend_threshold: default_signal_trend_threshold(),
+         event_rate_limit_ms: default_event_rate_limit_ms(),
       }
    }
 }
 
 impl Config {
    /// Loads configuration from disk or creates default if not exists.
-   pub fn load() -> Result<Self> {
+   ///
+   /// Out-of-range values are left to [`Self::validate`], which the caller
+   /// runs once logging is set up.
+   pub fn load_unvalidated() -> Result<Self> {
       let config_path = Self::config_path()?;
 
       if config_path.exists() {
@@ -99,6 +397,50 @@ impl Config {
       Ok(())
    }
 
+   /// Changes the configuration on disk, e.g. to persist a runtime setting.
+   ///
+   /// Updates are serialised so overlapping ones don't lose a change. The
+   /// file is saved as read, without [`Self::validate`], so no value the user
+   /// wrote is replaced by its default.
+   pub fn update(change: impl FnOnce(&mut Self)) -> Result<()> {
+      static WRITE_LOCK: parking_lot::Mutex<()> = parking_lot::Mutex::new(());
+
+      let _guard = WRITE_LOCK.lock();
+      let mut config = Self::load_unvalidated()?;
+      change(&mut config);
+      config.save()
+   }
+
+   /// Resets values that parse but are out of range to their defaults, so
+   /// one bad value doesn't take the rest of the file with it.
+   pub fn validate(&mut self) {
+      fn check(value: &mut f64, default: f64, validate: impl FnOnce(f64) -> Result<f64, String>) {
+         if let Err(e) = validate(*value) {
+            warn!("Ignoring invalid configuration: {e}, using {default}");
+            *value = default;
+         }
+      }
+
+      check(
+         &mut self.rssi_smoothing_alpha,
+         default_rssi_smoothing_alpha(),
+         smoothing::validate_rssi_alpha,
+      );
+      check(&mut self.battery_smoothing_alpha, 0.0, |alpha| {
+         smoothing::validate_alpha("battery_smoothing_alpha", alpha)
+      });
+      check(
+         &mut self.one_out_duck_factor,
+         default_one_out_duck_factor(),
+         media_control::validate_one_out_duck_factor,
+      );
+      check(
+         &mut self.signal_trend_threshold,
+         default_signal_trend_threshold(),
+         signal_trend::validate_threshold,
+      );
+   }
+
    fn config_path() -> Result<PathBuf> {
       // Check for override environment variable first
       if let Ok(path) = env::var("AIRPODS_CONFIG_PATH") {
@@ -121,3 +463,21 @@ impl Config {
          .map(|d| d.name.as_str())
    }
 }
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn out_of_range_values_fall_back_to_defaults() {
+      let mut config: Config = toml::from_str(
+         "rssi_smoothing_alpha = 0.0\none_out_duck_factor = 2.0\nbattery_smoothing_alpha = 0.5",
+      )
+      .unwrap();
+      config.validate();
+      assert_eq!(config.rssi_smoothing_alpha, default_rssi_smoothing_alpha());
+      assert_eq!(config.one_out_duck_factor, default_one_out_duck_factor());
+      // Valid values are kept
+      assert_eq!(config.battery_smoothing_alpha, 0.5);
+   }
+}
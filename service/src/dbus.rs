@@ -1,68 +1,115 @@
-use std::{collections::HashMap, fmt, str::FromStr};
+use std::{collections::HashMap, fmt, str::FromStr, sync::Arc, time::Duration};
 
 use bluer::Address;
-use log::info;
-use zbus::{fdo, interface, object_server::SignalEmitter, zvariant};
+use log::{info, warn};
+use serde::Serialize;
+use serde_json::json;
+use tokio::{sync::Notify, time};
+use zbus::{
+   Connection, fdo, interface, message::Header, names::BusName, object_server::SignalEmitter,
+   zvariant,
+};
 
 use crate::{
-   airpods::protocol::{FeatureId, NoiseControlMode},
+   airpods::{
+      device::AirPods,
+      parser,
+      protocol::{
+         BatteryInfo, BatteryState, Component, EarDetectionSensitivity, FeatureId, HoldDuration,
+         NoiseControlMode, PressSpeed, TapAction,
+      },
+      recognition::decode_proximity_pairing,
+   },
+   battery_study::BatteryStudy,
    bluetooth::manager::BluetoothManager,
-   media_control,
+   config::Config,
+   device_state::DeviceSettings,
+   error::AirPodsError,
+   logging, media_control,
+   quiet_hours::{self, QuietHours},
+   smoothing,
 };
 
 pub struct AirPodsService {
    bluetooth_manager: BluetoothManager,
+   battery_study: Option<BatteryStudy>,
+   /// Notified to shut the service down
+   quit: Arc<Notify>,
 }
 
 impl AirPodsService {
-   pub const fn new(bluetooth_manager: BluetoothManager) -> Self {
-      Self { bluetooth_manager }
+   pub const fn new(
+      bluetooth_manager: BluetoothManager,
+      battery_study: Option<BatteryStudy>,
+      quit: Arc<Notify>,
+   ) -> Self {
+      Self {
+         bluetooth_manager,
+         battery_study,
+         quit,
+      }
    }
-}
 
-fn to_arg_error<T: fmt::Display>(e: T) -> fdo::Error {
-   fdo::Error::InvalidArgs(e.to_string())
-}
+   /// Starts a graceful shutdown, as on Ctrl-C.
+   pub(crate) fn request_quit(&self) -> bool {
+      info!("Shutdown requested");
+      self.quit.notify_one();
+      true
+   }
 
-#[interface(name = "org.kairpods.manager")]
-impl AirPodsService {
-   async fn get_devices(&self) -> fdo::Result<String> {
-      let states: Vec<serde_json::Value> = self
-         .bluetooth_manager
-         .all_devices()
-         .await
-         .into_iter()
-         .map(|d| d.to_json())
-         .collect();
-      Ok(serde_json::to_string(&states).unwrap())
+   /// Records a setting applied to a device so it can be restored on
+   /// reconnect.
+   async fn remember_setting(&self, address: Address, update: impl FnOnce(&mut DeviceSettings)) {
+      let device_state = self.bluetooth_manager.device_state();
+      if let Err(e) = device_state.remember_device_setting(address, update).await {
+         warn!("Failed to save settings for {address}: {e}");
+      }
    }
 
-   async fn get_device(&self, address: String) -> fdo::Result<String> {
-      let addr = Address::from_str(&address).map_err(to_arg_error)?;
-      let dev = self.bluetooth_manager.get_device(addr).await?;
-      Ok(dev.to_json().to_string())
+   /// Emits `DevicesChanged` when an emitter is available.
+   async fn notify_devices_changed(&self, emitter: Option<&SignalEmitter<'_>>) -> zbus::Result<()> {
+      match emitter {
+         Some(emitter) => self.emit_devices_changed(emitter).await,
+         None => Ok(()),
+      }
    }
 
-   async fn passthrough(&self, address: String, packet: String) -> fdo::Result<bool> {
-      let addr = Address::from_str(&address).map_err(to_arg_error)?;
-      let dev = self.bluetooth_manager.get_device(addr).await?;
-      let packet = hex::decode(packet).map_err(to_arg_error)?;
-      dev.passthrough(&packet).await?;
-      Ok(true)
+   /// Emits the change of both device list properties.
+   pub(crate) async fn emit_devices_changed(
+      &self,
+      emitter: &SignalEmitter<'_>,
+   ) -> zbus::Result<()> {
+      self.devices_changed(emitter).await?;
+      self.devices_struct_changed(emitter).await
    }
 
-   async fn send_command(
+   pub(crate) async fn update_auto_play_pause(
+      &self,
+      enabled: bool,
+      emitter: Option<&SignalEmitter<'_>>,
+   ) -> zbus::Result<()> {
+      media_control::set_enabled(enabled);
+      info!("Auto play/pause set to {enabled}");
+      match emitter {
+         Some(emitter) => self.auto_play_pause_changed(emitter).await,
+         None => Ok(()),
+      }
+   }
+
+   /// Runs a `SendCommand` action, returning the value applied to the
+   /// device; signals are only emitted with an emitter.
+   pub(crate) async fn run_command(
       &self,
       address: String,
-      action: String,
-      params: HashMap<String, zvariant::Value<'_>>,
-      #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
-   ) -> fdo::Result<bool> {
+      action: &str,
+      params: &HashMap<String, zvariant::Value<'_>>,
+      emitter: Option<&SignalEmitter<'_>>,
+   ) -> fdo::Result<String> {
       let addr = Address::from_str(&address).map_err(to_arg_error)?;
 
       let dev = self.bluetooth_manager.get_device(addr).await?;
 
-      match action.as_str() {
+      let applied = match action {
          "set_noise_mode" => {
             let mode_str = params
                .get("value")
@@ -74,12 +121,56 @@ impl AirPodsService {
                .parse()
                .map_err(|_| to_arg_error(format_args!("Invalid noise mode: {mode_str:?}")))?;
 
-            dev.set_noise_control(mode).await?;
+            let mode = dev.set_noise_control(mode).await?;
 
             info!("Set noise mode to {mode} for {address}");
+            self
+               .remember_setting(addr, |settings| {
+                  settings.noise_mode = Some(mode.to_str().into());
+               })
+               .await;
 
             // Emit property change immediately so UI updates
-            self.devices_changed(&emitter).await?;
+            self.notify_devices_changed(emitter).await?;
+            mode.to_string()
+         },
+
+         "toggle_noise_control" => {
+            let mode_param = |key: &str, default: NoiseControlMode| {
+               let Some(value) = params.get(key) else {
+                  return Ok(default);
+               };
+               let mode_str = value
+                  .downcast_ref::<String>()
+                  .map_err(|e| to_arg_error(format_args!("Invalid '{key}' parameter: {e}")))?;
+               mode_str
+                  .parse::<NoiseControlMode>()
+                  .map_err(|_| to_arg_error(format_args!("Invalid noise mode: {mode_str:?}")))
+            };
+            let mode_a = mode_param("mode_a", NoiseControlMode::Active)?;
+            let mode_b = mode_param("mode_b", NoiseControlMode::Transparency)?;
+
+            // Anything other than mode_a (including unknown) switches to mode_a
+            let mode = if dev.noise_mode() == Some(mode_a) {
+               mode_b
+            } else {
+               mode_a
+            };
+            let mode = dev.set_noise_control(mode).await?;
+
+            info!("Toggled noise mode to {mode} for {address}");
+            self
+               .remember_setting(addr, |settings| {
+                  settings.noise_mode = Some(mode.to_str().into());
+               })
+               .await;
+
+            // Report the resulting mode and update the UI
+            if let Some(emitter) = emitter {
+               Self::noise_control_changed(emitter, &address, mode.to_str()).await?;
+            }
+            self.notify_devices_changed(emitter).await?;
+            mode.to_string()
          },
 
          "set_feature" => {
@@ -89,9 +180,7 @@ impl AirPodsService {
                .downcast_ref::<String>()
                .map_err(|e| to_arg_error(format_args!("Invalid 'feature' parameter: {e}")))?;
 
-            let feature: FeatureId = feature_str
-               .parse()
-               .map_err(|_| to_arg_error(format_args!("Invalid feature: {feature_str:?}")))?;
+            let feature = parse_on_off_feature(&feature_str).map_err(to_arg_error)?;
 
             let enabled = params
                .get("enabled")
@@ -105,48 +194,898 @@ impl AirPodsService {
 
             dev.set_feature(feature, enabled).await?;
             info!("Set feature {feature} to {enabled} for {address}");
+            self
+               .remember_setting(addr, |settings| {
+                  settings.features.insert(feature.to_str().into(), enabled);
+               })
+               .await;
+
+            // Emit property change immediately so UI updates
+            self.notify_devices_changed(emitter).await?;
+            enabled.to_string()
+         },
+
+         "set_tap_action" => {
+            let taps = params
+               .get("taps")
+               .ok_or_else(|| to_arg_error("Missing 'taps' parameter"))?
+               .downcast_ref::<u8>()
+               .map_err(|e| to_arg_error(format_args!("Invalid 'taps' parameter: {e}")))?;
+
+            let action_str = params
+               .get("action")
+               .ok_or_else(|| to_arg_error("Missing 'action' parameter"))?
+               .downcast_ref::<String>()
+               .map_err(|e| to_arg_error(format_args!("Invalid 'action' parameter: {e}")))?;
+
+            let tap_action: TapAction = action_str
+               .parse()
+               .map_err(|_| to_arg_error(format_args!("Invalid tap action: {action_str:?}")))?;
+
+            if !(1..=3).contains(&taps) {
+               return Err(to_arg_error(format_args!(
+                  "Invalid tap count: {taps} (must be 1-3)"
+               )));
+            }
+
+            dev.set_tap_action(taps, tap_action).await?;
+            info!("Set {taps}-tap action to {tap_action} for {address}");
 
             // Emit property change immediately so UI updates
-            self.devices_changed(&emitter).await?;
+            self.notify_devices_changed(emitter).await?;
+            tap_action.to_string()
+         },
+
+         "set_press_speed" => {
+            let speed_str = params
+               .get("speed")
+               .ok_or_else(|| to_arg_error("Missing 'speed' parameter"))?
+               .downcast_ref::<String>()
+               .map_err(|e| to_arg_error(format_args!("Invalid 'speed' parameter: {e}")))?;
+            let speed: PressSpeed = speed_str.parse().map_err(|_| {
+               to_arg_error(format_args!(
+                  "Invalid press speed: {speed_str:?} (must be default, slower or slowest)"
+               ))
+            })?;
+
+            dev.set_press_speed(speed).await?;
+            info!("Set press speed to {speed} for {address}");
+
+            // Emit property change immediately so UI updates
+            self.notify_devices_changed(emitter).await?;
+            speed.to_string()
+         },
+
+         "set_hold_duration" => {
+            let duration_str = params
+               .get("duration")
+               .ok_or_else(|| to_arg_error("Missing 'duration' parameter"))?
+               .downcast_ref::<String>()
+               .map_err(|e| to_arg_error(format_args!("Invalid 'duration' parameter: {e}")))?;
+            let duration: HoldDuration = duration_str.parse().map_err(|_| {
+               to_arg_error(format_args!(
+                  "Invalid hold duration: {duration_str:?} (must be default, shorter or shortest)"
+               ))
+            })?;
+
+            dev.set_hold_duration(duration).await?;
+            info!("Set hold duration to {duration} for {address}");
+
+            // Emit property change immediately so UI updates
+            self.notify_devices_changed(emitter).await?;
+            duration.to_string()
+         },
+
+         "set_loud_sound_reduction" => {
+            let enabled = params
+               .get("enabled")
+               .ok_or_else(|| to_arg_error("Missing 'enabled' parameter"))?
+               .downcast_ref::<bool>()
+               .map_err(|e| to_arg_error(format_args!("Invalid 'enabled' parameter: {e}")))?;
+
+            dev.set_loud_sound_reduction(enabled).await?;
+            info!("Set loud sound reduction to {enabled} for {address}");
+
+            // Emit property change immediately so UI updates
+            self.notify_devices_changed(emitter).await?;
+            enabled.to_string()
+         },
+
+         "set_volume" => {
+            let level = params
+               .get("level")
+               .ok_or_else(|| to_arg_error("Missing 'level' parameter"))?
+               .downcast_ref::<u32>()
+               .map_err(|e| to_arg_error(format_args!("Invalid 'level' parameter: {e}")))?;
+
+            let level = u8::try_from(level)
+               .ok()
+               .filter(|l| *l <= 100)
+               .ok_or_else(|| {
+                  to_arg_error(format_args!("Invalid volume: {level} (must be 0-100)"))
+               })?;
+
+            dev.set_volume(level).await?;
+            info!("Set volume to {level}% for {address}");
+
+            // Emit property change immediately so UI updates
+            self.notify_devices_changed(emitter).await?;
+            level.to_string()
          },
 
          _ => {
             return Err(to_arg_error(format_args!("Unknown action: {action}")));
          },
+      };
+
+      Ok(applied)
+   }
+
+   /// Applies several features at once, emitting a single `devices_changed`.
+   pub(crate) async fn apply_features(
+      &self,
+      address: String,
+      features: HashMap<String, bool>,
+      emitter: Option<&SignalEmitter<'_>>,
+   ) -> fdo::Result<String> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let dev = self.bluetooth_manager.get_device(addr).await?;
+
+      let mut results = serde_json::Map::new();
+      for (feature_str, enabled) in features {
+         let result = match parse_on_off_feature(&feature_str) {
+            Ok(feature) => match dev.set_feature(feature, enabled).await {
+               Ok(()) => Ok(feature),
+               Err(e) => Err(e.to_string()),
+            },
+            Err(e) => Err(e),
+         };
+         let entry = match result {
+            Ok(feature) => {
+               info!("Set feature {feature} to {enabled} for {address}");
+               self
+                  .remember_setting(addr, |settings| {
+                     settings.features.insert(feature.to_str().into(), enabled);
+                  })
+                  .await;
+               json!({ "ok": true })
+            },
+            Err(e) => {
+               warn!("Failed to set feature {feature_str:?} for {address}: {e}");
+               json!({ "ok": false, "error": e })
+            },
+         };
+         results.insert(feature_str, entry);
+      }
+
+      self.notify_devices_changed(emitter).await?;
+      Ok(serde_json::Value::Object(results).to_string())
+   }
+
+   /// Runs a `SendCommand` action on every connected device, returning a
+   /// JSON map of address to outcome. A failure on one device does not stop
+   /// the others.
+   pub(crate) async fn run_broadcast_command(
+      &self,
+      action: String,
+      params: HashMap<String, zvariant::Value<'_>>,
+      emitter: Option<&SignalEmitter<'_>>,
+   ) -> fdo::Result<String> {
+      let mut results = serde_json::Map::new();
+      for dev in self.bluetooth_manager.all_devices().await {
+         if !dev.is_connected() {
+            continue;
+         }
+         let address = dev.address_str().to_string();
+         let entry = match self
+            .run_command(address.clone(), &action, &params, emitter)
+            .await
+         {
+            Ok(applied) => json!({ "ok": true, "result": applied }),
+            Err(e) => {
+               warn!("Failed to run {action} for {address}: {e}");
+               json!({ "ok": false, "error": e.to_string() })
+            },
+         };
+         results.insert(address, entry);
+      }
+      Ok(serde_json::Value::Object(results).to_string())
+   }
+}
+
+/// Typed device entry of the `DevicesStruct` property, for clients that
+/// would rather not parse the JSON of `Devices`.
+///
+/// Unknown values are empty strings; see [`BatteryStruct`] for batteries.
+#[derive(Debug, zvariant::Type, zvariant::Value)]
+pub struct DeviceStruct {
+   address: String,
+   name: String,
+   model: String,
+   connected: bool,
+   connection_state: String,
+   left: BatteryStruct,
+   right: BatteryStruct,
+   case: BatteryStruct,
+   headphone: BatteryStruct,
+   noise_mode: String,
+   left_state: String,
+   right_state: String,
+}
+
+/// Battery of one component; `available` is false when it isn't reported.
+#[derive(Debug, Default, zvariant::Type, zvariant::Value)]
+pub struct BatteryStruct {
+   level: u8,
+   charging: bool,
+   available: bool,
+}
+
+impl From<BatteryState> for BatteryStruct {
+   fn from(state: BatteryState) -> Self {
+      Self {
+         level: state.level,
+         charging: state.is_charging(),
+         available: state.is_available(),
+      }
+   }
+}
+
+impl From<&AirPods> for DeviceStruct {
+   fn from(device: &AirPods) -> Self {
+      let battery = device.battery_info();
+      let component =
+         |pick: fn(BatteryInfo) -> BatteryState| battery.map(pick).unwrap_or_default().into();
+      let ears = device.ear_detection();
+      Self {
+         address: device.address_str().to_string(),
+         name: device.name().to_string(),
+         model: device
+            .model()
+            .map(|m| m.to_str())
+            .unwrap_or_default()
+            .to_string(),
+         connected: device.is_connected(),
+         connection_state: device.connection_state().to_str().to_string(),
+         left: component(|b| b.left),
+         right: component(|b| b.right),
+         case: component(|b| b.case),
+         headphone: component(|b| b.headphone),
+         noise_mode: device
+            .noise_mode()
+            .map(|m| m.to_str())
+            .unwrap_or_default()
+            .to_string(),
+         left_state: ears
+            .map(|e| e.left_state().to_str())
+            .unwrap_or_default()
+            .to_string(),
+         right_state: ears
+            .map(|e| e.right_state().to_str())
+            .unwrap_or_default()
+            .to_string(),
+      }
+   }
+}
+
+fn to_arg_error<T: fmt::Display>(e: T) -> fdo::Error {
+   fdo::Error::InvalidArgs(e.to_string())
+}
+
+/// Serializes a reply, failing the call instead of panicking the handler.
+fn to_json_string(value: &impl Serialize) -> fdo::Result<String> {
+   serde_json::to_string(value)
+      .map_err(|e| fdo::Error::Failed(format!("Failed to serialize reply: {e}")))
+}
+
+/// Parses a feature that can be switched on or off.
+///
+/// Press timings take more than on/off and have their own commands, so they
+/// are rejected here rather than sent as a boolean.
+fn parse_on_off_feature(feature_str: &str) -> Result<FeatureId, String> {
+   let feature: FeatureId = feature_str
+      .parse()
+      .map_err(|_| format!("Invalid feature: {feature_str:?}"))?;
+   let timing_action = match feature {
+      FeatureId::DOUBLE_CLICK_INTERVAL => Some("set_press_speed"),
+      FeatureId::CLICK_HOLD_INTERVAL => Some("set_hold_duration"),
+      _ => None,
+   };
+   match timing_action {
+      Some(action) => Err(format!(
+         "{feature} is not an on/off feature, use {action} instead"
+      )),
+      None => Ok(feature),
+   }
+}
+
+/// Sends an MPRIS transport command to the active player.
+async fn send_transport_command(method: &str) -> fdo::Result<bool> {
+   let player = media_control::send_to_active_player(method)
+      .await
+      .map_err(|e| fdo::Error::Failed(format!("{method} failed: {e}")))?;
+   info!("Sent {method} to {player}");
+   Ok(true)
+}
+
+#[interface(name = "org.kairpods.manager")]
+impl AirPodsService {
+   pub(crate) async fn get_devices(&self) -> fdo::Result<String> {
+      let states: Vec<serde_json::Value> = self
+         .bluetooth_manager
+         .all_devices()
+         .await
+         .into_iter()
+         .map(|d| d.to_json())
+         .collect();
+      to_json_string(&states)
+   }
+
+   pub(crate) async fn get_devices_filtered(&self, connected_only: bool) -> fdo::Result<String> {
+      let states: Vec<serde_json::Value> = self
+         .bluetooth_manager
+         .all_devices()
+         .await
+         .into_iter()
+         .filter(|d| !connected_only || d.is_connected())
+         .map(|d| d.to_json())
+         .collect();
+      to_json_string(&states)
+   }
+
+   pub(crate) async fn get_device(&self, address: String) -> fdo::Result<String> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let dev = self.bluetooth_manager.get_device(addr).await?;
+      Ok(dev.to_json().to_string())
+   }
+
+   pub(crate) async fn list_features(&self, address: String) -> fdo::Result<String> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let dev = self.bluetooth_manager.get_device(addr).await?;
+      Ok(dev.features_json().to_string())
+   }
+
+   pub(crate) async fn get_noise_modes(&self, address: String) -> fdo::Result<String> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let dev = self.bluetooth_manager.get_device(addr).await?;
+      let modes: Vec<_> = dev.noise_modes().iter().map(|m| m.to_str()).collect();
+      to_json_string(&modes)
+   }
+
+   pub(crate) async fn get_error_log(&self, address: String) -> fdo::Result<String> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let dev = self.bluetooth_manager.get_device(addr).await?;
+      let log: Vec<_> = dev.error_log().iter().map(|e| e.to_json()).collect();
+      Ok(serde_json::Value::from(log).to_string())
+   }
+
+   pub(crate) async fn get_case_status(&self, address: String) -> fdo::Result<String> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let dev = self.bluetooth_manager.get_device(addr).await?;
+      let case = dev.battery_info().map(|b| b.case).unwrap_or_default();
+      Ok(case.to_status_json().to_string())
+   }
+
+   pub(crate) async fn get_primary_bud(&self, address: String) -> fdo::Result<String> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let dev = self.bluetooth_manager.get_device(addr).await?;
+      let primary = dev
+         .primary_bud()
+         .ok_or_else(|| fdo::Error::Failed(format!("{address} has not reported a primary bud")))?;
+      Ok(primary.to_string())
+   }
+
+   pub(crate) async fn get_ear_detection(&self, address: String) -> fdo::Result<String> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let dev = self.bluetooth_manager.get_device(addr).await?;
+      if !dev.is_connected() {
+         return Err(AirPodsError::DeviceNotConnected.into());
+      }
+      let ear = dev
+         .ear_detection()
+         .ok_or_else(|| fdo::Error::Failed(format!("{address} has not reported ear detection")))?;
+      Ok(ear.to_json().to_string())
+   }
+
+   pub(crate) async fn get_fit_test(&self, address: String) -> fdo::Result<String> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let dev = self.bluetooth_manager.get_device(addr).await?;
+      Ok(dev.fit_test_json().to_string())
+   }
+
+   pub(crate) async fn get_noise_control(&self, address: String) -> fdo::Result<String> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let dev = self.bluetooth_manager.get_device(addr).await?;
+      if !dev.is_connected() {
+         return Err(AirPodsError::DeviceNotConnected.into());
       }
+      let mode = dev
+         .noise_mode()
+         .ok_or_else(|| fdo::Error::Failed(format!("{address} has not reported a noise mode")))?;
+      Ok(mode.to_str().to_string())
+   }
+
+   pub(crate) async fn get_all_batteries(&self) -> fdo::Result<String> {
+      let batteries: serde_json::Map<String, serde_json::Value> = self
+         .bluetooth_manager
+         .all_devices()
+         .await
+         .into_iter()
+         .map(|d| {
+            // Disconnected devices keep their last report
+            let battery = d.battery_info().unwrap_or_else(BatteryInfo::new);
+            let state = json!({
+               "left": battery.left.to_status_json(),
+               "right": battery.right.to_status_json(),
+               "case": battery.case.to_status_json(),
+               "headphone": battery.headphone.to_status_json(),
+               "stale": !d.is_connected(),
+            });
+            (d.address_str().to_string(), state)
+         })
+         .collect();
+      Ok(serde_json::Value::Object(batteries).to_string())
+   }
+
+   pub(crate) async fn get_battery_history(&self, address: String) -> fdo::Result<String> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let dev = self.bluetooth_manager.get_device(addr).await?;
+      Ok(dev.battery_history_json().to_string())
+   }
+
+   pub(crate) async fn get_battery_study_summary(&self, address: String) -> fdo::Result<String> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let study = self
+         .battery_study
+         .as_ref()
+         .ok_or_else(|| fdo::Error::NotSupported("Battery study is unavailable".to_string()))?;
+      let summary = study
+         .summary(addr)?
+         .ok_or_else(|| fdo::Error::Failed(format!("No battery study for {address}")))?;
+      Ok(summary.to_string())
+   }
+
+   pub(crate) async fn get_connection_uptime(&self, address: String) -> fdo::Result<u64> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let uptime = self.bluetooth_manager.connection_uptime(addr).await?;
+      Ok(uptime.as_secs())
+   }
+
+   pub(crate) async fn get_battery_raw(&self, address: String) -> fdo::Result<String> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let dev = self.bluetooth_manager.get_device(addr).await?;
+      let info = dev
+         .battery_raw_json()
+         .ok_or_else(|| fdo::Error::Failed(format!("No battery frame from {address} yet")))?;
+      Ok(info.to_string())
+   }
+
+   pub(crate) async fn get_handshake(&self, address: String) -> fdo::Result<String> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let dev = self.bluetooth_manager.get_device(addr).await?;
+      Ok(dev.handshake_json().to_string())
+   }
 
+   pub(crate) async fn get_advertisement(&self, address: String) -> fdo::Result<String> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let data = self.bluetooth_manager.get_advertisement(addr).await?;
+      Ok(json!({
+         "address": address,
+         "raw": hex::encode(&data),
+         "decoded": decode_proximity_pairing(&data),
+      })
+      .to_string())
+   }
+
+   pub(crate) async fn passthrough(&self, address: String, packet: String) -> fdo::Result<bool> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let dev = self.bluetooth_manager.get_device(addr).await?;
+      let packet = hex::decode(packet).map_err(to_arg_error)?;
+      parser::validate_frame(&packet).map_err(to_arg_error)?;
+      dev.passthrough(&packet).await?;
       Ok(true)
    }
 
-   async fn connect_device(&self, address: String) -> fdo::Result<bool> {
+   pub(crate) async fn passthrough_unchecked(
+      &self,
+      address: String,
+      packet: String,
+   ) -> fdo::Result<bool> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let dev = self.bluetooth_manager.get_device(addr).await?;
+      let packet = hex::decode(packet).map_err(to_arg_error)?;
+      warn!("Sending unvalidated packet to {address}");
+      dev.passthrough(&packet).await?;
+      Ok(true)
+   }
+
+   async fn send_command(
+      &self,
+      address: String,
+      action: String,
+      params: HashMap<String, zvariant::Value<'_>>,
+      #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+   ) -> fdo::Result<String> {
+      self
+         .run_command(address, &action, &params, Some(&emitter))
+         .await
+   }
+
+   async fn broadcast_command(
+      &self,
+      action: String,
+      params: HashMap<String, zvariant::Value<'_>>,
+      #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+   ) -> fdo::Result<String> {
+      self
+         .run_broadcast_command(action, params, Some(&emitter))
+         .await
+   }
+
+   async fn set_features(
+      &self,
+      address: String,
+      features: HashMap<String, bool>,
+      #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+   ) -> fdo::Result<String> {
+      self.apply_features(address, features, Some(&emitter)).await
+   }
+
+   pub(crate) async fn scan(&self, timeout_ms: u32) -> fdo::Result<String> {
+      let found = self
+         .bluetooth_manager
+         .scan(Duration::from_millis(u64::from(timeout_ms)))
+         .await?;
+      let devices: Vec<_> = found
+         .into_iter()
+         .map(|(addr, model)| {
+            json!({
+               "address": addr.to_string(),
+               "model": model.map(|m| m.to_str()),
+            })
+         })
+         .collect();
+      Ok(serde_json::Value::from(devices).to_string())
+   }
+
+   pub(crate) async fn connect_device(&self, address: String) -> fdo::Result<bool> {
       let addr = Address::from_str(&address).map_err(to_arg_error)?;
       self.bluetooth_manager.establish_aap(addr).await?;
       Ok(true)
    }
 
-   async fn disconnect_device(&self, address: String) -> fdo::Result<bool> {
+   pub(crate) async fn connect_device_sync(
+      &self,
+      address: String,
+      timeout_ms: u32,
+   ) -> fdo::Result<bool> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      self.bluetooth_manager.establish_aap(addr).await?;
+      let dev = self.bluetooth_manager.get_device(addr).await?;
+      let timeout = Duration::from_millis(u64::from(timeout_ms));
+      time::timeout(timeout, dev.wait_ready())
+         .await
+         .map_err(|_| AirPodsError::RequestTimeout)?;
+      Ok(true)
+   }
+
+   pub(crate) async fn disconnect_device(&self, address: String) -> fdo::Result<bool> {
       let addr = Address::from_str(&address).map_err(to_arg_error)?;
       self.bluetooth_manager.disconnect_aap(addr).await?;
       Ok(true)
    }
 
-   async fn set_auto_play_pause(&self, enabled: bool) -> fdo::Result<bool> {
-      media_control::set_enabled(enabled);
-      info!("Auto play/pause set to {enabled}");
+   pub(crate) async fn reconnect_device(&self, address: String) -> fdo::Result<bool> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      info!("Reconnecting AAP session for {address}");
+      self.bluetooth_manager.reconnect_aap(addr).await?;
+      Ok(true)
+   }
+
+   pub(crate) async fn reset_device(&self, address: String) -> fdo::Result<bool> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let dev = self.bluetooth_manager.get_device(addr).await?;
+      // Fails with NotSupported until a reset sequence is known
+      dev.factory_reset().await?;
+      warn!("Factory reset {address}, erasing its pairings");
+      Ok(true)
+   }
+
+   pub(crate) async fn set_ear_detection_sensitivity(
+      &self,
+      address: String,
+      level: String,
+   ) -> fdo::Result<bool> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let level = EarDetectionSensitivity::from_str(&level).map_err(|_| {
+         to_arg_error(format_args!(
+            "Invalid sensitivity: {level} (must be low, medium or high)"
+         ))
+      })?;
+      let dev = self.bluetooth_manager.get_device(addr).await?;
+      dev.set_ear_detection_sensitivity(level).await?;
+      Ok(true)
+   }
+
+   pub(crate) async fn stop_sound(&self, address: String, component: String) -> fdo::Result<bool> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let component = match component.as_str() {
+         "left" => Component::Left,
+         "right" => Component::Right,
+         "case" => Component::Case,
+         "headphone" => Component::Headphone,
+         _ => {
+            return Err(to_arg_error(format_args!(
+               "Invalid component: {component} (must be left, right, case or headphone)"
+            )));
+         },
+      };
+      let dev = self.bluetooth_manager.get_device(addr).await?;
+      dev.stop_sound(component).await?;
       Ok(true)
    }
 
-   async fn get_auto_play_pause(&self) -> fdo::Result<bool> {
+   pub(crate) async fn refresh_battery(&self, address: String) -> fdo::Result<bool> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let dev = self.bluetooth_manager.get_device(addr).await?;
+      dev.refresh_battery().await?;
+      Ok(true)
+   }
+
+   pub(crate) async fn set_battery_offset(
+      &self,
+      address: String,
+      component: String,
+      offset: i32,
+   ) -> fdo::Result<bool> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let offset = i8::try_from(offset)
+         .ok()
+         .filter(|o| (-100..=100).contains(o))
+         .ok_or_else(|| {
+            to_arg_error(format_args!(
+               "Invalid offset: {offset} (must be -100 to 100)"
+            ))
+         })?;
+
+      let offsets = self
+         .bluetooth_manager
+         .device_state()
+         .update_battery_offsets(addr, |offsets| {
+            let slot = offsets.get_mut(&component).ok_or_else(|| {
+               to_arg_error(format_args!(
+                  "Invalid component: {component} (must be left, right, case or headphone)"
+               ))
+            })?;
+            *slot = offset;
+            Ok::<_, fdo::Error>(())
+         })
+         .await?;
+
+      if let Ok(dev) = self.bluetooth_manager.get_device(addr).await {
+         dev.set_battery_offsets(offsets);
+      }
+      info!("{addr}: Battery offset for {component} set to {offset}");
+      Ok(true)
+   }
+
+   pub(crate) async fn set_ignore_ear_sensor(
+      &self,
+      address: String,
+      bud: String,
+      ignore: bool,
+   ) -> fdo::Result<bool> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+
+      let ignored = self
+         .bluetooth_manager
+         .device_state()
+         .update_ignored_ear_sensors(addr, |ignored| {
+            let slot = ignored.get_mut(&bud).ok_or_else(|| {
+               to_arg_error(format_args!("Invalid bud: {bud} (must be left or right)"))
+            })?;
+            *slot = ignore;
+            Ok::<_, fdo::Error>(())
+         })
+         .await?;
+
+      if let Ok(dev) = self.bluetooth_manager.get_device(addr).await {
+         dev.set_ignored_ear_sensors(ignored);
+      }
+      info!("{addr}: Ignoring the {bud} ear sensor: {ignore}");
+      Ok(true)
+   }
+
+   async fn set_auto_play_pause(
+      &self,
+      enabled: bool,
+      #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+   ) -> fdo::Result<bool> {
+      self.update_auto_play_pause(enabled, Some(&emitter)).await?;
+      Ok(true)
+   }
+
+   pub(crate) async fn get_auto_play_pause(&self) -> fdo::Result<bool> {
       Ok(media_control::is_enabled())
    }
 
+   pub(crate) async fn set_resume_on_reinsert(&self, enabled: bool) -> fdo::Result<bool> {
+      media_control::set_resume_on_reinsert(enabled);
+      info!("Resume on reinsert set to {enabled}");
+      Ok(true)
+   }
+
+   pub(crate) async fn get_resume_on_reinsert(&self) -> fdo::Result<bool> {
+      Ok(media_control::resume_on_reinsert())
+   }
+
+   pub(crate) async fn set_single_bud_mode(&self, enabled: bool) -> fdo::Result<bool> {
+      media_control::set_single_bud_mode(enabled);
+      info!("Single-bud mode set to {enabled}");
+      Ok(true)
+   }
+
+   pub(crate) async fn get_single_bud_mode(&self) -> fdo::Result<bool> {
+      Ok(media_control::single_bud_mode())
+   }
+
+   pub(crate) async fn set_ignore_held(&self, enabled: bool) -> fdo::Result<bool> {
+      media_control::set_ignore_held(enabled);
+      info!("Ignore held set to {enabled}");
+      Ok(true)
+   }
+
+   pub(crate) async fn get_ignore_held(&self) -> fdo::Result<bool> {
+      Ok(media_control::ignore_held())
+   }
+
+   pub(crate) async fn set_media_control_device(&self, address: String) -> fdo::Result<bool> {
+      let addr = if address.is_empty() {
+         None
+      } else {
+         Some(Address::from_str(&address).map_err(to_arg_error)?)
+      };
+      media_control::set_media_control_device(addr);
+      info!("Media control device set to {address:?}");
+      Ok(true)
+   }
+
+   pub(crate) async fn get_media_control_device(&self) -> fdo::Result<String> {
+      Ok(media_control::media_control_device()
+         .map(|addr| addr.to_string())
+         .unwrap_or_default())
+   }
+
+   pub(crate) async fn media_next(&self) -> fdo::Result<bool> {
+      send_transport_command("Next").await
+   }
+
+   pub(crate) async fn media_previous(&self) -> fdo::Result<bool> {
+      send_transport_command("Previous").await
+   }
+
+   pub(crate) async fn media_play_pause(&self) -> fdo::Result<bool> {
+      send_transport_command("PlayPause").await
+   }
+
+   pub(crate) async fn set_allowed_players(&self, players: Vec<String>) -> fdo::Result<bool> {
+      info!("Auto-pause player allow-list set to {players:?}");
+      media_control::set_allowed_players(players);
+      Ok(true)
+   }
+
+   pub(crate) async fn get_allowed_players(&self) -> fdo::Result<Vec<String>> {
+      Ok(media_control::allowed_players())
+   }
+
+   pub(crate) async fn set_rssi_smoothing_alpha(&self, alpha: f64) -> fdo::Result<bool> {
+      smoothing::set_rssi_alpha(alpha).map_err(to_arg_error)?;
+      info!("RSSI smoothing alpha set to {alpha}");
+      Ok(true)
+   }
+
+   pub(crate) async fn get_rssi_smoothing_alpha(&self) -> fdo::Result<f64> {
+      Ok(smoothing::rssi_alpha())
+   }
+
+   pub(crate) async fn set_battery_smoothing_alpha(&self, alpha: f64) -> fdo::Result<bool> {
+      smoothing::set_battery_alpha(alpha).map_err(to_arg_error)?;
+      info!("Battery smoothing alpha set to {alpha}");
+      Ok(true)
+   }
+
+   pub(crate) async fn get_battery_smoothing_alpha(&self) -> fdo::Result<f64> {
+      Ok(smoothing::battery_alpha())
+   }
+
+   /// Sets the quiet hours window as `HH:MM` local times; empty times
+   /// disable it.
+   pub(crate) async fn set_quiet_hours(
+      &self,
+      start: String,
+      end: String,
+      suppress_media: bool,
+   ) -> fdo::Result<bool> {
+      let window = if start.is_empty() && end.is_empty() {
+         None
+      } else {
+         Some(QuietHours {
+            start: start.parse().map_err(to_arg_error)?,
+            end: end.parse().map_err(to_arg_error)?,
+            suppress_media,
+         })
+      };
+      quiet_hours::set_quiet_hours(window);
+      info!("Quiet hours set to {start:?}-{end:?}");
+      Ok(true)
+   }
+
+   /// Gets the quiet hours window, or an empty object when disabled.
+   pub(crate) async fn get_quiet_hours(&self) -> fdo::Result<String> {
+      let json = quiet_hours::quiet_hours().map_or_else(
+         || json!({}),
+         |w| {
+            let mut json = w.to_json();
+            json["active"] = json!(quiet_hours::is_quiet());
+            json
+         },
+      );
+      Ok(json.to_string())
+   }
+
+   /// Shuts the service down gracefully; only processes of the user running
+   /// the service may do so.
+   async fn quit(
+      &self,
+      #[zbus(header)] header: Header<'_>,
+      #[zbus(connection)] connection: &Connection,
+   ) -> fdo::Result<bool> {
+      let sender = header
+         .sender()
+         .ok_or_else(|| fdo::Error::AccessDenied("Unknown caller".to_string()))?;
+      let caller = fdo::DBusProxy::new(connection)
+         .await?
+         .get_connection_unix_user(BusName::Unique(sender.clone()))
+         .await?;
+      // SAFETY: getuid has no preconditions and cannot fail
+      let owner = unsafe { libc::getuid() };
+      if caller != owner {
+         warn!("Refusing Quit from {sender} running as uid {caller}");
+         return Err(fdo::Error::AccessDenied(
+            "Only the user running the service may stop it".to_string(),
+         ));
+      }
+      Ok(self.request_quit())
+   }
+
+   pub(crate) async fn set_log_filter(&self, filter: String, persist: bool) -> fdo::Result<bool> {
+      logging::set_filter(&filter)
+         .map_err(|e| to_arg_error(format_args!("Invalid log filter {filter:?}: {e}")))?;
+      info!("Log filter set to {filter:?}");
+
+      if persist {
+         Config::update(|config| config.log_filter = Some(filter.into()))?;
+      }
+      Ok(true)
+   }
+
    // Signals
    #[zbus(signal)]
    pub async fn device_connected(emitter: &SignalEmitter<'_>, address: &str) -> zbus::Result<()>;
 
    #[zbus(signal)]
-   pub async fn device_disconnected(emitter: &SignalEmitter<'_>, address: &str)
-   -> zbus::Result<()>;
+   pub async fn device_disconnected(
+      emitter: &SignalEmitter<'_>,
+      address: &str,
+      reason: &str,
+   ) -> zbus::Result<()>;
+
+   #[zbus(signal)]
+   pub async fn connection_state_changed(
+      emitter: &SignalEmitter<'_>,
+      address: &str,
+      state: &str,
+   ) -> zbus::Result<()>;
 
    #[zbus(signal)]
    pub async fn battery_updated(
@@ -162,6 +1101,14 @@ impl AirPodsService {
       mode: &str,
    ) -> zbus::Result<()>;
 
+   #[zbus(signal)]
+   pub async fn feature_changed(
+      emitter: &SignalEmitter<'_>,
+      address: &str,
+      feature: &str,
+      enabled: bool,
+   ) -> zbus::Result<()>;
+
    #[zbus(signal)]
    pub async fn ear_detection_changed(
       emitter: &SignalEmitter<'_>,
@@ -176,6 +1123,27 @@ impl AirPodsService {
       name: &str,
    ) -> zbus::Result<()>;
 
+   #[zbus(signal)]
+   pub async fn spatial_state_changed(
+      emitter: &SignalEmitter<'_>,
+      address: &str,
+      head_tracking: bool,
+   ) -> zbus::Result<()>;
+
+   #[zbus(signal)]
+   pub async fn volume_changed(
+      emitter: &SignalEmitter<'_>,
+      address: &str,
+      volume: u8,
+   ) -> zbus::Result<()>;
+
+   #[zbus(signal)]
+   pub async fn raw_event(
+      emitter: &SignalEmitter<'_>,
+      address: &str,
+      event_json: &str,
+   ) -> zbus::Result<()>;
+
    #[zbus(signal)]
    pub async fn device_error(emitter: &SignalEmitter<'_>, address: &str) -> zbus::Result<()>;
 
@@ -185,8 +1153,141 @@ impl AirPodsService {
       self.get_devices().await.unwrap_or_default()
    }
 
+   #[zbus(property)]
+   async fn devices_struct(&self) -> Vec<DeviceStruct> {
+      self
+         .bluetooth_manager
+         .all_devices()
+         .await
+         .iter()
+         .map(DeviceStruct::from)
+         .collect()
+   }
+
+   #[zbus(property)]
+   async fn auto_play_pause(&self) -> bool {
+      media_control::is_enabled()
+   }
+
+   #[zbus(property, name = "AutoPlayPause")]
+   async fn set_auto_play_pause_property(&self, enabled: bool) {
+      media_control::set_enabled(enabled);
+      info!("Auto play/pause set to {enabled}");
+   }
+
    #[zbus(property)]
    async fn connected_count(&self) -> u32 {
       self.bluetooth_manager.count_devices().await
    }
+
+   #[zbus(property)]
+   async fn connected_devices(&self) -> String {
+      let addresses: Vec<_> = self
+         .bluetooth_manager
+         .all_devices()
+         .await
+         .iter()
+         .filter(|d| d.is_connected())
+         .map(|d| d.address_str().to_string())
+         .collect();
+      serde_json::Value::from(addresses).to_string()
+   }
+}
+
+/// Debug interface for injecting synthetic device events.
+///
+/// Only compiled with the `testing` feature, so release builds cannot expose it.
+#[cfg(feature = "testing")]
+pub struct DebugService {
+   bluetooth_manager: BluetoothManager,
+   event_tx: crate::event::EventSender,
+}
+
+#[cfg(feature = "testing")]
+impl DebugService {
+   pub const fn new(
+      bluetooth_manager: BluetoothManager,
+      event_tx: crate::event::EventSender,
+   ) -> Self {
+      Self {
+         bluetooth_manager,
+         event_tx,
+      }
+   }
+}
+
+#[cfg(feature = "testing")]
+#[interface(name = "org.kairpods.debug")]
+impl DebugService {
+   async fn simulate_event(&self, address: String, event_json: String) -> fdo::Result<bool> {
+      let addr = Address::from_str(&address).map_err(to_arg_error)?;
+      let event = crate::simulate::parse_event(&event_json).map_err(to_arg_error)?;
+
+      // Without hardware there is no managed device, so use a detached one
+      let dev = match self.bluetooth_manager.get_device(addr).await {
+         Ok(dev) => dev,
+         Err(_) => {
+            crate::airpods::device::AirPods::new(addr, "Simulated AirPods".to_string(), None, None)
+         },
+      };
+
+      crate::simulate::apply_event(&dev, &event);
+      warn!("Injecting simulated event for {address}: {event:?}");
+      self.event_tx.emit(&dev, event);
+      Ok(true)
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use std::collections::BTreeMap;
+
+   use super::*;
+
+   #[test]
+   fn unserializable_reply_is_an_error() {
+      // JSON object keys must be strings
+      let value = BTreeMap::from([((1, 2), "pair")]);
+      let err = to_json_string(&value).unwrap_err();
+      assert!(matches!(err, fdo::Error::Failed(_)), "{err:?}");
+
+      assert_eq!(to_json_string(&["a", "b"]).unwrap(), r#"["a","b"]"#);
+   }
+
+   #[test]
+   fn press_timings_are_not_on_off_features() {
+      for feature in [
+         FeatureId::DOUBLE_CLICK_INTERVAL,
+         FeatureId::CLICK_HOLD_INTERVAL,
+      ] {
+         let err = parse_on_off_feature(feature.to_str()).unwrap_err();
+         assert!(err.contains("not an on/off feature"), "{err}");
+      }
+      assert_eq!(
+         parse_on_off_feature(FeatureId::SINGLE_CLICK_MODE.to_str()),
+         Ok(FeatureId::SINGLE_CLICK_MODE)
+      );
+      assert!(parse_on_off_feature("bogus").is_err());
+   }
+
+   #[test]
+   fn device_struct_has_a_stable_signature() {
+      use zvariant::Type;
+
+      assert_eq!(
+         Vec::<DeviceStruct>::SIGNATURE.to_string(),
+         "a(sssbs(ybb)(ybb)(ybb)(ybb)sss)"
+      );
+
+      let device = AirPods::new(
+         "AA:BB:CC:DD:EE:FF".parse().unwrap(),
+         "Test AirPods".to_string(),
+         None,
+         None,
+      );
+      let entry = DeviceStruct::from(&device);
+      assert_eq!(entry.address, "AA:BB:CC:DD:EE:FF");
+      assert!(!entry.left.available);
+      assert_eq!(entry.noise_mode, "");
+   }
 }
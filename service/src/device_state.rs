@@ -0,0 +1,286 @@
+//! Device state remembered across restarts.
+//!
+//! Settings changed at runtime are kept in a state file of their own rather
+//! than in config.toml, so saving them never rewrites the file the user
+//! edits by hand. The state is held in memory and written out on every
+//! change, under one lock so overlapping setters can't lose an update.
+
+use std::{
+   collections::BTreeMap,
+   env, io,
+   path::{Path, PathBuf},
+};
+
+use bluer::Address;
+use serde::{Deserialize, Serialize};
+use smol_str::SmolStr;
+use tokio::{fs, sync::RwLock};
+
+use crate::{
+   airpods::protocol::{BatteryOffsets, IgnoredEarSensors},
+   error::{AirPodsError, Result},
+};
+
+/// Settings last applied to a device, re-applied when it reconnects.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct DeviceSettings {
+   #[serde(default)]
+   pub noise_mode: Option<SmolStr>,
+   /// Feature name -> enabled
+   #[serde(default)]
+   pub features: BTreeMap<SmolStr, bool>,
+}
+
+/// Contents of the state file, keyed by device address.
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct State {
+   #[serde(default)]
+   device_settings: BTreeMap<String, DeviceSettings>,
+
+   #[serde(default)]
+   battery_offsets: BTreeMap<String, BatteryOffsets>,
+
+   #[serde(default)]
+   ignored_ear_sensors: BTreeMap<String, IgnoredEarSensors>,
+}
+
+/// Runtime device state shared by the D-Bus service and the manager.
+pub struct DeviceState {
+   /// Where changes are saved; `None` keeps them in memory only
+   path: Option<PathBuf>,
+   /// Whether applied settings are recorded, per `restore_settings_on_connect`
+   remember_settings: bool,
+   state: RwLock<State>,
+}
+
+impl DeviceState {
+   /// Loads the state file, starting empty if there is none yet.
+   pub fn open(remember_settings: bool) -> Result<Self> {
+      Self::open_at(Self::state_path()?, remember_settings)
+   }
+
+   fn open_at(path: PathBuf, remember_settings: bool) -> Result<Self> {
+      let state = match std::fs::read_to_string(&path) {
+         Ok(contents) => toml::from_str(&contents)?,
+         Err(e) if e.kind() == io::ErrorKind::NotFound => State::default(),
+         Err(e) => return Err(e.into()),
+      };
+      Ok(Self {
+         path: Some(path),
+         remember_settings,
+         state: RwLock::new(state),
+      })
+   }
+
+   /// Keeps the state in memory only, e.g. when the state file is unreadable
+   /// and must not be overwritten.
+   pub fn in_memory(remember_settings: bool) -> Self {
+      Self {
+         path: None,
+         remember_settings,
+         state: RwLock::default(),
+      }
+   }
+
+   fn state_path() -> Result<PathBuf> {
+      // Check for override environment variable first
+      if let Ok(path) = env::var("AIRPODS_STATE_PATH") {
+         return Ok(PathBuf::from(path));
+      }
+
+      // ~/.local/share/kairpods/state.toml
+      let base = dirs::data_local_dir().ok_or(AirPodsError::ConfigDirNotFound)?;
+      Ok(base.join("kairpods").join("state.toml"))
+   }
+
+   /// Gets the settings recorded for the given address.
+   pub async fn device_settings(&self, address: Address) -> Option<DeviceSettings> {
+      let state = self.state.read().await;
+      state.device_settings.get(&address.to_string()).cloned()
+   }
+
+   /// Records a setting applied to a device, if `restore_settings_on_connect`
+   /// is enabled.
+   pub async fn remember_device_setting(
+      &self,
+      address: Address,
+      update: impl FnOnce(&mut DeviceSettings),
+   ) -> Result<()> {
+      if !self.remember_settings {
+         return Ok(());
+      }
+      self
+         .update(|state| {
+            update(
+               state
+                  .device_settings
+                  .entry(address.to_string())
+                  .or_default(),
+            );
+            Ok::<_, AirPodsError>(())
+         })
+         .await
+   }
+
+   /// Gets the battery calibration for the given address.
+   pub async fn battery_offsets(&self, address: Address) -> BatteryOffsets {
+      let state = self.state.read().await;
+      state
+         .battery_offsets
+         .get(&address.to_string())
+         .copied()
+         .unwrap_or_default()
+   }
+
+   /// Changes the battery calibration for the given address, returning the
+   /// offsets now in effect.
+   pub async fn update_battery_offsets<E: From<AirPodsError>>(
+      &self,
+      address: Address,
+      change: impl FnOnce(&mut BatteryOffsets) -> std::result::Result<(), E>,
+   ) -> std::result::Result<BatteryOffsets, E> {
+      self
+         .update(|state| {
+            let offsets = state
+               .battery_offsets
+               .entry(address.to_string())
+               .or_default();
+            change(offsets)?;
+            Ok(*offsets)
+         })
+         .await
+   }
+
+   /// Gets the ear sensors to ignore for the given address.
+   pub async fn ignored_ear_sensors(&self, address: Address) -> IgnoredEarSensors {
+      let state = self.state.read().await;
+      state
+         .ignored_ear_sensors
+         .get(&address.to_string())
+         .copied()
+         .unwrap_or_default()
+   }
+
+   /// Changes the ear sensors to ignore for the given address, returning the
+   /// sensors now ignored.
+   pub async fn update_ignored_ear_sensors<E: From<AirPodsError>>(
+      &self,
+      address: Address,
+      change: impl FnOnce(&mut IgnoredEarSensors) -> std::result::Result<(), E>,
+   ) -> std::result::Result<IgnoredEarSensors, E> {
+      self
+         .update(|state| {
+            let ignored = state
+               .ignored_ear_sensors
+               .entry(address.to_string())
+               .or_default();
+            change(ignored)?;
+            Ok(*ignored)
+         })
+         .await
+   }
+
+   /// Applies a change and saves the result, holding the lock throughout so
+   /// changes are written in the order they are made. A change that fails,
+   /// or can't be saved, is dropped.
+   async fn update<T, E: From<AirPodsError>>(
+      &self,
+      change: impl FnOnce(&mut State) -> std::result::Result<T, E>,
+   ) -> std::result::Result<T, E> {
+      let mut state = self.state.write().await;
+      let mut updated = state.clone();
+      let value = change(&mut updated)?;
+      if let Some(path) = &self.path {
+         save(path, &updated).await?;
+      }
+      *state = updated;
+      Ok(value)
+   }
+}
+
+async fn save(path: &Path, state: &State) -> Result<()> {
+   if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent).await?;
+   }
+   // Replace the file in one step, so a crash never leaves half of it
+   let tmp = path.with_extension("toml.tmp");
+   fs::write(&tmp, toml::to_string_pretty(state)?).await?;
+   fs::rename(&tmp, path).await?;
+   Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+   use std::sync::Arc;
+
+   use super::*;
+
+   #[tokio::test]
+   async fn overlapping_changes_are_all_saved() {
+      let dir = tempfile::tempdir().unwrap();
+      let path = dir.path().join("state.toml");
+      let state = Arc::new(DeviceState::open_at(path.clone(), true).unwrap());
+      let address: Address = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+
+      let tasks: Vec<_> = (0..8)
+         .map(|i| {
+            let state = state.clone();
+            tokio::spawn(async move {
+               state
+                  .remember_device_setting(address, |settings| {
+                     settings
+                        .features
+                        .insert(format!("feature_{i}").into(), true);
+                  })
+                  .await
+            })
+         })
+         .collect();
+      for task in tasks {
+         task.await.unwrap().unwrap();
+      }
+
+      // Both in memory and after a restart
+      let reopened = DeviceState::open_at(path, true).unwrap();
+      for state in [&*state, &reopened] {
+         let settings = state.device_settings(address).await.unwrap();
+         assert_eq!(settings.features.len(), 8);
+      }
+   }
+
+   #[tokio::test]
+   async fn failed_changes_are_dropped() {
+      let state = DeviceState::in_memory(true);
+      let address: Address = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+      let offsets = state
+         .update_battery_offsets(address, |offsets| {
+            offsets.left = -5;
+            Ok::<_, AirPodsError>(())
+         })
+         .await
+         .unwrap();
+      assert_eq!(offsets.left, -5);
+
+      let result = state
+         .update_battery_offsets(address, |offsets| {
+            offsets.left = 10;
+            Err(AirPodsError::FeatureNotSupported("test".to_string()))
+         })
+         .await;
+      assert!(result.is_err());
+      assert_eq!(state.battery_offsets(address).await.left, -5);
+   }
+
+   #[tokio::test]
+   async fn settings_are_only_remembered_when_enabled() {
+      let state = DeviceState::in_memory(false);
+      let address: Address = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+      state
+         .remember_device_setting(address, |settings| {
+            settings.noise_mode = Some("anc".into());
+         })
+         .await
+         .unwrap();
+      assert!(state.device_settings(address).await.is_none());
+   }
+}
@@ -36,6 +36,9 @@ pub enum AirPodsError {
    #[error("Device not paired")]
    DeviceNotPaired,
 
+   #[error("{0} is not a known device and known_devices_only is enabled")]
+   DeviceNotKnown(Address),
+
    #[error("Invalid packet: {0}")]
    InvalidPacket(#[from] parser::ProtoError),
 
@@ -54,6 +57,9 @@ pub enum AirPodsError {
    #[error("Request timeout")]
    RequestTimeout,
 
+   #[error("ATT request for handle 0x{handle:04x} failed with error 0x{code:02x}")]
+   Att { handle: u16, code: u8 },
+
    #[error("Could not determine config directory")]
    ConfigDirNotFound,
 
@@ -77,6 +83,12 @@ pub enum AirPodsError {
 
    #[error("Battery study error: {0}")]
    BatteryStudy(#[from] battery_study::Error),
+
+   #[error("Unknown argument: {0}")]
+   InvalidArgument(String),
+
+   #[error("{0} is already owned by another process")]
+   BusNameTaken(&'static str),
 }
 
 /// Convenience type alias for Results with `AirPodsError`.
@@ -84,6 +96,9 @@ pub type Result<T, E = AirPodsError> = std::result::Result<T, E>;
 
 impl From<AirPodsError> for zbus::fdo::Error {
    fn from(error: AirPodsError) -> Self {
-      Self::Failed(error.to_string())
+      match error {
+         AirPodsError::FeatureNotSupported(_) => Self::NotSupported(error.to_string()),
+         _ => Self::Failed(error.to_string()),
+      }
    }
 }
@@ -6,23 +6,139 @@
 
 use std::sync::Arc;
 
+use serde::{Deserialize, Serialize};
 use smol_str::SmolStr;
 
 use crate::airpods::{
    device::AirPods,
-   protocol::{BatteryInfo, EarDetectionStatus, NoiseControlMode},
+   protocol::{BatteryInfo, EarDetectionStatus, FeatureId, NoiseControlMode},
 };
 
+/// Why a device was disconnected.
+#[derive(
+   Debug, Clone, Copy, PartialEq, Eq, strum::Display, strum::EnumString, strum::IntoStaticStr,
+)]
+#[strum(serialize_all = "snake_case")]
+pub enum DisconnectReason {
+   /// Disconnected on request (D-Bus call or device removed by the user)
+   User,
+   /// Link dropped, typically out of range
+   Timeout,
+   /// Protocol or I/O failure
+   Error,
+   /// The adapter was powered off
+   BluetoothOff,
+   /// Reconnecting failed `max_reconnect_attempts` times in a row
+   GaveUp,
+}
+
+impl DisconnectReason {
+   pub fn to_str(self) -> &'static str {
+      self.into()
+   }
+}
+
+/// Connection progress of a device, as reported to clients.
+#[derive(
+   Debug,
+   Default,
+   Copy,
+   Clone,
+   PartialEq,
+   Eq,
+   strum::Display,
+   strum::EnumString,
+   strum::IntoStaticStr,
+)]
+#[strum(serialize_all = "snake_case")]
+pub enum ConnectionState {
+   /// No Bluetooth link
+   #[default]
+   Disconnected,
+   /// Linked by bluetoothd, without an AAP session
+   BluetoothConnected,
+   /// AAP session opening, waiting for the device's first state report
+   Handshaking,
+   /// AAP session established and device state known
+   Ready,
+}
+
+impl ConnectionState {
+   pub fn to_str(self) -> &'static str {
+      self.into()
+   }
+}
+
 /// Events that can be emitted by the `AirPods` service.
 #[derive(Debug, Clone)]
 pub enum AirPodsEvent {
    DeviceConnected,
-   DeviceDisconnected,
+   DeviceDisconnected(DisconnectReason),
+   ConnectionStateChanged(ConnectionState),
    DeviceError,
    BatteryUpdated(BatteryInfo),
    NoiseControlChanged(NoiseControlMode),
+   FeatureChanged(FeatureId, bool),
    EarDetectionChanged(EarDetectionStatus),
    DeviceNameChanged(SmolStr),
+   /// Head tracking started (true) or stopped (false)
+   SpatialStateChanged(bool),
+   /// Media transport volume, in percent
+   VolumeChanged(u8),
+   RawFrame(serde_json::Value),
+}
+
+/// Event types, named after the D-Bus signal each one is emitted as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+   DeviceConnected,
+   DeviceDisconnected,
+   ConnectionStateChanged,
+   DeviceError,
+   BatteryUpdated,
+   NoiseControlChanged,
+   FeatureChanged,
+   EarDetectionChanged,
+   DeviceNameChanged,
+   SpatialStateChanged,
+   VolumeChanged,
+   RawEvent,
+}
+
+impl EventKind {
+   /// Whether events of this type only report the latest state, so a newer
+   /// one makes an undelivered older one redundant.
+   pub const fn is_state_report(self) -> bool {
+      matches!(
+         self,
+         Self::BatteryUpdated
+            | Self::NoiseControlChanged
+            | Self::EarDetectionChanged
+            | Self::DeviceNameChanged
+            | Self::SpatialStateChanged
+            | Self::VolumeChanged
+      )
+   }
+}
+
+impl AirPodsEvent {
+   pub const fn kind(&self) -> EventKind {
+      match self {
+         Self::DeviceConnected => EventKind::DeviceConnected,
+         Self::DeviceDisconnected(_) => EventKind::DeviceDisconnected,
+         Self::ConnectionStateChanged(_) => EventKind::ConnectionStateChanged,
+         Self::DeviceError => EventKind::DeviceError,
+         Self::BatteryUpdated(_) => EventKind::BatteryUpdated,
+         Self::NoiseControlChanged(_) => EventKind::NoiseControlChanged,
+         Self::FeatureChanged(..) => EventKind::FeatureChanged,
+         Self::EarDetectionChanged(_) => EventKind::EarDetectionChanged,
+         Self::DeviceNameChanged(_) => EventKind::DeviceNameChanged,
+         Self::SpatialStateChanged(_) => EventKind::SpatialStateChanged,
+         Self::VolumeChanged(_) => EventKind::VolumeChanged,
+         Self::RawFrame(_) => EventKind::RawEvent,
+      }
+   }
 }
 
 /// Trait for implementing event emission.
@@ -33,3 +149,29 @@ pub trait EventBus: Send + Sync {
 
 /// Type alias for a thread-safe event sender.
 pub type EventSender = Arc<dyn EventBus>;
+
+/// Event bus that keeps every emitted event, for tests.
+#[cfg(test)]
+#[derive(Default)]
+pub struct RecordingBus {
+   events: parking_lot::Mutex<Vec<(SmolStr, AirPodsEvent)>>,
+}
+
+#[cfg(test)]
+impl RecordingBus {
+   pub fn record(&self, address: &str, event: AirPodsEvent) {
+      self.events.lock().push((address.into(), event));
+   }
+
+   /// Takes the events recorded so far.
+   pub fn take(&self) -> Vec<(SmolStr, AirPodsEvent)> {
+      std::mem::take(&mut *self.events.lock())
+   }
+}
+
+#[cfg(test)]
+impl EventBus for RecordingBus {
+   fn emit(&self, device: &AirPods, event: AirPodsEvent) {
+      self.record(device.address_str(), event);
+   }
+}
@@ -0,0 +1,213 @@
+//! Per-device rate limiting of repeated events.
+//!
+//! A misbehaving firmware may report the same state many times a second.
+//! Events that only carry the latest state are let through at most once per
+//! interval for each device and event type; while one is held back, newer
+//! ones replace it, so the most recent state is still delivered at the end
+//! of the interval. Any other event of the device first flushes what is held
+//! back, so reports never trail its connection events.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use bluer::Address;
+use parking_lot::Mutex;
+use tokio::time::{self, Instant};
+
+use crate::{
+   airpods::device::AirPods,
+   event::{AirPodsEvent, EventKind},
+};
+
+#[derive(Default)]
+struct Slot {
+   /// When an event of this type last went through
+   last: Option<Instant>,
+   /// Event waiting for the end of the interval
+   pending: Option<(AirPods, AirPodsEvent)>,
+}
+
+#[derive(Default)]
+struct State {
+   slots: HashMap<(Address, EventKind), Slot>,
+   /// Events replaced by a newer one before delivery, per device
+   coalesced: HashMap<Address, u64>,
+}
+
+/// Hands an event on for dispatch.
+pub type Deliver = Arc<dyn Fn(AirPods, AirPodsEvent) + Send + Sync>;
+
+pub struct EventThrottle {
+   interval: Duration,
+   deliver: Deliver,
+   state: Arc<Mutex<State>>,
+}
+
+impl EventThrottle {
+   /// Limits each device to one event of a type per `interval`; zero
+   /// disables the limit.
+   pub fn new(interval: Duration, deliver: Deliver) -> Self {
+      Self {
+         interval,
+         deliver,
+         state: Arc::default(),
+      }
+   }
+
+   /// Delivers the event now, or holds it back until the end of the
+   /// interval, unless a newer one of the same type replaces it first.
+   ///
+   /// Deliveries happen under the lock, so they reach the queue in order.
+   pub fn admit(&self, device: &AirPods, event: AirPodsEvent) {
+      let kind = event.kind();
+      if self.interval.is_zero() {
+         (self.deliver)(device.clone(), event);
+         return;
+      }
+
+      let address = device.address();
+      let now = Instant::now();
+      let mut state = self.state.lock();
+      let State { slots, coalesced } = &mut *state;
+      if !kind.is_state_report() {
+         // Reports held back for the device go first, so none of them is
+         // dispatched after it connects or disconnects
+         for (_, slot) in slots.iter_mut().filter(|((addr, _), _)| *addr == address) {
+            if let Some((device, event)) = slot.pending.take() {
+               (self.deliver)(device, event);
+            }
+         }
+         (self.deliver)(device.clone(), event);
+         return;
+      }
+
+      let slot = slots.entry((address, kind)).or_default();
+      if slot.pending.is_some() {
+         // A delivery is already scheduled and will carry this one instead
+         slot.pending = Some((device.clone(), event));
+         *coalesced.entry(address).or_default() += 1;
+         return;
+      }
+      match slot.last.map(|last| now - last) {
+         Some(elapsed) if elapsed < self.interval => {
+            slot.pending = Some((device.clone(), event));
+            let delay = self.interval - elapsed;
+            let state = self.state.clone();
+            let deliver = self.deliver.clone();
+            tokio::spawn(async move {
+               time::sleep(delay).await;
+               let mut state = state.lock();
+               let slot = state.slots.entry((address, kind)).or_default();
+               slot.last = Some(Instant::now());
+               // Already flushed if the device connected or disconnected
+               if let Some((device, event)) = slot.pending.take() {
+                  deliver(device, event);
+               }
+            });
+         },
+         _ => {
+            slot.last = Some(now);
+            (self.deliver)(device.clone(), event);
+         },
+      }
+   }
+
+   /// Counts the events dropped in favor of a newer one, per device.
+   pub fn coalesced(&self) -> HashMap<Address, u64> {
+      self.state.lock().coalesced.clone()
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::{
+      airpods::protocol::{BatteryInfo, BudState, EarDetectionStatus, NoiseControlMode},
+      event::DisconnectReason,
+   };
+
+   fn throttle() -> (
+      EventThrottle,
+      AirPods,
+      tokio::sync::mpsc::UnboundedReceiver<AirPodsEvent>,
+   ) {
+      let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+      let throttle = EventThrottle::new(
+         Duration::from_millis(50),
+         Arc::new(move |_, event| {
+            let _ = tx.send(event);
+         }),
+      );
+      let device = AirPods::new(
+         "AA:BB:CC:DD:EE:FF".parse().unwrap(),
+         "Test AirPods".to_string(),
+         None,
+         None,
+      );
+      (throttle, device, rx)
+   }
+
+   fn battery(level: u8) -> AirPodsEvent {
+      let mut battery = BatteryInfo::new();
+      battery.left.level = level;
+      AirPodsEvent::BatteryUpdated(battery)
+   }
+
+   #[tokio::test]
+   async fn flood_is_coalesced_to_latest() {
+      let (throttle, device, mut rx) = throttle();
+
+      throttle.admit(&device, battery(10));
+      for level in 11..=15 {
+         throttle.admit(&device, battery(level));
+      }
+      // Other event types have their own budget
+      throttle.admit(
+         &device,
+         AirPodsEvent::NoiseControlChanged(NoiseControlMode::Off),
+      );
+      assert!(matches!(rx.try_recv(), Ok(AirPodsEvent::BatteryUpdated(b)) if b.left.level == 10));
+      assert!(matches!(
+         rx.try_recv(),
+         Ok(AirPodsEvent::NoiseControlChanged(_))
+      ));
+      assert!(rx.try_recv().is_err());
+
+      let Some(AirPodsEvent::BatteryUpdated(delivered)) = rx.recv().await else {
+         panic!("expected the held back battery update");
+      };
+      assert_eq!(delivered.left.level, 15);
+      assert_eq!(throttle.coalesced().get(&device.address()), Some(&4));
+   }
+
+   #[tokio::test]
+   async fn held_back_reports_precede_a_disconnect() {
+      let (throttle, device, mut rx) = throttle();
+
+      let ears = AirPodsEvent::EarDetectionChanged(EarDetectionStatus::from_states(
+         BudState::Out,
+         BudState::Out,
+      ));
+      throttle.admit(&device, ears.clone());
+      throttle.admit(&device, ears);
+      throttle.admit(
+         &device,
+         AirPodsEvent::DeviceDisconnected(DisconnectReason::Timeout),
+      );
+
+      assert!(matches!(
+         rx.try_recv(),
+         Ok(AirPodsEvent::EarDetectionChanged(_))
+      ));
+      assert!(matches!(
+         rx.try_recv(),
+         Ok(AirPodsEvent::EarDetectionChanged(_))
+      ));
+      assert!(matches!(
+         rx.try_recv(),
+         Ok(AirPodsEvent::DeviceDisconnected(_))
+      ));
+      // Nothing is left for the timer to deliver afterwards
+      time::sleep(Duration::from_millis(80)).await;
+      assert!(rx.try_recv().is_err());
+   }
+}
@@ -0,0 +1,65 @@
+//! Runtime-reloadable logging.
+//!
+//! This module wraps `env_logger` with a filter that can be swapped while
+//! the service is running, so verbosity can be raised without restarting
+//! and dropping active AAP sessions.
+
+use std::{env, sync::LazyLock};
+
+use env_filter::{Filter, ParseError};
+use log::{LevelFilter, Log, Metadata, Record};
+use parking_lot::RwLock;
+
+static FILTER: LazyLock<RwLock<Filter>> =
+   LazyLock::new(|| RwLock::new(env_filter::Builder::new().build()));
+
+struct ReloadableLogger {
+   inner: env_logger::Logger,
+}
+
+impl Log for ReloadableLogger {
+   fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+      FILTER.read().enabled(metadata)
+   }
+
+   fn log(&self, record: &Record<'_>) {
+      if FILTER.read().matches(record) {
+         self.inner.log(record);
+      }
+   }
+
+   fn flush(&self) {
+      self.inner.flush();
+   }
+}
+
+/// Parses a filter string, rejecting invalid directives.
+fn parse_filter(filter: &str) -> Result<Filter, ParseError> {
+   Ok(env_filter::Builder::new().try_parse(filter)?.build())
+}
+
+/// Installs the global logger.
+///
+/// `RUST_LOG` takes precedence over `default_filter`, matching `env_logger`.
+pub fn init(default_filter: &str) {
+   let spec = env::var("RUST_LOG").unwrap_or_else(|_| default_filter.to_owned());
+   let filter = env_filter::Builder::new().parse(&spec).build();
+   log::set_max_level(filter.filter());
+   *FILTER.write() = filter;
+
+   // The inner logger only formats; all filtering happens in `FILTER`
+   let inner = env_logger::Builder::from_env(env_logger::Env::new().write_style("RUST_LOG_STYLE"))
+      .filter_level(LevelFilter::Trace)
+      .build();
+   if log::set_boxed_logger(Box::new(ReloadableLogger { inner })).is_err() {
+      eprintln!("Logger already initialized");
+   }
+}
+
+/// Replaces the active log filter.
+pub fn set_filter(filter: &str) -> Result<(), ParseError> {
+   let filter = parse_filter(filter)?;
+   log::set_max_level(filter.filter());
+   *FILTER.write() = filter;
+   Ok(())
+}
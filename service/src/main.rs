@@ -4,28 +4,64 @@
 //! in KDE Plasma, including battery monitoring, noise control, and
 //! feature management.
 
-use std::{sync::Arc, time::Duration};
+use std::{
+   collections::HashMap,
+   panic::AssertUnwindSafe,
+   sync::{
+      Arc,
+      atomic::{AtomicUsize, Ordering},
+   },
+   time::Duration,
+};
 
-use crossbeam::queue::SegQueue;
-use log::{info, warn};
-use tokio::{signal, sync::Notify, time};
-use zbus::{Connection, connection, object_server::InterfaceRef};
+use bluer::Address;
+use futures::{FutureExt, StreamExt, future::BoxFuture};
+use log::{debug, error, info, warn};
+use parking_lot::Mutex;
+use tokio::{
+   select, signal,
+   sync::{Mutex as AsyncMutex, Notify, mpsc},
+   task::{self, JoinHandle},
+   time::{self, Instant},
+};
+use zbus::{
+   Connection, connection,
+   fdo::{DBusProxy, RequestNameFlags, RequestNameReply},
+   object_server::{InterfaceRef, SignalEmitter},
+};
 
 use bluetooth::manager::BluetoothManager;
 use dbus::AirPodsService;
-use event::{AirPodsEvent, EventBus};
+use event::{AirPodsEvent, DisconnectReason, EventBus, EventKind};
 
 mod airpods;
 mod battery_study;
 mod bluetooth;
 mod config;
 mod dbus;
+mod device_state;
 mod error;
 mod event;
+mod event_throttle;
+mod logging;
 mod media_control;
+mod metrics;
+mod notifications;
+mod quiet_hours;
 mod ringbuf;
+mod sd_notify;
+mod signal_trend;
+#[cfg(feature = "testing")]
+mod simulate;
+mod smoothing;
+mod socket;
 
-use crate::{airpods::device::AirPods, dbus::AirPodsServiceSignals, error::Result};
+use crate::{
+   airpods::{device::AirPods, protocol::NoiseControlMode},
+   dbus::AirPodsServiceSignals,
+   device_state::DeviceState,
+   error::{AirPodsError, Result},
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -48,21 +84,21 @@ async fn main() -> Result<()> {
          arg => {
             eprintln!("Unknown argument: {arg}");
             eprintln!("Try '{} --help' for more information.", args[0]);
-            std::process::exit(1);
+            return Err(AirPodsError::InvalidArgument(arg.to_string()));
          },
       }
    }
 
-   let (config, config_err) = match config::Config::load() {
+   let (mut config, config_err) = match config::Config::load_unvalidated() {
       Ok(config) => (config, None),
       Err(e) => (config::Config::default(), Some(e)),
    };
 
    let default_filter = config.log_filter.as_deref().unwrap_or("info");
-   env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_filter))
-      .init();
+   logging::init(default_filter);
    info!("Starting kAirPods D-Bus service...");
 
+   config.validate();
    if let Some(err) = config_err {
       warn!("Failed to load configuration: {err:?}");
    } else {
@@ -72,8 +108,86 @@ async fn main() -> Result<()> {
       );
    }
 
+   media_control::set_resume_on_reinsert(config.resume_on_reinsert);
+   media_control::set_media_target(config.media_target);
+   media_control::set_resume_mode(config.resume_mode);
+   media_control::set_allowed_players(config.media_allowed_players.clone());
+   media_control::set_single_bud_mode(config.single_bud_mode);
+   media_control::set_ignore_held(config.ignore_held);
+   media_control::set_pause_only_when_worn(config.pause_only_when_worn);
+   media_control::set_pause_on(config.pause_on);
+   media_control::set_skip_idle_players(config.skip_idle_players);
+   media_control::set_suppress_during_calls(config.suppress_during_calls);
+   media_control::set_call_apps(config.call_apps.clone());
+   quiet_hours::set_quiet_hours(config.quiet_hours);
+   if !config.media_control_device.is_empty() {
+      match config.media_control_device.parse() {
+         Ok(addr) => media_control::set_media_control_device(Some(addr)),
+         Err(e) => warn!(
+            "Ignoring invalid media_control_device {:?}: {e}",
+            config.media_control_device
+         ),
+      }
+   }
+
+   let notifier = config.desktop_notifications.then(|| {
+      notifications::Notifier::new(
+         config.notification_events.clone(),
+         config.low_battery_level,
+         Duration::from_secs(config.notification_interval_sec),
+      )
+   });
+
+   if let Err(e) = smoothing::set_rssi_alpha(config.rssi_smoothing_alpha) {
+      warn!("Ignoring invalid configuration: {e}");
+   }
+   if let Err(e) = smoothing::set_battery_alpha(config.battery_smoothing_alpha) {
+      warn!("Ignoring invalid configuration: {e}");
+   }
+   if let Err(e) = signal_trend::set_threshold(config.signal_trend_threshold) {
+      warn!("Ignoring invalid configuration: {e}");
+   }
+   if let Err(e) = media_control::set_one_out_duck_factor(config.one_out_duck_factor) {
+      warn!("Ignoring invalid configuration: {e}");
+   }
+
    // Create event channel
-   let event_bus = EventProcessor::new();
+   let event_bus = EventProcessor::new(
+      Duration::from_secs(config.disconnect_pause_grace_sec),
+      Duration::from_millis(config.devices_changed_interval_ms),
+      Duration::from_millis(config.startup_quiet_period_ms),
+      config.tiered_ear_policy,
+      config.signal_filter.clone(),
+      Duration::from_millis(config.event_rate_limit_ms),
+      notifier,
+      Arc::new(Mpris),
+   );
+
+   // Claim the bus name before touching any device, so a second instance
+   // never drives the AirPods alongside the first
+   let socket_path = config.socket_path.clone();
+   let metrics_addr = config.metrics_addr;
+   let connection = match connect_session().await {
+      Ok(connection) => Some(connection),
+      Err(e) if socket_path.is_some() => {
+         warn!("No session bus ({e}), serving only the control socket");
+         None
+      },
+      Err(e) => return Err(e.into()),
+   };
+   let name_lost = Arc::new(Notify::new());
+   if let Some(connection) = &connection {
+      if !acquire_bus_name(
+         connection,
+         config.wait_for_bus_name,
+         config.replace_running_instance,
+      )
+      .await?
+      {
+         return Err(AirPodsError::BusNameTaken(BUS_NAME));
+      }
+      watch_bus_name(connection, name_lost.clone()).await?;
+   }
 
    // Initialize battery study database
    let battery_study = match battery_study::BatteryStudy::open() {
@@ -87,181 +201,1259 @@ async fn main() -> Result<()> {
       },
    };
 
+   let media_noise_modes = config.noise_control_follows_media.then(|| {
+      (
+         config.playing_noise_mode.parse::<NoiseControlMode>(),
+         config.paused_noise_mode.parse::<NoiseControlMode>(),
+      )
+   });
+
+   let device_state = match DeviceState::open(config.restore_settings_on_connect) {
+      Ok(state) => state,
+      Err(e) => {
+         warn!("Failed to load device state, changes won't be saved: {e}");
+         DeviceState::in_memory(config.restore_settings_on_connect)
+      },
+   };
+
    // Create Bluetooth manager with event sender and config
-   let bluetooth_manager = BluetoothManager::new(event_bus.clone(), config, battery_study).await?;
+   let bluetooth_manager = BluetoothManager::new(
+      event_bus.clone(),
+      config,
+      battery_study.clone(),
+      Arc::new(device_state),
+   )
+   .await?;
+
+   match media_noise_modes {
+      Some((Ok(playing_mode), Ok(paused_mode))) => {
+         spawn_noise_control_follows_media(bluetooth_manager.clone(), playing_mode, paused_mode);
+      },
+      Some(_) => {
+         warn!("Ignoring noise_control_follows_media: invalid playing or paused noise mode")
+      },
+      None => {},
+   }
 
    // Create D-Bus service
-   let service = AirPodsService::new(bluetooth_manager);
+   #[cfg(feature = "testing")]
+   let debug_service = dbus::DebugService::new(bluetooth_manager.clone(), event_bus.clone());
+   let quit = Arc::new(Notify::new());
+   let socket_service = AirPodsService::new(
+      bluetooth_manager.clone(),
+      battery_study.clone(),
+      quit.clone(),
+   );
+   let service = AirPodsService::new(bluetooth_manager.clone(), battery_study, quit.clone());
 
-   // Build D-Bus connection
-   let connection = connection::Builder::session()?
-      .name("org.kairpods")?
-      .serve_at("/org/kairpods/manager", service)?
-      .build()
-      .await?;
+   // Serve the interfaces
+   if let Some(connection) = &connection {
+      let object_server = connection.object_server();
+      object_server.at("/org/kairpods/manager", service).await?;
+      #[cfg(feature = "testing")]
+      {
+         warn!("Built with the testing feature, serving org.kairpods.debug");
+         object_server
+            .at("/org/kairpods/manager", debug_service)
+            .await?;
+      }
 
-   info!("kAirPods D-Bus service started at org.kairpods");
+      info!("kAirPods D-Bus service started at {BUS_NAME}");
+   }
+
+   if let Some(path) = &socket_path {
+      let emitter = connection
+         .as_ref()
+         .map(|connection| SignalEmitter::new(connection, "/org/kairpods/manager"))
+         .transpose()?;
+      socket::SocketServer::new(socket_service, emitter)
+         .listen(path.clone())
+         .await?;
+   }
+
+   // Metrics are optional, so a listener that can't bind doesn't stop startup
+   let metrics = match metrics_addr {
+      Some(addr) => {
+         match metrics::serve(addr, bluetooth_manager.clone(), event_bus.clone()).await {
+            Ok(handle) => Some(handle),
+            Err(e) => {
+               warn!("Failed to serve metrics at {addr}, continuing without them: {e}");
+               None
+            },
+         }
+      },
+      None => None,
+   };
 
    // Start event processor
-   event_bus.spawn_dispatcher(connection).await?;
+   let dispatcher = match connection {
+      Some(connection) => event_bus.clone().spawn_dispatcher(connection).await?,
+      None => event_bus.clone().spawn_discarder(),
+   };
+   let watchdog =
+      sd_notify::watchdog_timeout().map(|timeout| event_bus.clone().spawn_watchdog(timeout));
+   sd_notify::notify(sd_notify::READY);
 
    // Wait for shutdown signal
-   signal::ctrl_c().await?;
-   info!("Shutting down kAirPods service...");
+   select! {
+      result = signal::ctrl_c() => {
+         result?;
+         info!("Shutting down kAirPods service...");
+      },
+      () = name_lost.notified() => {
+         info!("Shutting down kAirPods service after losing {BUS_NAME}...");
+      },
+      () = quit.notified() => {
+         info!("Shutting down kAirPods service on request...");
+      },
+   }
+
+   sd_notify::notify(sd_notify::STOPPING);
+   if let Some(watchdog) = watchdog {
+      watchdog.abort();
+   }
+   if let Some(metrics) = metrics {
+      metrics.abort();
+   }
+   if time::timeout(SHUTDOWN_DISCONNECT_TIMEOUT, bluetooth_manager.shutdown())
+      .await
+      .is_err()
+   {
+      warn!("Timed out disconnecting devices");
+   }
+   // Let queued events reach subscribers before exiting
+   event_bus.shutdown();
+   if time::timeout(SHUTDOWN_DRAIN_TIMEOUT, dispatcher)
+      .await
+      .is_err()
+   {
+      warn!("Timed out draining pending events");
+   }
+   if let Some(path) = &socket_path {
+      socket::remove(path);
+   }
+
+   Ok(())
+}
+
+/// Maximum time to spend disconnecting devices on shutdown
+const SHUTDOWN_DISCONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Maximum time to spend dispatching queued events on shutdown
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Well-known name the service is reachable at
+const BUS_NAME: &str = "org.kairpods";
+
+async fn connect_session() -> zbus::Result<Connection> {
+   connection::Builder::session()?.build().await
+}
+
+/// Requests the bus name, returning `false` if another process owns it.
+///
+/// With `wait`, the request is queued instead and this resolves once the
+/// current owner releases the name. With `replace`, the name is taken over
+/// from a running instance that also set it, which then shuts down.
+async fn acquire_bus_name(connection: &Connection, wait: bool, replace: bool) -> Result<bool> {
+   let dbus = DBusProxy::new(connection).await?;
+   let mut acquired = dbus
+      .receive_name_acquired_with_args(&[(0, BUS_NAME)])
+      .await?;
 
+   let flags = [
+      (replace, RequestNameFlags::AllowReplacement),
+      (replace, RequestNameFlags::ReplaceExisting),
+      (!wait, RequestNameFlags::DoNotQueue),
+   ]
+   .into_iter()
+   .filter_map(|(set, flag)| set.then_some(flag))
+   .collect();
+   match connection.request_name_with_flags(BUS_NAME, flags).await {
+      Ok(RequestNameReply::InQueue) => {
+         info!("{BUS_NAME} is owned by another process, waiting for it to be released");
+         acquired.next().await;
+         info!("Acquired {BUS_NAME}");
+         Ok(true)
+      },
+      Ok(_) => Ok(true),
+      Err(zbus::Error::NameTaken) => {
+         error!(
+            "{BUS_NAME} is already owned by another process, is kairpodsd already running? \
+             Set wait_for_bus_name to wait for it, or replace_running_instance to take over"
+         );
+         Ok(false)
+      },
+      Err(e) => Err(e.into()),
+   }
+}
+
+/// Watches for the bus name being taken over by another process, notifying
+/// `name_lost` so the service shuts down rather than driving the devices
+/// alongside the new owner.
+async fn watch_bus_name(connection: &Connection, name_lost: Arc<Notify>) -> Result<()> {
+   let dbus = DBusProxy::new(connection).await?;
+   let mut lost = dbus.receive_name_lost_with_args(&[(0, BUS_NAME)]).await?;
+   tokio::spawn(async move {
+      if lost.next().await.is_some() {
+         error!("Lost {BUS_NAME} to another process, exiting");
+         name_lost.notify_one();
+      }
+   });
    Ok(())
 }
 
+/// Switches connected devices between noise modes as media starts and stops.
+fn spawn_noise_control_follows_media(
+   manager: BluetoothManager,
+   playing_mode: NoiseControlMode,
+   paused_mode: NoiseControlMode,
+) {
+   let (tx, mut rx) = mpsc::channel(1);
+   tokio::spawn(async move {
+      if let Err(e) = media_control::watch_playback(tx).await {
+         warn!("Failed to watch media playback: {e}");
+      }
+   });
+   tokio::spawn(async move {
+      while let Some(playing) = rx.recv().await {
+         let mode = if playing { playing_mode } else { paused_mode };
+         for device in manager.all_devices().await {
+            if !device.is_connected()
+               || !media_control::controls_media(device.address())
+               || device.noise_mode() == Some(mode)
+            {
+               continue;
+            }
+            match device.set_noise_control(mode).await {
+               Ok(applied) => info!("{}: Switched to {applied} for media", device.address()),
+               Err(e) => warn!("{}: Failed to switch to {mode}: {e}", device.address()),
+            }
+         }
+      }
+   });
+}
+
 struct EventProcessor {
-   queue: SegQueue<(AirPods, AirPodsEvent)>,
-   notifier: Notify,
+   rx: AsyncMutex<mpsc::UnboundedReceiver<(AirPods, AirPodsEvent)>>,
+   /// Events emitted but not yet taken by the dispatcher
+   queued: Arc<AtomicUsize>,
+   /// Coalesces floods of repeated events and queues the rest
+   throttle: event_throttle::EventThrottle,
+   shutdown: Notify,
+   /// How long a device must stay disconnected before media is paused
+   pause_grace: Duration,
+   pending_pauses: Mutex<HashMap<Address, JoinHandle<()>>>,
+   /// Whether each device had at least one bud in ear at the last ear event
+   worn: Mutex<HashMap<Address, bool>>,
+   /// Duck media when one bud comes out and pause when both do
+   tiered_ear_policy: bool,
+   /// How many buds each device had in ear at the last ear event, for the
+   /// tiered policy
+   ear_tiers: Mutex<HashMap<Address, EarTier>>,
+   /// Minimum time between `devices_changed` emissions
+   devices_changed_interval: Duration,
+   devices_changed_throttle: Mutex<Throttle>,
+   /// Event types emitted as signals, all when unset
+   signal_filter: Option<Vec<EventKind>>,
+   /// Posts desktop notifications, when enabled
+   notifier: Option<notifications::Notifier>,
+   /// Plays and pauses media on ear and connection changes
+   media: Arc<dyn MediaActions>,
+   /// When the dispatcher started on the current event, while it is busy
+   dispatching_since: Mutex<Option<Instant>>,
 }
 
-impl EventProcessor {
-   fn new() -> Arc<Self> {
-      Arc::new(Self {
-         queue: SegQueue::new(),
-         notifier: Notify::new(),
+/// Receives the signals and property changes produced by the dispatcher.
+trait SignalSink: Send + Sync {
+   /// Emits the signal announcing a device event.
+   fn event_signal<'a>(
+      &'a self,
+      address: &'a str,
+      event: &'a AirPodsEvent,
+   ) -> BoxFuture<'a, zbus::Result<()>>;
+
+   /// Emits `DevicesChanged`.
+   fn devices_changed(&self) -> BoxFuture<'_, zbus::Result<()>>;
+
+   /// Emits the changes of the connected count and device list properties.
+   fn connections_changed(&self) -> BoxFuture<'_, zbus::Result<()>>;
+}
+
+impl SignalSink for InterfaceRef<AirPodsService> {
+   fn event_signal<'a>(
+      &'a self,
+      address: &'a str,
+      event: &'a AirPodsEvent,
+   ) -> BoxFuture<'a, zbus::Result<()>> {
+      Box::pin(async move {
+         match event {
+            AirPodsEvent::DeviceConnected => self.device_connected(address).await,
+            AirPodsEvent::DeviceDisconnected(reason) => {
+               self.device_disconnected(address, reason.to_str()).await
+            },
+            AirPodsEvent::ConnectionStateChanged(state) => {
+               self.connection_state_changed(address, state.to_str()).await
+            },
+            AirPodsEvent::BatteryUpdated(battery) => {
+               self
+                  .battery_updated(address, &battery.to_json().to_string())
+                  .await
+            },
+            AirPodsEvent::NoiseControlChanged(mode) => {
+               self.noise_control_changed(address, mode.to_str()).await
+            },
+            AirPodsEvent::FeatureChanged(feature, enabled) => {
+               self
+                  .feature_changed(address, feature.to_str(), *enabled)
+                  .await
+            },
+            AirPodsEvent::EarDetectionChanged(ear_detection) => {
+               self
+                  .ear_detection_changed(address, &ear_detection.to_json().to_string())
+                  .await
+            },
+            AirPodsEvent::DeviceNameChanged(name) => self.device_name_changed(address, name).await,
+            AirPodsEvent::SpatialStateChanged(active) => {
+               self.spatial_state_changed(address, *active).await
+            },
+            AirPodsEvent::VolumeChanged(volume) => self.volume_changed(address, *volume).await,
+            AirPodsEvent::RawFrame(frame) => self.raw_event(address, &frame.to_string()).await,
+            AirPodsEvent::DeviceError => self.device_error(address).await,
+         }
+      })
+   }
+
+   fn devices_changed(&self) -> BoxFuture<'_, zbus::Result<()>> {
+      Box::pin(async move {
+         self
+            .get_mut()
+            .await
+            .emit_devices_changed(self.signal_emitter())
+            .await
       })
    }
+
+   fn connections_changed(&self) -> BoxFuture<'_, zbus::Result<()>> {
+      Box::pin(async move {
+         let iface = self.get_mut().await;
+         iface.connected_count_changed(self.signal_emitter()).await?;
+         iface.connected_devices_changed(self.signal_emitter()).await
+      })
+   }
+}
+
+/// Media side effects of device events.
+trait MediaActions: Send + Sync {
+   /// Whether automatic play/pause is on.
+   fn is_enabled(&self) -> bool;
+   fn play(&self) -> BoxFuture<'_, ()>;
+   fn pause(&self) -> BoxFuture<'_, ()>;
+   /// Lowers the volume of playing media until [`Self::unduck`].
+   fn duck(&self) -> BoxFuture<'_, ()>;
+   fn unduck(&self) -> BoxFuture<'_, ()>;
+}
+
+/// Controls players through MPRIS.
+struct Mpris;
+
+impl MediaActions for Mpris {
+   fn is_enabled(&self) -> bool {
+      media_control::is_enabled()
+   }
+
+   fn play(&self) -> BoxFuture<'_, ()> {
+      Box::pin(media_control::send_play())
+   }
+
+   fn pause(&self) -> BoxFuture<'_, ()> {
+      Box::pin(media_control::send_pause())
+   }
+
+   fn duck(&self) -> BoxFuture<'_, ()> {
+      Box::pin(media_control::hold_duck(
+         media_control::one_out_duck_factor(),
+      ))
+   }
+
+   fn unduck(&self) -> BoxFuture<'_, ()> {
+      Box::pin(media_control::restore_held_duck())
+   }
+}
+
+/// How many buds are in ear, for the tiered ear policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EarTier {
+   BothIn,
+   OneOut,
+   BothOut,
+}
+
+impl EarTier {
+   /// With `ignore_held`, a bud held in hand counts as in ear, as it does for
+   /// plain play/pause.
+   const fn from_status(status: airpods::protocol::EarDetectionStatus, ignore_held: bool) -> Self {
+      let left = status.is_left_in_ear() || (ignore_held && status.is_left_held());
+      let right = status.is_right_in_ear() || (ignore_held && status.is_right_held());
+      match (left, right) {
+         (true, true) => Self::BothIn,
+         (false, false) => Self::BothOut,
+         _ => Self::OneOut,
+      }
+   }
+}
+
+/// Tracks `devices_changed` emissions so bursts collapse into one.
+#[derive(Default)]
+struct Throttle {
+   last: Option<Instant>,
+   /// A trailing emission is already scheduled and will cover new changes
+   pending: bool,
+   /// Until then, changes from the initial connects are held back and
+   /// emitted once at its end
+   quiet_until: Option<Instant>,
 }
 
 impl EventProcessor {
-   async fn recv(self: &Arc<Self>) -> Option<(AirPods, AirPodsEvent)> {
-      loop {
-         if let Some(event) = self.queue.pop() {
-            return Some(event);
+   #[allow(clippy::too_many_arguments)]
+   fn new(
+      pause_grace: Duration,
+      devices_changed_interval: Duration,
+      startup_quiet_period: Duration,
+      tiered_ear_policy: bool,
+      signal_filter: Option<Vec<EventKind>>,
+      event_interval: Duration,
+      notifier: Option<notifications::Notifier>,
+      media: Arc<dyn MediaActions>,
+   ) -> Arc<Self> {
+      let (tx, rx) = mpsc::unbounded_channel();
+      let queued = Arc::<AtomicUsize>::default();
+      let deliver = {
+         let queued = queued.clone();
+         Arc::new(move |device, event| enqueue(&tx, &queued, device, event))
+      };
+      Arc::new(Self {
+         rx: AsyncMutex::new(rx),
+         queued,
+         throttle: event_throttle::EventThrottle::new(event_interval, deliver),
+         shutdown: Notify::new(),
+         pause_grace,
+         pending_pauses: Mutex::new(HashMap::new()),
+         worn: Mutex::new(HashMap::new()),
+         tiered_ear_policy,
+         ear_tiers: Mutex::new(HashMap::new()),
+         devices_changed_interval,
+         devices_changed_throttle: Mutex::new(Throttle {
+            quiet_until: Some(Instant::now() + startup_quiet_period),
+            ..Default::default()
+         }),
+         signal_filter,
+         notifier,
+         media,
+         dispatching_since: Mutex::new(None),
+      })
+   }
+
+   /// Pauses media once the grace period elapses, unless cancelled by a reconnect.
+   ///
+   /// Only a link that dropped on its own counts; disconnects the user asked
+   /// for, including shutting the service down, leave media alone.
+   fn schedule_disconnect_pause(self: &Arc<Self>, address: Address, reason: DisconnectReason) {
+      if !matches!(reason, DisconnectReason::Timeout | DisconnectReason::Error) {
+         return;
+      }
+      if !self.media.is_enabled() {
+         return;
+      }
+      if quiet_hours::suppresses_media() {
+         debug!("{address}: Quiet hours, not pausing media on disconnect");
+         return;
+      }
+      let grace = self.pause_grace;
+      let this = self.clone();
+      // Held until the handle is stored, so the task can't finish first
+      let mut pending = self.pending_pauses.lock();
+      let handle = tokio::spawn(async move {
+         time::sleep(grace).await;
+         debug!("{address} still disconnected after {grace:?}, pausing media");
+         this.media.pause().await;
+         let mut pending = this.pending_pauses.lock();
+         if pending
+            .get(&address)
+            .is_some_and(|handle| handle.id() == task::id())
+         {
+            pending.remove(&address);
          }
-         let notify = self.notifier.notified();
-         if let Some(event) = self.queue.pop() {
-            return Some(event);
+      });
+      if let Some(prev) = pending.insert(address, handle) {
+         prev.abort();
+      }
+   }
+
+   /// Plays, ducks or pauses media as the device moves between ear tiers;
+   /// going back restores in reverse, e.g. both out to one in resumes ducked.
+   async fn apply_ear_tier(&self, address: Address, tier: EarTier) {
+      // Before the first ear event nothing is being listened to
+      let prev = self
+         .ear_tiers
+         .lock()
+         .insert(address, tier)
+         .unwrap_or(EarTier::BothOut);
+      if prev == tier {
+         return;
+      }
+      debug!("{address}: Ear tier {prev:?} -> {tier:?}");
+      match tier {
+         EarTier::BothIn => {
+            if prev == EarTier::BothOut {
+               self.media.play().await;
+            }
+            self.media.unduck().await;
+         },
+         EarTier::OneOut => {
+            if prev == EarTier::BothOut {
+               self.media.play().await;
+            }
+            self.media.duck().await;
+         },
+         EarTier::BothOut => self.media.pause().await,
+      }
+   }
+
+   /// Records whether the device is worn, returning the previous state.
+   fn update_worn(&self, address: Address, worn: bool) -> Option<bool> {
+      self.worn.lock().insert(address, worn)
+   }
+
+   /// Returns how long to wait before emitting `devices_changed`, or `None`
+   /// if an already scheduled emission covers this change.
+   fn throttle_devices_changed(&self) -> Option<Duration> {
+      let mut throttle = self.devices_changed_throttle.lock();
+      if throttle.pending {
+         return None;
+      }
+      let now = Instant::now();
+      if let Some(until) = throttle.quiet_until {
+         if now < until {
+            throttle.pending = true;
+            return Some(until - now);
          }
-         if Arc::strong_count(self) == 1 {
-            return None;
+         throttle.quiet_until = None;
+      }
+      let elapsed = throttle.last.map(|last| now - last);
+      match elapsed {
+         Some(elapsed) if elapsed < self.devices_changed_interval => {
+            throttle.pending = true;
+            Some(self.devices_changed_interval - elapsed)
+         },
+         _ => {
+            throttle.last = Some(now);
+            Some(Duration::ZERO)
+         },
+      }
+   }
+
+   /// Emits `devices_changed`, coalescing bursts to at most one per interval.
+   async fn devices_changed(self: &Arc<Self>, sink: &Arc<dyn SignalSink>) -> Result<()> {
+      match self.throttle_devices_changed() {
+         Some(Duration::ZERO) => Ok(sink.devices_changed().await?),
+         Some(delay) => {
+            let this = self.clone();
+            let sink = sink.clone();
+            tokio::spawn(async move {
+               time::sleep(delay).await;
+               this.flush_devices_changed(sink.as_ref()).await;
+            });
+            Ok(())
+         },
+         None => Ok(()),
+      }
+   }
+
+   /// Emits a scheduled `devices_changed`, if there is one.
+   async fn flush_devices_changed(&self, sink: &dyn SignalSink) {
+      {
+         let mut throttle = self.devices_changed_throttle.lock();
+         if !throttle.pending {
+            return;
          }
-         let _ = time::timeout(Duration::from_secs(1), notify).await;
+         throttle.pending = false;
+         throttle.last = Some(Instant::now());
+      }
+      if let Err(e) = sink.devices_changed().await {
+         warn!("Error emitting devices changed: {e}");
       }
    }
 
+   fn cancel_disconnect_pause(&self, address: Address) {
+      if let Some(handle) = self.pending_pauses.lock().remove(&address) {
+         debug!("{address} reconnected within grace period, not pausing media");
+         handle.abort();
+      }
+   }
+}
+
+impl metrics::EventStats for EventProcessor {
+   fn queue_depth(&self) -> usize {
+      self.queued.load(Ordering::Relaxed)
+   }
+
+   fn coalesced_events(&self) -> HashMap<Address, u64> {
+      self.throttle.coalesced()
+   }
+}
+
+impl EventProcessor {
+   async fn recv(&self) -> Option<(AirPods, AirPodsEvent)> {
+      let event = self.rx.lock().await.recv().await;
+      if event.is_some() {
+         self.queued.fetch_sub(1, Ordering::Relaxed);
+      }
+      event
+   }
+
+   /// Stops the dispatcher once the events already queued are delivered.
+   fn shutdown(&self) {
+      self.shutdown.notify_one();
+   }
+
    async fn dispatch(
-      &self,
-      iface: &InterfaceRef<AirPodsService>,
+      self: &Arc<Self>,
+      sink: &Arc<dyn SignalSink>,
       (device, event): (AirPods, AirPodsEvent),
    ) -> Result<()> {
       let addr_str = device.address_str();
+      if let Some(notifier) = &self.notifier {
+         notifier.handle(&device, &event);
+      }
+      // Filtered events skip only their signal; the state and properties
+      // below are still updated
+      let kind = event.kind();
+      if self
+         .signal_filter
+         .as_ref()
+         .is_none_or(|kinds| kinds.contains(&kind))
+      {
+         sink.event_signal(addr_str, &event).await?;
+      } else {
+         debug!("{addr_str}: {kind:?} is not in the signal filter, not emitting it");
+      }
       match event {
          AirPodsEvent::DeviceConnected => {
-            iface.device_connected(addr_str).await?;
+            self.cancel_disconnect_pause(device.address());
             // Emit property changes
-            iface
-               .get_mut()
-               .await
-               .devices_changed(iface.signal_emitter())
-               .await?;
-            iface
-               .get_mut()
-               .await
-               .connected_count_changed(iface.signal_emitter())
-               .await?;
+            self.devices_changed(sink).await?;
+            sink.connections_changed().await?;
          },
-         AirPodsEvent::DeviceDisconnected => {
-            iface.device_disconnected(addr_str).await?;
+         AirPodsEvent::DeviceDisconnected(reason) => {
+            self.schedule_disconnect_pause(device.address(), reason);
+            self.worn.lock().remove(&device.address());
+            if self.ear_tiers.lock().remove(&device.address()).is_some() {
+               // Don't leave media ducked for a device that's gone, including
+               // a duck held while both buds were out
+               self.media.unduck().await;
+            }
             // Emit property changes
-            iface
-               .get_mut()
-               .await
-               .devices_changed(iface.signal_emitter())
-               .await?;
-            iface
-               .get_mut()
-               .await
-               .connected_count_changed(iface.signal_emitter())
-               .await?;
-         },
-         AirPodsEvent::BatteryUpdated(battery) => {
-            iface
-               .battery_updated(addr_str, &battery.to_json().to_string())
-               .await?;
-            // Emit property change for devices (battery state changed)
-            iface
-               .get_mut()
-               .await
-               .devices_changed(iface.signal_emitter())
-               .await?;
-         },
-         AirPodsEvent::NoiseControlChanged(mode) => {
-            iface.noise_control_changed(addr_str, mode.to_str()).await?;
-            // Emit property change for devices (noise control state changed)
-            iface
-               .get_mut()
-               .await
-               .devices_changed(iface.signal_emitter())
-               .await?;
+            self.devices_changed(sink).await?;
+            sink.connections_changed().await?;
          },
          AirPodsEvent::EarDetectionChanged(ear_detection) => {
-            iface
-               .ear_detection_changed(addr_str, &ear_detection.to_json().to_string())
-               .await?;
             // Emit property change for devices (ear detection state changed)
-            iface
-               .get_mut()
-               .await
-               .devices_changed(iface.signal_emitter())
-               .await?;
+            self.devices_changed(sink).await?;
+
+            if !media_control::controls_media(device.address()) {
+               debug!("{addr_str}: Not the media control device, ignoring for play/pause");
+               return Ok(());
+            }
+            // A bud with an ignored sensor counts as always in ear
+            let ear_detection = device.ignored_ear_sensors().apply(ear_detection);
 
             // Handle play/pause based on ear detection
             // Pause when both earbuds are removed, play when at least one is in
             let one_in_ear = ear_detection.is_left_in_ear() || ear_detection.is_right_in_ear();
-            if one_in_ear {
+            let one_held = ear_detection.is_left_held() || ear_detection.is_right_held();
+            if !one_in_ear && one_held && media_control::ignore_held() {
+               // Taken out but still in hand, e.g. to talk briefly; keep playing
+               debug!("{addr_str}: Bud held in hand, not treating it as removed");
+               return Ok(());
+            }
+            let one_in_case = ear_detection.is_left_in_case() || ear_detection.is_right_in_case();
+            if !one_in_ear
+               && !one_in_case
+               && media_control::pause_on() == media_control::PauseOn::InCase
+            {
+               // Only putting a bud away counts as done listening
+               debug!("{addr_str}: No bud in the case, not treating it as removed");
+               return Ok(());
+            }
+            let was_worn = self.update_worn(device.address(), one_in_ear);
+            if !self.media.is_enabled() {
+               // Skip the player lookups entirely
+               return Ok(());
+            }
+            if quiet_hours::suppresses_media() {
+               debug!("{addr_str}: Quiet hours, not playing or pausing media");
+               return Ok(());
+            }
+            if media_control::single_bud_mode() && was_worn == Some(one_in_ear) {
+               // Only the unused bud moved; the one being worn is unchanged
+               debug!("{addr_str}: Single-bud mode, ignoring ear change of the unused bud");
+            } else if self.tiered_ear_policy && !media_control::single_bud_mode() {
+               self
+                  .apply_ear_tier(
+                     device.address(),
+                     EarTier::from_status(ear_detection, media_control::ignore_held()),
+                  )
+                  .await;
+            } else if one_in_ear {
                // One AirPod in ear - send play command
-               media_control::send_play().await;
+               self.media.play().await;
+            } else if media_control::pause_only_when_worn() && was_worn != Some(true) {
+               // Nothing was being listened to on them, so whatever plays
+               // now (e.g. on the speakers) was started or paused by the user
+               debug!("{addr_str}: Buds were not worn, not pausing");
             } else {
                // Both AirPods are out of ear - send pause command
-               media_control::send_pause().await;
+               self.media.pause().await;
             }
          },
-         AirPodsEvent::DeviceNameChanged(name) => {
-            iface.device_name_changed(addr_str, &name).await?;
-            // Emit property change for devices (name changed)
-            iface
-               .get_mut()
-               .await
-               .devices_changed(iface.signal_emitter())
-               .await?;
-         },
-         AirPodsEvent::DeviceError => {
-            iface.device_error(addr_str).await?;
-            // Emit property change for devices (error state might affect device info)
-            iface
-               .get_mut()
-               .await
-               .devices_changed(iface.signal_emitter())
-               .await?;
+         AirPodsEvent::ConnectionStateChanged(_)
+         | AirPodsEvent::BatteryUpdated(_)
+         | AirPodsEvent::NoiseControlChanged(_)
+         | AirPodsEvent::FeatureChanged(..)
+         | AirPodsEvent::DeviceNameChanged(_)
+         | AirPodsEvent::SpatialStateChanged(_)
+         | AirPodsEvent::VolumeChanged(_)
+         | AirPodsEvent::DeviceError => {
+            // Emit property change for devices (reported state changed)
+            self.devices_changed(sink).await?;
          },
+         AirPodsEvent::RawFrame(_) => {},
       }
       Ok(())
    }
 
-   async fn spawn_dispatcher(self: Arc<Self>, connection: Connection) -> Result<()> {
+   async fn spawn_dispatcher(self: Arc<Self>, connection: Connection) -> Result<JoinHandle<()>> {
       let iface = connection
          .object_server()
          .interface::<_, AirPodsService>("/org/kairpods/manager")
          .await?;
+      let sink: Arc<dyn SignalSink> = Arc::new(iface);
+      Ok(tokio::spawn(async move {
+         loop {
+            let event = select! {
+               event = self.recv() => event,
+               () = self.shutdown.notified() => break,
+            };
+            let Some(event) = event else {
+               return;
+            };
+            *self.dispatching_since.lock() = Some(Instant::now());
+            dispatch_guarded(self.dispatch(&sink, event)).await;
+            *self.dispatching_since.lock() = None;
+         }
+
+         // Drain events queued before shutdown; later emits are dropped
+         let mut rx = self.rx.lock().await;
+         rx.close();
+         let mut drained = 0;
+         while let Ok(event) = rx.try_recv() {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            dispatch_guarded(self.dispatch(&sink, event)).await;
+            drained += 1;
+         }
+         debug!("Dispatched {drained} pending event(s) before shutdown");
+         self.flush_devices_changed(sink.as_ref()).await;
+      }))
+   }
+}
+
+impl EventProcessor {
+   /// Consumes events when there is no session bus to deliver them to.
+   fn spawn_discarder(self: Arc<Self>) -> JoinHandle<()> {
       tokio::spawn(async move {
-         while let Some(event) = self.recv().await {
-            if let Err(e) = self.dispatch(&iface, event).await {
-               warn!("Error dispatching event: {e}");
+         loop {
+            select! {
+               event = self.recv() => match event {
+                  Some((device, event)) => {
+                     debug!("{}: No session bus, dropping {event:?}", device.address_str());
+                  },
+                  None => return,
+               },
+               () = self.shutdown.notified() => return,
             }
          }
-      });
+      })
+   }
+}
 
-      Ok(())
+impl EventProcessor {
+   /// Pings the systemd watchdog at half its timeout, as long as the
+   /// dispatcher is idle or making progress; a dispatcher stuck on one event
+   /// for the whole timeout gets the service restarted.
+   fn spawn_watchdog(self: Arc<Self>, timeout: Duration) -> JoinHandle<()> {
+      info!("Pinging the systemd watchdog every {:?}", timeout / 2);
+      tokio::spawn(async move {
+         let mut interval = time::interval(timeout / 2);
+         loop {
+            interval.tick().await;
+            let busy_for = self.dispatching_since.lock().map(|since| since.elapsed());
+            match busy_for {
+               Some(busy_for) if busy_for >= timeout => {
+                  warn!("Dispatcher stuck on one event for {busy_for:?}, skipping watchdog ping");
+               },
+               _ => sd_notify::notify(sd_notify::WATCHDOG),
+            }
+         }
+      })
+   }
+}
+
+/// Awaits the dispatch of one event, logging errors and panics so that the
+/// events after it are still delivered.
+async fn dispatch_guarded(dispatch: impl Future<Output = Result<()>>) {
+   match AssertUnwindSafe(dispatch).catch_unwind().await {
+      Ok(Ok(())) => {},
+      Ok(Err(e)) => warn!("Error dispatching event: {e}"),
+      Err(panic) => {
+         let msg = panic
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown cause");
+         error!("Event handler panicked, skipping event: {msg}");
+      },
    }
 }
 
 impl EventBus for EventProcessor {
    fn emit(&self, device: &AirPods, event: AirPodsEvent) {
-      self.queue.push((device.clone(), event));
-      self.notifier.notify_waiters();
+      self.throttle.admit(device, event);
+   }
+}
+
+fn enqueue(
+   tx: &mpsc::UnboundedSender<(AirPods, AirPodsEvent)>,
+   queued: &AtomicUsize,
+   device: AirPods,
+   event: AirPodsEvent,
+) {
+   // Counted before sending so the dispatcher never takes it below zero
+   queued.fetch_add(1, Ordering::Relaxed);
+   if tx.send((device, event)).is_err() {
+      // Closed by the drain on shutdown
+      queued.fetch_sub(1, Ordering::Relaxed);
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+   use super::*;
+   use crate::{
+      airpods::protocol::{BatteryInfo, BudState, EarDetectionStatus, IgnoredEarSensors},
+      event::RecordingBus,
+   };
+
+   impl SignalSink for RecordingBus {
+      fn event_signal<'a>(
+         &'a self,
+         address: &'a str,
+         event: &'a AirPodsEvent,
+      ) -> BoxFuture<'a, zbus::Result<()>> {
+         self.record(address, event.clone());
+         Box::pin(async { Ok(()) })
+      }
+
+      fn devices_changed(&self) -> BoxFuture<'_, zbus::Result<()>> {
+         Box::pin(async { Ok(()) })
+      }
+
+      fn connections_changed(&self) -> BoxFuture<'_, zbus::Result<()>> {
+         Box::pin(async { Ok(()) })
+      }
+   }
+
+   /// Counts play and pause requests instead of talking to MPRIS.
+   #[derive(Default)]
+   struct FakeMedia {
+      disabled: bool,
+      plays: AtomicUsize,
+      pauses: AtomicUsize,
+      ducked: AtomicBool,
+   }
+
+   impl MediaActions for FakeMedia {
+      fn is_enabled(&self) -> bool {
+         !self.disabled
+      }
+
+      fn play(&self) -> BoxFuture<'_, ()> {
+         self.plays.fetch_add(1, Ordering::Relaxed);
+         Box::pin(async {})
+      }
+
+      fn pause(&self) -> BoxFuture<'_, ()> {
+         self.pauses.fetch_add(1, Ordering::Relaxed);
+         Box::pin(async {})
+      }
+
+      fn duck(&self) -> BoxFuture<'_, ()> {
+         self.ducked.store(true, Ordering::Relaxed);
+         Box::pin(async {})
+      }
+
+      fn unduck(&self) -> BoxFuture<'_, ()> {
+         self.ducked.store(false, Ordering::Relaxed);
+         Box::pin(async {})
+      }
+   }
+
+   fn processor_with(media: Arc<FakeMedia>) -> Arc<EventProcessor> {
+      EventProcessor::new(
+         Duration::ZERO,
+         Duration::ZERO,
+         Duration::ZERO,
+         false,
+         None,
+         Duration::ZERO,
+         None,
+         media,
+      )
+   }
+
+   fn test_device() -> AirPods {
+      AirPods::new(
+         Address([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]),
+         "Test AirPods".to_string(),
+         None,
+         None,
+      )
+   }
+
+   fn ears(left: BudState, right: BudState) -> AirPodsEvent {
+      AirPodsEvent::EarDetectionChanged(EarDetectionStatus::from_states(left, right))
+   }
+
+   #[tokio::test]
+   async fn emitted_event_is_received_promptly() {
+      let processor = processor_with(Arc::default());
+      let device = test_device();
+
+      let receiver = {
+         let processor = processor.clone();
+         tokio::spawn(async move { processor.recv().await })
+      };
+      processor.emit(&device, AirPodsEvent::DeviceConnected);
+
+      let (received, event) = time::timeout(Duration::from_millis(50), receiver)
+         .await
+         .expect("event not received within 50ms")
+         .unwrap()
+         .unwrap();
+      assert_eq!(received.address(), device.address());
+      assert!(matches!(event, AirPodsEvent::DeviceConnected));
+   }
+
+   #[test]
+   fn devices_changed_bursts_are_coalesced() {
+      let interval = Duration::from_secs(3600);
+      let processor = EventProcessor::new(
+         Duration::ZERO,
+         interval,
+         Duration::ZERO,
+         false,
+         None,
+         Duration::ZERO,
+         None,
+         Arc::new(Mpris),
+      );
+
+      // The first change goes out immediately, the next is deferred to the
+      // end of the interval and any further ones ride along with it
+      assert_eq!(processor.throttle_devices_changed(), Some(Duration::ZERO));
+      let delay = processor.throttle_devices_changed().unwrap();
+      assert!(!delay.is_zero() && delay <= interval);
+      assert_eq!(processor.throttle_devices_changed(), None);
+   }
+
+   #[test]
+   fn startup_changes_are_emitted_once_after_quiet_period() {
+      let quiet = Duration::from_secs(3600);
+      let processor = EventProcessor::new(
+         Duration::ZERO,
+         Duration::ZERO,
+         quiet,
+         false,
+         None,
+         Duration::ZERO,
+         None,
+         Arc::new(Mpris),
+      );
+
+      // Every change during the quiet period rides on one deferred emission
+      let delay = processor.throttle_devices_changed().unwrap();
+      assert!(!delay.is_zero() && delay <= quiet);
+      assert_eq!(processor.throttle_devices_changed(), None);
+      assert_eq!(processor.throttle_devices_changed(), None);
+   }
+
+   #[tokio::test]
+   async fn no_disconnect_pause_while_auto_pause_disabled() {
+      let processor = processor_with(Arc::new(FakeMedia {
+         disabled: true,
+         ..Default::default()
+      }));
+
+      processor.schedule_disconnect_pause(
+         Address([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]),
+         DisconnectReason::Timeout,
+      );
+      assert!(processor.pending_pauses.lock().is_empty());
+   }
+
+   #[tokio::test]
+   async fn only_dropped_links_pause_on_disconnect() {
+      let media = Arc::new(FakeMedia::default());
+      let processor = processor_with(media.clone());
+      let address = Address([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+
+      for reason in [
+         DisconnectReason::User,
+         DisconnectReason::BluetoothOff,
+         DisconnectReason::GaveUp,
+      ] {
+         processor.schedule_disconnect_pause(address, reason);
+         assert!(processor.pending_pauses.lock().is_empty(), "{reason}");
+      }
+
+      processor.schedule_disconnect_pause(address, DisconnectReason::Timeout);
+      assert!(processor.pending_pauses.lock().contains_key(&address));
+      // The finished pause leaves no handle behind
+      while processor.pending_pauses.lock().contains_key(&address) {
+         tokio::task::yield_now().await;
+      }
+      assert_eq!(media.pauses.load(Ordering::Relaxed), 1);
+   }
+
+   #[tokio::test]
+   async fn ear_changes_drive_play_and_pause() {
+      let media = Arc::new(FakeMedia::default());
+      let processor = processor_with(media.clone());
+      let bus = Arc::new(RecordingBus::default());
+      let sink: Arc<dyn SignalSink> = bus.clone();
+      let device = test_device();
+      let counts = || {
+         (
+            media.plays.load(Ordering::Relaxed),
+            media.pauses.load(Ordering::Relaxed),
+         )
+      };
+
+      // Both in: play
+      let event = ears(BudState::InEar, BudState::InEar);
+      processor
+         .dispatch(&sink, (device.clone(), event))
+         .await
+         .unwrap();
+      assert_eq!(counts(), (1, 0));
+
+      // One bud out while the other is still worn: keep playing
+      let event = ears(BudState::InCase, BudState::InEar);
+      processor
+         .dispatch(&sink, (device.clone(), event))
+         .await
+         .unwrap();
+      assert_eq!(counts(), (2, 0));
+
+      // Both out: pause
+      let event = ears(BudState::InCase, BudState::Out);
+      processor
+         .dispatch(&sink, (device.clone(), event))
+         .await
+         .unwrap();
+      assert_eq!(counts(), (2, 1));
+
+      // Still out, e.g. the second bud put in the case: pauses again, as
+      // pause_only_when_worn is off by default
+      let event = ears(BudState::InCase, BudState::InCase);
+      processor
+         .dispatch(&sink, (device.clone(), event))
+         .await
+         .unwrap();
+      assert_eq!(counts(), (2, 2));
+
+      // Every change is also announced as a signal
+      let signals = bus.take();
+      assert_eq!(signals.len(), 4);
+      assert!(signals.iter().all(|(address, event)| {
+         address == device.address_str() && matches!(event, AirPodsEvent::EarDetectionChanged(_))
+      }));
+   }
+
+   #[tokio::test]
+   async fn filtered_signals_still_drive_media() {
+      let media = Arc::new(FakeMedia::default());
+      let processor = EventProcessor::new(
+         Duration::ZERO,
+         Duration::ZERO,
+         Duration::ZERO,
+         false,
+         Some(vec![EventKind::BatteryUpdated]),
+         Duration::ZERO,
+         None,
+         media.clone(),
+      );
+      let bus = Arc::new(RecordingBus::default());
+      let sink: Arc<dyn SignalSink> = bus.clone();
+
+      let event = ears(BudState::InEar, BudState::InEar);
+      processor
+         .dispatch(&sink, (test_device(), event))
+         .await
+         .unwrap();
+      assert!(bus.take().is_empty());
+      assert_eq!(media.plays.load(Ordering::Relaxed), 1);
+
+      let event = AirPodsEvent::BatteryUpdated(BatteryInfo::new());
+      processor
+         .dispatch(&sink, (test_device(), event))
+         .await
+         .unwrap();
+      assert_eq!(bus.take().len(), 1);
+   }
+
+   #[tokio::test]
+   async fn ignored_ear_sensor_counts_as_in_ear() {
+      let media = Arc::new(FakeMedia::default());
+      let processor = processor_with(media.clone());
+      let sink: Arc<dyn SignalSink> = Arc::new(RecordingBus::default());
+      let device = test_device();
+      device.set_ignored_ear_sensors(IgnoredEarSensors {
+         ignore_right: true,
+         ..Default::default()
+      });
+
+      // The right sensor keeps reporting removal, which doesn't pause
+      for (left, right) in [
+         (BudState::InEar, BudState::InEar),
+         (BudState::InEar, BudState::Out),
+      ] {
+         processor
+            .dispatch(&sink, (device.clone(), ears(left, right)))
+            .await
+            .unwrap();
+      }
+      assert_eq!(media.pauses.load(Ordering::Relaxed), 0);
+
+      // Nor does taking out the left bud, as the right still counts as worn
+      processor
+         .dispatch(
+            &sink,
+            (device.clone(), ears(BudState::InCase, BudState::Out)),
+         )
+         .await
+         .unwrap();
+      assert_eq!(media.pauses.load(Ordering::Relaxed), 0);
+   }
+
+   #[tokio::test]
+   async fn ear_changes_are_ignored_while_auto_pause_disabled() {
+      let media = Arc::new(FakeMedia {
+         disabled: true,
+         ..Default::default()
+      });
+      let processor = processor_with(media.clone());
+      let sink: Arc<dyn SignalSink> = Arc::new(RecordingBus::default());
+
+      let event = ears(BudState::InCase, BudState::InCase);
+      processor
+         .dispatch(&sink, (test_device(), event))
+         .await
+         .unwrap();
+      assert_eq!(media.pauses.load(Ordering::Relaxed), 0);
+   }
+
+   #[tokio::test]
+   async fn tiered_policy_ducks_then_pauses() {
+      let media = Arc::new(FakeMedia::default());
+      let processor = EventProcessor::new(
+         Duration::ZERO,
+         Duration::ZERO,
+         Duration::ZERO,
+         true,
+         None,
+         Duration::ZERO,
+         None,
+         media.clone(),
+      );
+      let sink: Arc<dyn SignalSink> = Arc::new(RecordingBus::default());
+      let device = test_device();
+      let state = || {
+         (
+            media.plays.load(Ordering::Relaxed),
+            media.pauses.load(Ordering::Relaxed),
+            media.ducked.load(Ordering::Relaxed),
+         )
+      };
+
+      // (left, right, expected plays, pauses, ducked) after each change
+      let steps = [
+         (BudState::InEar, BudState::InEar, (1, 0, false)),
+         // One out: duck
+         (BudState::Out, BudState::InEar, (1, 0, true)),
+         // Both out: pause, keeping the duck for when it resumes
+         (BudState::InCase, BudState::Out, (1, 1, true)),
+         // One back in: resume, still ducked
+         (BudState::InEar, BudState::InCase, (2, 1, true)),
+         // Both in: restore the volume
+         (BudState::InEar, BudState::InEar, (2, 1, false)),
+         // Back down to both out, holding the duck
+         (BudState::Out, BudState::InEar, (2, 1, true)),
+         (BudState::Out, BudState::Out, (2, 2, true)),
+      ];
+      for (left, right, expected) in steps {
+         processor
+            .dispatch(&sink, (device.clone(), ears(left, right)))
+            .await
+            .unwrap();
+         assert_eq!(state(), expected, "after {left:?}/{right:?}");
+      }
+
+      // A device gone while both out doesn't leave the duck behind
+      let disconnected = AirPodsEvent::DeviceDisconnected(DisconnectReason::Timeout);
+      processor
+         .dispatch(&sink, (device.clone(), disconnected))
+         .await
+         .unwrap();
+      assert!(!media.ducked.load(Ordering::Relaxed));
+
+      // With ignore_held, a bud held in hand is not a removal. No other test
+      // holds a bud, so the global setting doesn't leak into them
+      media_control::set_ignore_held(true);
+      for (left, right) in [
+         (BudState::InEar, BudState::InEar),
+         (BudState::Held, BudState::InEar),
+      ] {
+         processor
+            .dispatch(&sink, (device.clone(), ears(left, right)))
+            .await
+            .unwrap();
+      }
+      media_control::set_ignore_held(false);
+      assert!(!media.ducked.load(Ordering::Relaxed));
+   }
+
+   #[tokio::test]
+   async fn handler_panic_does_not_stop_dispatch() {
+      let handled = std::sync::atomic::AtomicUsize::new(0);
+      for i in 0..3 {
+         dispatch_guarded(async {
+            assert_ne!(i, 1, "malformed event");
+            handled.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            Ok(())
+         })
+         .await;
+      }
+      assert_eq!(handled.into_inner(), 2);
    }
 }
@@ -3,14 +3,113 @@
 //! This module provides functionality to control media playback using the
 //! MPRIS (Media Player Remote Interfacing Specification) D-Bus interface.
 
-use std::sync::atomic::{AtomicBool, Ordering};
-
+use std::{
+   collections::HashMap,
+   sync::atomic::{AtomicBool, Ordering},
+   time::Duration,
+};
+
+use bluer::Address;
+use crossbeam::atomic::AtomicCell;
+use futures::StreamExt;
 use log::{debug, warn};
 use parking_lot::Mutex;
-use zbus::Connection;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use zbus::{Connection, MatchRule, MessageStream, message, zvariant::OwnedValue};
+
+/// Selects which players auto play/pause acts on.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaTarget {
+   /// Pause every playing player
+   #[default]
+   All,
+   /// Pause only the most recently active player
+   ActiveOnly,
+}
+
+/// Selects which of the players we paused are resumed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResumeMode {
+   /// Resume every player we paused
+   #[default]
+   All,
+   /// Resume only the player paused last
+   LastOnly,
+   /// Resume only the player paused first
+   FirstOnly,
+}
+
+/// Selects which removals pause media.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PauseOn {
+   /// Pause once no bud is in an ear
+   #[default]
+   AnyRemoval,
+   /// Pause only once no bud is in an ear and one is put in the case;
+   /// buds held or set down keep playing
+   InCase,
+}
+
+/// Default volume factor while one bud is out
+pub const DEFAULT_ONE_OUT_DUCK_FACTOR: f64 = 0.5;
 
 static ENABLED: AtomicBool = AtomicBool::new(false);
 
+/// Which players are paused when the `AirPods` are removed
+static MEDIA_TARGET: AtomicCell<MediaTarget> = AtomicCell::new(MediaTarget::All);
+
+/// playerctld proxies the active player and tracks player activity
+const PLAYERCTLD_SERVICE: &str = "org.mpris.MediaPlayer2.playerctld";
+
+/// Whether players we paused are resumed when an `AirPod` is reinserted
+static RESUME_ON_REINSERT: AtomicBool = AtomicBool::new(true);
+
+/// Which paused players are resumed on reinsertion
+static RESUME_MODE: AtomicCell<ResumeMode> = AtomicCell::new(ResumeMode::All);
+
+/// Players auto-pause is limited to, matched by bus name, `Identity` or
+/// `DesktopEntry`; empty means every player
+static ALLOWED_PLAYERS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Whether play/pause only reacts when the device goes from worn to unworn
+/// or back, ignoring movements of a bud that is not in use
+static SINGLE_BUD_MODE: AtomicBool = AtomicBool::new(false);
+
+/// Whether a bud held in hand counts as still worn rather than removed
+static IGNORE_HELD: AtomicBool = AtomicBool::new(false);
+
+/// Whether taking the buds out only pauses if they were being worn before
+static PAUSE_ONLY_WHEN_WORN: AtomicBool = AtomicBool::new(false);
+
+/// Which removals pause media
+static PAUSE_ON: AtomicCell<PauseOn> = AtomicCell::new(PauseOn::AnyRemoval);
+
+/// The only device whose ear detection drives play/pause; `None` means any
+static MEDIA_CONTROL_DEVICE: AtomicCell<Option<Address>> = AtomicCell::new(None);
+
+/// Whether players reporting `Playing` without a track are left alone
+static SKIP_IDLE_PLAYERS: AtomicBool = AtomicBool::new(false);
+
+/// Whether auto-pause is suppressed while a call app is playing
+static SUPPRESS_DURING_CALLS: AtomicBool = AtomicBool::new(false);
+
+/// Players treated as calls, matched like `ALLOWED_PLAYERS`
+static CALL_APPS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Set while a volume duck is in progress, so overlapping ducks don't
+/// restore the already lowered volume
+static DUCKING: AtomicBool = AtomicBool::new(false);
+
+/// Players ducked while one bud is out, with the volume to restore
+static HELD_DUCK: Mutex<Vec<(String, f64)>> = Mutex::new(Vec::new());
+
+/// Volume factor applied while one bud is out, with the tiered ear policy
+static ONE_OUT_DUCK_FACTOR: AtomicCell<f64> = AtomicCell::new(DEFAULT_ONE_OUT_DUCK_FACTOR);
+
 /// Tracks which players we paused (so we can resume all of them)
 static PAUSED_PLAYERS: Mutex<Vec<String>> = Mutex::new(Vec::new());
 
@@ -23,6 +122,140 @@ pub fn is_enabled() -> bool {
    ENABLED.load(Ordering::Relaxed)
 }
 
+pub fn set_media_target(target: MediaTarget) {
+   MEDIA_TARGET.store(target);
+   debug!("Media target set to {target:?}");
+}
+
+pub fn media_target() -> MediaTarget {
+   MEDIA_TARGET.load()
+}
+
+pub fn set_resume_mode(mode: ResumeMode) {
+   RESUME_MODE.store(mode);
+   debug!("Resume mode set to {mode:?}");
+}
+
+pub fn resume_mode() -> ResumeMode {
+   RESUME_MODE.load()
+}
+
+pub fn set_single_bud_mode(enabled: bool) {
+   SINGLE_BUD_MODE.store(enabled, Ordering::Relaxed);
+   debug!("Single-bud mode set to {enabled}");
+}
+
+pub fn single_bud_mode() -> bool {
+   SINGLE_BUD_MODE.load(Ordering::Relaxed)
+}
+
+pub fn set_ignore_held(enabled: bool) {
+   IGNORE_HELD.store(enabled, Ordering::Relaxed);
+   debug!("Ignore held set to {enabled}");
+}
+
+pub fn ignore_held() -> bool {
+   IGNORE_HELD.load(Ordering::Relaxed)
+}
+
+pub fn set_pause_only_when_worn(enabled: bool) {
+   PAUSE_ONLY_WHEN_WORN.store(enabled, Ordering::Relaxed);
+   debug!("Pause only when worn set to {enabled}");
+}
+
+pub fn pause_only_when_worn() -> bool {
+   PAUSE_ONLY_WHEN_WORN.load(Ordering::Relaxed)
+}
+
+pub fn set_pause_on(pause_on: PauseOn) {
+   PAUSE_ON.store(pause_on);
+   debug!("Pause on set to {pause_on:?}");
+}
+
+pub fn pause_on() -> PauseOn {
+   PAUSE_ON.load()
+}
+
+pub fn set_media_control_device(address: Option<Address>) {
+   MEDIA_CONTROL_DEVICE.store(address);
+   debug!("Media control device set to {address:?}");
+}
+
+pub fn media_control_device() -> Option<Address> {
+   MEDIA_CONTROL_DEVICE.load()
+}
+
+/// Whether ear detection from `address` may drive play/pause.
+pub fn controls_media(address: Address) -> bool {
+   media_control_device().is_none_or(|primary| primary == address)
+}
+
+pub fn set_skip_idle_players(enabled: bool) {
+   SKIP_IDLE_PLAYERS.store(enabled, Ordering::Relaxed);
+   debug!("Skip idle players set to {enabled}");
+}
+
+pub fn skip_idle_players() -> bool {
+   SKIP_IDLE_PLAYERS.load(Ordering::Relaxed)
+}
+
+pub fn set_suppress_during_calls(enabled: bool) {
+   SUPPRESS_DURING_CALLS.store(enabled, Ordering::Relaxed);
+   debug!("Suppress during calls set to {enabled}");
+}
+
+pub fn suppress_during_calls() -> bool {
+   SUPPRESS_DURING_CALLS.load(Ordering::Relaxed)
+}
+
+pub fn set_call_apps(apps: Vec<String>) {
+   debug!("Call apps set to {apps:?}");
+   *CALL_APPS.lock() = apps;
+}
+
+pub fn call_apps() -> Vec<String> {
+   CALL_APPS.lock().clone()
+}
+
+pub fn set_allowed_players(players: Vec<String>) {
+   debug!("Auto-pause player allow-list set to {players:?}");
+   *ALLOWED_PLAYERS.lock() = players;
+}
+
+pub fn allowed_players() -> Vec<String> {
+   ALLOWED_PLAYERS.lock().clone()
+}
+
+pub fn validate_one_out_duck_factor(factor: f64) -> Result<f64, String> {
+   if (0.0..=1.0).contains(&factor) {
+      Ok(factor)
+   } else {
+      Err(format!(
+         "one_out_duck_factor must be between 0 and 1, got {factor}"
+      ))
+   }
+}
+
+pub fn set_one_out_duck_factor(factor: f64) -> Result<(), String> {
+   let factor = validate_one_out_duck_factor(factor)?;
+   ONE_OUT_DUCK_FACTOR.store(factor);
+   debug!("One-out duck factor set to {factor}");
+   Ok(())
+}
+
+pub fn one_out_duck_factor() -> f64 {
+   ONE_OUT_DUCK_FACTOR.load()
+}
+
+pub fn set_resume_on_reinsert(enabled: bool) {
+   RESUME_ON_REINSERT.store(enabled, Ordering::Relaxed);
+   debug!("Resume on reinsert set to {enabled}");
+}
+
+pub fn resume_on_reinsert() -> bool {
+   RESUME_ON_REINSERT.load(Ordering::Relaxed)
+}
+
 /// Sends a play command to all players we previously paused.
 /// Only plays if we previously paused the media.
 pub async fn send_play() {
@@ -31,13 +264,48 @@ pub async fn send_play() {
    }
 
    // Get all players we paused
-   let paused_players = PAUSED_PLAYERS.lock().clone();
+   let mut paused_players = PAUSED_PLAYERS.lock().clone();
 
    if paused_players.is_empty() {
       debug!("No media was paused by us, skipping play command");
       return;
    }
 
+   if !resume_on_reinsert() {
+      debug!("Resume on reinsert disabled, forgetting paused player(s)");
+      PAUSED_PLAYERS.lock().clear();
+      return;
+   }
+
+   // Drop players that have left the bus since we paused them
+   match list_bus_names().await {
+      Ok(names) => {
+         let before = paused_players.len();
+         paused_players.retain(|player| names.iter().any(|name| name.as_str() == player));
+         let pruned = before - paused_players.len();
+         if pruned > 0 {
+            debug!("Pruned {pruned} stale player(s) no longer on the bus");
+         }
+      },
+      Err(e) => {
+         warn!("Failed to list D-Bus names, resuming without staleness check: {e}");
+      },
+   }
+
+   if paused_players.is_empty() {
+      debug!("All paused players have left the bus, skipping play command");
+      PAUSED_PLAYERS.lock().clear();
+      return;
+   }
+
+   match resume_mode() {
+      ResumeMode::All => {},
+      ResumeMode::LastOnly => {
+         paused_players.drain(..paused_players.len() - 1);
+      },
+      ResumeMode::FirstOnly => paused_players.truncate(1),
+   }
+
    debug!(
       "Resuming {} previously paused player(s): {:?}",
       paused_players.len(),
@@ -98,15 +366,9 @@ pub async fn send_pause() {
       },
    };
 
-   // Find all MPRIS media players (excluding KDE Connect, which is for remote control)
    let mpris_services: Vec<_> = names
       .iter()
-      .filter(|name| {
-         let name_str = name.as_str();
-         name_str.starts_with("org.mpris.MediaPlayer2.")
-            && !name_str.contains("kdeconnect")
-            && !name_str.contains("KDEConnect")
-      })
+      .filter(|name| is_local_player(name.as_str()))
       .collect();
 
    if mpris_services.is_empty() {
@@ -114,29 +376,30 @@ pub async fn send_pause() {
       return;
    }
 
+   if suppress_during_calls() && is_call_active(&mpris_services).await {
+      debug!("A call is in progress, not pausing");
+      return;
+   }
+
    debug!(
       "Found {} MPRIS player(s), checking which are playing",
       mpris_services.len()
    );
 
-   let mut paused_players = Vec::new();
-
-   // Check each player and pause all that are playing
+   // Check each player and collect all that are playing
+   let allowed = allowed_players();
+   let mut playing_players = Vec::new();
    for service_name in &mpris_services {
-      // Check if this player is playing
+      if !is_player_allowed(service_name.as_str(), &allowed).await {
+         debug!("Player {service_name} is not in the allow-list, skipping");
+         continue;
+      }
       if let Ok(was_playing) = is_player_playing(service_name.as_str()).await {
-         if was_playing {
-            debug!("Player {service_name} is playing, pausing it");
-            // Pause this player
-            match send_mpris_command_to_player("Pause", service_name.as_str()).await {
-               Ok(()) => {
-                  debug!("Successfully paused player: {service_name}");
-                  paused_players.push(service_name.as_str().to_string());
-               },
-               Err(e) => {
-                  warn!("Failed to pause player {service_name}: {e}");
-               },
-            }
+         if was_playing && skip_idle_players() && !has_track(service_name.as_str()).await {
+            debug!("Player {service_name} is playing without a track, skipping");
+         } else if was_playing {
+            debug!("Player {service_name} is playing");
+            playing_players.push(service_name.as_str().to_string());
          } else {
             debug!("Player {service_name} is not playing, skipping");
          }
@@ -145,17 +408,263 @@ pub async fn send_pause() {
       }
    }
 
+   if media_target() == MediaTarget::ActiveOnly && playing_players.len() > 1 {
+      let active = most_recent_player(&playing_players).await;
+      debug!("Targeting only the most recently active player: {active}");
+      playing_players = vec![active];
+   }
+
+   let mut paused_players = Vec::new();
+   for player_name in playing_players {
+      match send_mpris_command_to_player("Pause", &player_name).await {
+         Ok(()) => {
+            debug!("Successfully paused player: {player_name}");
+            paused_players.push(player_name);
+         },
+         Err(e) => {
+            warn!("Failed to pause player {player_name}: {e}");
+         },
+      }
+   }
+
+   record_paused(paused_players);
+}
+
+/// Stores the players a pause request actually paused, for resuming later.
+///
+/// Only players that were playing get here, so media the user paused is
+/// never resumed by us. A request that paused nothing keeps the earlier
+/// record, so a second pause (e.g. on disconnect) doesn't forget it.
+fn record_paused(paused_players: Vec<String>) {
    if paused_players.is_empty() {
       debug!("No playing players found to pause");
-   } else {
-      debug!(
-         "Paused {} player(s), storing for resume: {:?}",
-         paused_players.len(),
-         paused_players
-      );
-      // Store all paused players
-      *PAUSED_PLAYERS.lock() = paused_players;
+      return;
+   }
+   debug!(
+      "Paused {} player(s), storing for resume: {:?}",
+      paused_players.len(),
+      paused_players
+   );
+   *PAUSED_PLAYERS.lock() = paused_players;
+}
+
+/// Sends a transport command (`Next`, `Previous`, `PlayPause`, ...) to the
+/// most recently active allowed player, returning that player's bus name.
+pub async fn send_to_active_player(
+   method: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+   let allowed = allowed_players();
+   let mut players = Vec::new();
+   for name in list_bus_names().await? {
+      if is_local_player(name.as_str()) && is_player_allowed(name.as_str(), &allowed).await {
+         players.push(name.to_string());
+      }
+   }
+   if players.is_empty() {
+      return Err("No media player available".into());
+   }
+
+   let player = most_recent_player(&players).await;
+   send_mpris_command_to_player(method, &player).await?;
+   Ok(player)
+}
+
+/// Briefly lowers the volume of every playing player to `factor` of its
+/// current level, restoring it after `duration`.
+pub async fn duck_playing(factor: f64, duration: Duration) {
+   if DUCKING.swap(true, Ordering::Relaxed) {
+      debug!("Volume duck already in progress");
+      return;
+   }
+
+   let ducked = duck_players(factor).await;
+   if !ducked.is_empty() {
+      debug!("Ducked {} player(s) for {duration:?}", ducked.len());
+      tokio::time::sleep(duration).await;
+      restore_volumes(ducked).await;
+   }
+   DUCKING.store(false, Ordering::Relaxed);
+}
+
+/// Lowers the volume of playing players until [`restore_held_duck`].
+pub async fn hold_duck(factor: f64) {
+   if !HELD_DUCK.lock().is_empty() {
+      debug!("Players are already ducked");
+      return;
+   }
+   let ducked = duck_players(factor).await;
+   if !ducked.is_empty() {
+      debug!("Ducked {} player(s) until restored", ducked.len());
+      *HELD_DUCK.lock() = ducked;
+   }
+}
+
+/// Restores the volumes lowered by [`hold_duck`].
+pub async fn restore_held_duck() {
+   let ducked = std::mem::take(&mut *HELD_DUCK.lock());
+   restore_volumes(ducked).await;
+}
+
+/// Scales the volume of every playing allowed player, returning the
+/// players ducked with their previous volume.
+async fn duck_players(factor: f64) -> Vec<(String, f64)> {
+   let allowed = allowed_players();
+   let mut ducked = Vec::new();
+   match list_bus_names().await {
+      Ok(names) => {
+         for name in names {
+            let name = name.as_str();
+            if !is_local_player(name)
+               || !is_player_allowed(name, &allowed).await
+               || !is_player_playing(name).await.unwrap_or(false)
+            {
+               continue;
+            }
+            match get_player_volume(name).await {
+               Ok(volume) => match set_player_volume(name, volume * factor).await {
+                  Ok(()) => ducked.push((name.to_string(), volume)),
+                  Err(e) => warn!("Failed to duck player {name}: {e}"),
+               },
+               Err(e) => debug!("Could not read volume of player {name}: {e}"),
+            }
+         }
+      },
+      Err(e) => warn!("Failed to list D-Bus names: {e}"),
    }
+   ducked
+}
+
+async fn restore_volumes(ducked: Vec<(String, f64)>) {
+   for (name, volume) in ducked {
+      if let Err(e) = set_player_volume(&name, volume).await {
+         warn!("Failed to restore volume of player {name}: {e}");
+      }
+   }
+}
+
+/// Whether a bus name is an MPRIS player we control, excluding playerctld's
+/// proxy and KDE Connect, which is for remote control.
+fn is_local_player(name: &str) -> bool {
+   name.starts_with("org.mpris.MediaPlayer2.")
+      && name != PLAYERCTLD_SERVICE
+      && !name.contains("kdeconnect")
+      && !name.contains("KDEConnect")
+}
+
+/// Checks whether any player matching the call apps list is playing.
+async fn is_call_active(services: &[&zbus::names::OwnedBusName]) -> bool {
+   let call_apps = call_apps();
+   if call_apps.is_empty() {
+      return false;
+   }
+
+   for service_name in services {
+      let service_name = service_name.as_str();
+      if is_player_allowed(service_name, &call_apps).await
+         && is_player_playing(service_name).await.unwrap_or(false)
+      {
+         debug!("Call app {service_name} is playing");
+         return true;
+      }
+   }
+   false
+}
+
+/// Checks whether a player matches the allow-list.
+///
+/// Entries are compared case-insensitively against the bus name suffix
+/// (with and without its instance part) and the player's `Identity` and
+/// `DesktopEntry`. An empty allow-list allows every player.
+async fn is_player_allowed(service_name: &str, allowed: &[String]) -> bool {
+   if allowed.is_empty() {
+      return true;
+   }
+
+   let suffix = service_name
+      .strip_prefix("org.mpris.MediaPlayer2.")
+      .unwrap_or(service_name);
+   let mut names = vec![suffix.to_string()];
+   if let Some((base, _)) = suffix.split_once('.') {
+      names.push(base.to_string());
+   }
+   for property in ["Identity", "DesktopEntry"] {
+      match get_player_identity(service_name, property).await {
+         Ok(name) => names.push(name),
+         Err(e) => debug!("Could not read {property} of player {service_name}: {e}"),
+      }
+   }
+
+   allowed
+      .iter()
+      .any(|entry| names.iter().any(|name| name.eq_ignore_ascii_case(entry)))
+}
+
+/// Gets a string property of the player's root `org.mpris.MediaPlayer2` interface.
+async fn get_player_identity(
+   service_name: &str,
+   property: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+   let connection = Connection::session().await?;
+   let path = zbus::zvariant::ObjectPath::from_str_unchecked("/org/mpris/MediaPlayer2");
+
+   let reply = connection
+      .call_method(
+         Some(service_name),
+         &path,
+         Some("org.freedesktop.DBus.Properties"),
+         "Get",
+         &("org.mpris.MediaPlayer2", property),
+      )
+      .await?;
+
+   let body = reply.body();
+   let variant: zbus::zvariant::Value = body.deserialize()?;
+   Ok(String::try_from(variant)?)
+}
+
+/// Picks the most recently active of the given players.
+///
+/// Uses playerctld's activity-ordered player list when it is running,
+/// otherwise falls back to the first player.
+async fn most_recent_player(players: &[String]) -> String {
+   match playerctld_player_names().await {
+      Ok(names) => {
+         if let Some(name) = names.iter().find(|name| players.contains(name)) {
+            return name.clone();
+         }
+      },
+      Err(e) => debug!("playerctld not available: {e}"),
+   }
+   players[0].clone()
+}
+
+/// Gets playerctld's list of players, most recently active first.
+async fn playerctld_player_names() -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>>
+{
+   let connection = Connection::session().await?;
+   let path = zbus::zvariant::ObjectPath::from_str_unchecked("/org/mpris/MediaPlayer2");
+
+   let reply = connection
+      .call_method(
+         Some(PLAYERCTLD_SERVICE),
+         &path,
+         Some("org.freedesktop.DBus.Properties"),
+         "Get",
+         &("com.github.altdesktop.playerctld", "PlayerNames"),
+      )
+      .await?;
+
+   let body = reply.body();
+   let variant: zbus::zvariant::Value = body.deserialize()?;
+   Ok(Vec::<String>::try_from(variant)?)
+}
+
+/// Lists all names currently owned on the session bus.
+async fn list_bus_names()
+-> Result<Vec<zbus::names::OwnedBusName>, Box<dyn std::error::Error + Send + Sync>> {
+   let connection = Connection::session().await?;
+   let dbus_proxy = zbus::fdo::DBusProxy::new(&connection).await?;
+   Ok(dbus_proxy.list_names().await?)
 }
 
 /// Checks if a specific player is currently playing.
@@ -193,6 +702,149 @@ async fn is_player_playing(
    Ok(status == "Playing")
 }
 
+/// Checks whether any player we control is playing.
+async fn any_player_playing() -> bool {
+   let Ok(names) = list_bus_names().await else {
+      return false;
+   };
+   for name in names.iter().filter(|name| is_local_player(name.as_str())) {
+      if is_player_playing(name.as_str()).await.unwrap_or(false) {
+         return true;
+      }
+   }
+   false
+}
+
+/// Watches the players' `PlaybackStatus` and sends whether any of them is
+/// playing whenever that changes, until the receiver is dropped.
+pub async fn watch_playback(tx: mpsc::Sender<bool>) -> zbus::Result<()> {
+   let connection = Connection::session().await?;
+   let rule = MatchRule::builder()
+      .msg_type(message::Type::Signal)
+      .interface("org.freedesktop.DBus.Properties")?
+      .member("PropertiesChanged")?
+      .path("/org/mpris/MediaPlayer2")?
+      .arg(0, "org.mpris.MediaPlayer2.Player")?
+      .build();
+   let mut changes = MessageStream::for_match_rule(rule, &connection, None).await?;
+
+   let mut playing = any_player_playing().await;
+   while let Some(message) = changes.next().await {
+      let Ok(message) = message else {
+         continue;
+      };
+      let Ok((_, changed, _)) = message
+         .body()
+         .deserialize::<(String, HashMap<String, OwnedValue>, Vec<String>)>()
+      else {
+         continue;
+      };
+      if !changed.contains_key("PlaybackStatus") {
+         continue;
+      }
+      // Another player may still be playing, so ask all of them
+      let now = any_player_playing().await;
+      if now != playing {
+         playing = now;
+         debug!("Media {}", if now { "started" } else { "stopped" });
+         if tx.send(now).await.is_err() {
+            break;
+         }
+      }
+   }
+   Ok(())
+}
+
+/// Checks whether a player's `Metadata` describes an actual track.
+///
+/// Some players report `Playing` while idle; those expose empty metadata or
+/// a zero `mpris:length`. Players whose metadata can't be read count as
+/// having a track, so they are still paused.
+async fn has_track(service_name: &str) -> bool {
+   match get_player_metadata(service_name).await {
+      Ok(metadata) => {
+         let length = metadata.get("mpris:length").map(|v| match &**v {
+            zbus::zvariant::Value::I64(n) => *n,
+            zbus::zvariant::Value::U64(n) => i64::try_from(*n).unwrap_or(i64::MAX),
+            _ => -1,
+         });
+         !metadata.is_empty() && length != Some(0)
+      },
+      Err(e) => {
+         debug!("Could not read metadata of player {service_name}: {e}");
+         true
+      },
+   }
+}
+
+/// Gets the `Metadata` property of a player.
+async fn get_player_metadata(
+   service_name: &str,
+) -> Result<HashMap<String, zbus::zvariant::OwnedValue>, Box<dyn std::error::Error + Send + Sync>> {
+   let connection = Connection::session().await?;
+   let path = zbus::zvariant::ObjectPath::from_str_unchecked("/org/mpris/MediaPlayer2");
+
+   let reply = connection
+      .call_method(
+         Some(service_name),
+         &path,
+         Some("org.freedesktop.DBus.Properties"),
+         "Get",
+         &("org.mpris.MediaPlayer2.Player", "Metadata"),
+      )
+      .await?;
+
+   let body = reply.body();
+   let variant: zbus::zvariant::Value = body.deserialize()?;
+   Ok(HashMap::try_from(variant)?)
+}
+
+/// Gets the `Volume` property of a player (0.0-1.0).
+async fn get_player_volume(
+   service_name: &str,
+) -> Result<f64, Box<dyn std::error::Error + Send + Sync>> {
+   let connection = Connection::session().await?;
+   let path = zbus::zvariant::ObjectPath::from_str_unchecked("/org/mpris/MediaPlayer2");
+
+   let reply = connection
+      .call_method(
+         Some(service_name),
+         &path,
+         Some("org.freedesktop.DBus.Properties"),
+         "Get",
+         &("org.mpris.MediaPlayer2.Player", "Volume"),
+      )
+      .await?;
+
+   let body = reply.body();
+   let variant: zbus::zvariant::Value = body.deserialize()?;
+   Ok(f64::try_from(variant)?)
+}
+
+/// Sets the `Volume` property of a player.
+async fn set_player_volume(
+   service_name: &str,
+   volume: f64,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+   let connection = Connection::session().await?;
+   let path = zbus::zvariant::ObjectPath::from_str_unchecked("/org/mpris/MediaPlayer2");
+
+   connection
+      .call_method(
+         Some(service_name),
+         &path,
+         Some("org.freedesktop.DBus.Properties"),
+         "Set",
+         &(
+            "org.mpris.MediaPlayer2.Player",
+            "Volume",
+            zbus::zvariant::Value::from(volume),
+         ),
+      )
+      .await?;
+   Ok(())
+}
+
 /// Sends a command to a specific player by service name.
 async fn send_mpris_command_to_player(
    method: &str,
@@ -210,3 +862,26 @@ async fn send_mpris_command_to_player(
 
    Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn only_players_we_paused_are_recorded() {
+      PAUSED_PLAYERS.lock().clear();
+
+      // Nothing was playing: a user-paused player must not be recorded
+      record_paused(Vec::new());
+      assert!(PAUSED_PLAYERS.lock().is_empty());
+
+      record_paused(vec!["org.mpris.MediaPlayer2.a".to_string()]);
+      assert_eq!(*PAUSED_PLAYERS.lock(), ["org.mpris.MediaPlayer2.a"]);
+
+      // A later pause that found nothing playing keeps what we paused
+      record_paused(Vec::new());
+      assert_eq!(*PAUSED_PLAYERS.lock(), ["org.mpris.MediaPlayer2.a"]);
+
+      PAUSED_PLAYERS.lock().clear();
+   }
+}
@@ -0,0 +1,311 @@
+//! Optional Prometheus metrics endpoint.
+//!
+//! When `metrics_addr` is set, device battery levels, signal strength,
+//! connection counts, the depth of the event queue and the events coalesced
+//! by the rate limit are served in the
+//! Prometheus text format at `/metrics`. The listener speaks just enough
+//! HTTP/1.1 for a scraper, so it needs no HTTP dependency.
+
+use std::{collections::HashMap, fmt::Write as _, net::SocketAddr, sync::Arc, time::Duration};
+
+use bluer::Address;
+use log::{debug, info, warn};
+use tokio::{
+   io::{AsyncReadExt, AsyncWriteExt},
+   net::{TcpListener, TcpStream},
+   task::JoinHandle,
+   time,
+};
+
+use crate::{
+   airpods::{
+      device::AirPods,
+      protocol::{BatteryState, BatteryStatus},
+   },
+   bluetooth::manager::BluetoothManager,
+   error::Result,
+};
+
+/// Maximum size of a request head, which scrapers keep well below
+const MAX_REQUEST_LEN: usize = 8192;
+/// Time allowed for a client to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Statistics of the event pipeline.
+pub trait EventStats: Send + Sync {
+   /// Events waiting to be dispatched
+   fn queue_depth(&self) -> usize;
+   /// Events replaced by a newer one before dispatch, per device
+   fn coalesced_events(&self) -> HashMap<Address, u64>;
+}
+
+/// Binds the listener and starts serving metrics.
+pub async fn serve(
+   addr: SocketAddr,
+   manager: BluetoothManager,
+   stats: Arc<dyn EventStats>,
+) -> Result<JoinHandle<()>> {
+   let listener = TcpListener::bind(addr).await?;
+   info!("Serving metrics at http://{addr}/metrics");
+
+   Ok(tokio::spawn(async move {
+      loop {
+         match listener.accept().await {
+            Ok((stream, peer)) => {
+               let manager = manager.clone();
+               let stats = stats.clone();
+               tokio::spawn(async move {
+                  if let Err(e) = serve_client(stream, &manager, &*stats).await {
+                     debug!("Metrics client {peer} error: {e}");
+                  }
+               });
+            },
+            Err(e) => warn!("Failed to accept metrics client: {e}"),
+         }
+      }
+   }))
+}
+
+async fn serve_client(
+   mut stream: TcpStream,
+   manager: &BluetoothManager,
+   stats: &dyn EventStats,
+) -> std::io::Result<()> {
+   let Ok(head) = time::timeout(REQUEST_TIMEOUT, read_request_head(&mut stream)).await else {
+      return Ok(());
+   };
+   let head = head?;
+   let mut parts = head.split_whitespace();
+   let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+
+   let (status, body) = match (method, path.split('?').next()) {
+      ("GET", Some("/metrics")) => {
+         let devices = manager.all_devices().await;
+         (
+            "200 OK",
+            render(&devices, stats.queue_depth(), &stats.coalesced_events()),
+         )
+      },
+      ("GET", _) => ("404 Not Found", "Not found\n".to_string()),
+      _ => ("405 Method Not Allowed", "Method not allowed\n".to_string()),
+   };
+   let response = format!(
+      "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+       Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+      body.len()
+   );
+   stream.write_all(response.as_bytes()).await?;
+   stream.shutdown().await
+}
+
+/// Reads up to the blank line ending the request head.
+async fn read_request_head(stream: &mut TcpStream) -> std::io::Result<String> {
+   let mut buf = Vec::with_capacity(1024);
+   let mut chunk = [0u8; 1024];
+   while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+      let n = stream.read(&mut chunk).await?;
+      if n == 0 {
+         break;
+      }
+      buf.extend_from_slice(&chunk[..n]);
+      if buf.len() > MAX_REQUEST_LEN {
+         return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "request head too large",
+         ));
+      }
+   }
+   Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+/// Renders the metrics for the tracked devices in the Prometheus text format.
+fn render(devices: &[AirPods], queue_depth: usize, coalesced: &HashMap<Address, u64>) -> String {
+   let mut out = String::new();
+   let connected = devices.iter().filter(|d| d.is_connected()).count();
+
+   metric(
+      &mut out,
+      "kairpods_devices_tracked",
+      "gauge",
+      "Devices known to the service",
+   );
+   let _ = writeln!(out, "kairpods_devices_tracked {}", devices.len());
+   metric(
+      &mut out,
+      "kairpods_devices_connected",
+      "gauge",
+      "Devices with an active AAP connection",
+   );
+   let _ = writeln!(out, "kairpods_devices_connected {connected}");
+   metric(
+      &mut out,
+      "kairpods_event_queue_depth",
+      "gauge",
+      "Events waiting to be dispatched",
+   );
+   let _ = writeln!(out, "kairpods_event_queue_depth {queue_depth}");
+
+   metric(
+      &mut out,
+      "kairpods_device_connected",
+      "gauge",
+      "Whether each device is connected",
+   );
+   for device in devices {
+      let _ = writeln!(
+         out,
+         "kairpods_device_connected{{{}}} {}",
+         device_labels(device),
+         u8::from(device.is_connected())
+      );
+   }
+   // Levels stay at their last report while a device is away
+   metric(
+      &mut out,
+      "kairpods_battery_level_percent",
+      "gauge",
+      "Battery level of each component",
+   );
+   for device in devices {
+      for (component, state) in components(device) {
+         let _ = writeln!(
+            out,
+            "kairpods_battery_level_percent{{{},component=\"{component}\"}} {}",
+            device_labels(device),
+            state.level
+         );
+      }
+   }
+   metric(
+      &mut out,
+      "kairpods_battery_charging",
+      "gauge",
+      "Whether each component is charging",
+   );
+   for device in devices {
+      for (component, state) in components(device) {
+         let _ = writeln!(
+            out,
+            "kairpods_battery_charging{{{},component=\"{component}\"}} {}",
+            device_labels(device),
+            u8::from(state.status == BatteryStatus::Charging)
+         );
+      }
+   }
+   metric(
+      &mut out,
+      "kairpods_rssi_dbm",
+      "gauge",
+      "Smoothed signal strength",
+   );
+   for device in devices.iter().filter(|d| d.is_connected()) {
+      if let Some(rssi) = device.rssi() {
+         let _ = writeln!(out, "kairpods_rssi_dbm{{{}}} {rssi}", device_labels(device));
+      }
+   }
+   metric(
+      &mut out,
+      "kairpods_events_coalesced_total",
+      "counter",
+      "Events dropped by the rate limit in favor of a newer one",
+   );
+   for device in devices {
+      let count = coalesced.get(&device.address()).copied().unwrap_or(0);
+      let _ = writeln!(
+         out,
+         "kairpods_events_coalesced_total{{{}}} {count}",
+         device_labels(device)
+      );
+   }
+   out
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+   let _ = writeln!(out, "# HELP {name} {help}");
+   let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Reported battery components, skipping those not present.
+fn components(device: &AirPods) -> Vec<(&'static str, BatteryState)> {
+   let Some(battery) = device.battery_info() else {
+      return Vec::new();
+   };
+   [
+      ("left", battery.left),
+      ("right", battery.right),
+      ("case", battery.case),
+      ("headphone", battery.headphone),
+   ]
+   .into_iter()
+   .filter(|(_, state)| state.status != BatteryStatus::Disconnected)
+   .collect()
+}
+
+fn device_labels(device: &AirPods) -> String {
+   format!(
+      "address=\"{}\",name=\"{}\"",
+      device.address_str(),
+      escape_label(&device.name())
+   )
+}
+
+/// Escapes a label value as the text format requires.
+fn escape_label(value: &str) -> String {
+   value
+      .replace('\\', "\\\\")
+      .replace('"', "\\\"")
+      .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::airpods::protocol::BatteryInfo;
+
+   #[test]
+   fn renders_device_batteries() {
+      let device = AirPods::new(
+         "AA:BB:CC:DD:EE:FF".parse().unwrap(),
+         "Sam's \"AirPods\"".to_string(),
+         None,
+         None,
+      );
+      let idle = AirPods::new(
+         "11:22:33:44:55:66".parse().unwrap(),
+         "Spare".to_string(),
+         None,
+         None,
+      );
+      device.update_battery_info(BatteryInfo {
+         left: BatteryState {
+            level: 80,
+            status: BatteryStatus::Discharging,
+         },
+         case: BatteryState {
+            level: 40,
+            status: BatteryStatus::Charging,
+         },
+         ..BatteryInfo::new()
+      });
+
+      let coalesced = HashMap::from([(device.address(), 7)]);
+      let text = render(&[device, idle], 3, &coalesced);
+      let labels = r#"address="AA:BB:CC:DD:EE:FF",name="Sam's \"AirPods\"""#;
+      assert!(text.contains("kairpods_devices_tracked 2\n"));
+      assert!(text.contains("kairpods_devices_connected 0\n"));
+      assert!(text.contains("kairpods_event_queue_depth 3\n"));
+      assert!(text.contains(&format!(
+         "kairpods_battery_level_percent{{{labels},component=\"left\"}} 80\n"
+      )));
+      assert!(text.contains(&format!(
+         "kairpods_battery_charging{{{labels},component=\"case\"}} 1\n"
+      )));
+      assert!(text.contains(&format!("kairpods_device_connected{{{labels}}} 0\n")));
+      assert!(text.contains(&format!("kairpods_events_coalesced_total{{{labels}}} 7\n")));
+      assert!(text.contains(
+         "kairpods_events_coalesced_total{address=\"11:22:33:44:55:66\",name=\"Spare\"} 0\n"
+      ));
+      assert!(!text.contains("component=\"right\""));
+      assert!(!text.contains(r#"name="Spare",component"#));
+   }
+}
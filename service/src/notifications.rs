@@ -0,0 +1,175 @@
+//! Desktop notifications via `org.freedesktop.Notifications`.
+//!
+//! This module lets the daemon post notifications on its own, for setups
+//! that do not run the KDE applet.
+
+use std::{
+   collections::{HashMap, HashSet},
+   time::{Duration, Instant},
+};
+
+use bluer::Address;
+use log::{debug, warn};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use zbus::{Connection, zvariant::Value};
+
+use crate::{
+   airpods::{device::AirPods, protocol::BatteryInfo},
+   event::AirPodsEvent,
+   quiet_hours,
+};
+
+const NOTIFICATIONS_SERVICE: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+const APP_NAME: &str = "kAirPods";
+const APP_ICON: &str = "audio-headphones";
+
+/// Event types that can produce a desktop notification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationEvent {
+   Connected,
+   Disconnected,
+   LowBattery,
+}
+
+impl NotificationEvent {
+   pub const ALL: [Self; 3] = [Self::Connected, Self::Disconnected, Self::LowBattery];
+}
+
+/// Posts desktop notifications for selected device events.
+pub struct Notifier {
+   events: Vec<NotificationEvent>,
+   /// Battery level at or below which a bud counts as low
+   low_battery_level: u8,
+   /// Minimum time between notifications of one kind for one device
+   interval: Duration,
+   last_sent: Mutex<HashMap<(Address, NotificationEvent), Instant>>,
+   /// Devices already warned about since their battery was last above the threshold
+   low_battery: Mutex<HashSet<Address>>,
+}
+
+impl Notifier {
+   pub fn new(events: Vec<NotificationEvent>, low_battery_level: u8, interval: Duration) -> Self {
+      Self {
+         events,
+         low_battery_level,
+         interval,
+         last_sent: Mutex::new(HashMap::new()),
+         low_battery: Mutex::new(HashSet::new()),
+      }
+   }
+
+   /// Posts a notification for the event, if it is enabled and not throttled.
+   pub fn handle(&self, device: &AirPods, event: &AirPodsEvent) {
+      let address = device.address();
+      let name = device.name();
+      let (kind, summary, body) = match event {
+         AirPodsEvent::DeviceConnected => (
+            NotificationEvent::Connected,
+            format!("{name} connected"),
+            String::new(),
+         ),
+         AirPodsEvent::DeviceDisconnected(reason) => {
+            self.low_battery.lock().remove(&address);
+            (
+               NotificationEvent::Disconnected,
+               format!("{name} disconnected"),
+               format!("Reason: {reason}"),
+            )
+         },
+         AirPodsEvent::BatteryUpdated(battery) => {
+            let Some(level) = self.check_low_battery(address, battery) else {
+               return;
+            };
+            (
+               NotificationEvent::LowBattery,
+               format!("{name} battery low"),
+               format!("{level}% remaining"),
+            )
+         },
+         _ => return,
+      };
+
+      if !self.events.contains(&kind) {
+         return;
+      }
+      if quiet_hours::is_quiet() {
+         debug!("{address}: Quiet hours, not posting {kind:?} notification");
+         return;
+      }
+      if !self.should_send(address, kind) {
+         return;
+      }
+
+      tokio::spawn(async move {
+         if let Err(e) = send_notification(&summary, &body).await {
+            warn!("Failed to post desktop notification: {e}");
+         }
+      });
+   }
+
+   /// Returns the lowest bud level when the device first drops to or below
+   /// the threshold; charging buds are ignored.
+   fn check_low_battery(&self, address: Address, battery: &BatteryInfo) -> Option<u8> {
+      let lowest = [battery.left, battery.right, battery.headphone]
+         .into_iter()
+         .filter(|state| state.is_available() && !state.is_charging())
+         .map(|state| state.level)
+         .min();
+
+      let mut warned = self.low_battery.lock();
+      match lowest {
+         Some(level) if level <= self.low_battery_level => warned.insert(address).then_some(level),
+         _ => {
+            warned.remove(&address);
+            None
+         },
+      }
+   }
+
+   /// Records a notification about to be sent, unless one of the same kind
+   /// went out for the device within the throttle interval.
+   fn should_send(&self, address: Address, kind: NotificationEvent) -> bool {
+      let now = Instant::now();
+      let mut last_sent = self.last_sent.lock();
+      if let Some(last) = last_sent.get(&(address, kind))
+         && now.duration_since(*last) < self.interval
+      {
+         debug!("{address}: Throttling {kind:?} notification");
+         return false;
+      }
+      last_sent.insert((address, kind), now);
+      true
+   }
+}
+
+/// Posts a notification through the session's notification server.
+async fn send_notification(
+   summary: &str,
+   body: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+   let connection = Connection::session().await?;
+   let path = zbus::zvariant::ObjectPath::from_str_unchecked(NOTIFICATIONS_PATH);
+
+   connection
+      .call_method(
+         Some(NOTIFICATIONS_SERVICE),
+         &path,
+         Some(NOTIFICATIONS_SERVICE),
+         "Notify",
+         &(
+            APP_NAME,
+            0u32,
+            APP_ICON,
+            summary,
+            body,
+            Vec::<&str>::new(),
+            HashMap::<&str, Value>::new(),
+            -1i32,
+         ),
+      )
+      .await?;
+   Ok(())
+}
@@ -0,0 +1,155 @@
+//! Quiet hours during which the service stays out of the way.
+//!
+//! Within the window no desktop notifications are posted and, if enabled,
+//! removing or disconnecting the buds does not play or pause media. The
+//! window is a runtime setting in local time and may cross midnight.
+
+use std::{fmt, str::FromStr, time::SystemTime};
+
+use crossbeam::atomic::AtomicCell;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// A local time of day with minute precision, written as `HH:MM`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeOfDay(u16);
+
+impl TimeOfDay {
+   const MINUTES_PER_DAY: u16 = 24 * 60;
+
+   /// Gets the current local time.
+   pub fn now() -> Self {
+      let secs = SystemTime::now()
+         .duration_since(SystemTime::UNIX_EPOCH)
+         .map_or(0, |d| d.as_secs());
+      let time = secs as libc::time_t;
+      // SAFETY: `tm` is plain data and `localtime_r` only writes to it
+      let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+      if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+         // Without a usable time zone, UTC is the best guess
+         return Self(((secs / 60) % u64::from(Self::MINUTES_PER_DAY)) as u16);
+      }
+      Self((tm.tm_hour * 60 + tm.tm_min) as u16)
+   }
+}
+
+impl FromStr for TimeOfDay {
+   type Err = String;
+
+   fn from_str(s: &str) -> Result<Self, Self::Err> {
+      let invalid = || format!("Invalid time {s:?}, expected HH:MM");
+      let (hours, minutes) = s.trim().split_once(':').ok_or_else(invalid)?;
+      let hours: u16 = hours.parse().map_err(|_| invalid())?;
+      let minutes: u16 = minutes.parse().map_err(|_| invalid())?;
+      if hours >= 24 || minutes >= 60 {
+         return Err(invalid());
+      }
+      Ok(Self(hours * 60 + minutes))
+   }
+}
+
+impl TryFrom<String> for TimeOfDay {
+   type Error = String;
+
+   fn try_from(s: String) -> Result<Self, Self::Error> {
+      s.parse()
+   }
+}
+
+impl From<TimeOfDay> for String {
+   fn from(time: TimeOfDay) -> Self {
+      time.to_string()
+   }
+}
+
+impl fmt::Display for TimeOfDay {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      write!(f, "{:02}:{:02}", self.0 / 60, self.0 % 60)
+   }
+}
+
+/// A daily window, from `start` up to but excluding `end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuietHours {
+   pub start: TimeOfDay,
+   pub end: TimeOfDay,
+   /// Also suppress playing and pausing media on ear detection and
+   /// disconnects
+   #[serde(default)]
+   pub suppress_media: bool,
+}
+
+impl QuietHours {
+   /// Checks if the time falls within the window; a window whose end is
+   /// before its start runs past midnight.
+   pub fn contains(&self, time: TimeOfDay) -> bool {
+      if self.start <= self.end {
+         self.start <= time && time < self.end
+      } else {
+         time >= self.start || time < self.end
+      }
+   }
+
+   pub fn to_json(self) -> serde_json::Value {
+      json!({
+         "start": self.start.to_string(),
+         "end": self.end.to_string(),
+         "suppress_media": self.suppress_media,
+      })
+   }
+}
+
+static QUIET_HOURS: AtomicCell<Option<QuietHours>> = AtomicCell::new(None);
+
+pub fn set_quiet_hours(window: Option<QuietHours>) {
+   QUIET_HOURS.store(window);
+   match window {
+      Some(w) => debug!("Quiet hours set to {}-{}", w.start, w.end),
+      None => debug!("Quiet hours disabled"),
+   }
+}
+
+pub fn quiet_hours() -> Option<QuietHours> {
+   QUIET_HOURS.load()
+}
+
+/// Checks if quiet hours are in effect now.
+pub fn is_quiet() -> bool {
+   quiet_hours().is_some_and(|w| w.contains(TimeOfDay::now()))
+}
+
+/// Checks if media automation should be held back now.
+pub fn suppresses_media() -> bool {
+   quiet_hours().is_some_and(|w| w.suppress_media && w.contains(TimeOfDay::now()))
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn window(start: &str, end: &str) -> QuietHours {
+      QuietHours {
+         start: start.parse().unwrap(),
+         end: end.parse().unwrap(),
+         suppress_media: false,
+      }
+   }
+
+   #[test]
+   fn window_crossing_midnight() {
+      let night = window("22:30", "07:00");
+      for time in ["22:30", "23:59", "00:00", "06:59"] {
+         assert!(night.contains(time.parse().unwrap()), "{time}");
+      }
+      for time in ["07:00", "12:00", "22:29"] {
+         assert!(!night.contains(time.parse().unwrap()), "{time}");
+      }
+
+      let afternoon = window("13:00", "15:00");
+      assert!(afternoon.contains("14:00".parse().unwrap()));
+      assert!(!afternoon.contains("23:00".parse().unwrap()));
+      assert!("24:00".parse::<TimeOfDay>().is_err());
+   }
+}
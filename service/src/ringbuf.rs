@@ -1,4 +1,4 @@
-use std::fmt;
+use std::{collections::VecDeque, fmt};
 
 #[derive(Clone, Copy)]
 pub struct Ring<T: Default + Copy, const N: usize> {
@@ -50,6 +50,17 @@ impl<T: Default + Copy, const N: usize> Ring<T, N> {
       self.tail += 1;
    }
 
+   /// Push a slice to the back, copying only the elements that are kept.
+   pub fn extend_from_slice(&mut self, items: &[T]) {
+      let skip = items.len().saturating_sub(N);
+      let kept = &items[skip..];
+      let start = (self.tail + skip) % N;
+      let (first, second) = kept.split_at(kept.len().min(N - start));
+      self.data[start..start + first.len()].copy_from_slice(first);
+      self.data[..second.len()].copy_from_slice(second);
+      self.tail += items.len();
+   }
+
    /// Clears the buffer.
    pub const fn clear(&mut self) {
       self.tail = 0;
@@ -84,14 +95,15 @@ impl<T: Default + Copy, const N: usize> Ring<T, N> {
          return (&[], &[]);
       }
 
-      let head = self.head();
-      let tail_pos = self.tail % N;
-
-      if self.tail <= N || head < tail_pos {
-         // Data is contiguous
-         (&self.data[head..tail_pos], &[])
+      if len < N {
+         // Not yet full, so the data starts at the front and is contiguous
+         (&self.data[..len], &[])
       } else {
-         // Data wraps around
+         // Full: the oldest element sits where the next push goes, which is
+         // also the case for exactly N elements, where both indices are 0
+         let head = self.head();
+         let tail_pos = self.tail % N;
+         debug_assert_eq!(head, tail_pos);
          (&self.data[head..], &self.data[..tail_pos])
       }
    }
@@ -102,6 +114,54 @@ impl<T: Default + Copy, const N: usize> Ring<T, N> {
       RingIter { left, right }
    }
 
+   /// Oldest element matching the predicate.
+   pub fn find_first(&self, mut pred: impl FnMut(&T) -> bool) -> Option<&T> {
+      self.iter().find(|item| pred(item))
+   }
+
+   /// Newest element matching the predicate.
+   pub fn find_last(&self, mut pred: impl FnMut(&T) -> bool) -> Option<&T> {
+      self.iter().rev().find(|item| pred(item))
+   }
+
+   /// Oldest and newest elements matching the predicate, e.g. the bounds of
+   /// a time window. Both are the same element if only one matches.
+   pub fn find_bounds(&self, mut pred: impl FnMut(&T) -> bool) -> Option<(&T, &T)> {
+      let first = self.find_first(&mut pred)?;
+      let last = self.find_last(pred)?;
+      Some((first, last))
+   }
+
+   /// `true` if the buffer holds an element equal to `value`.
+   pub fn contains(&self, value: &T) -> bool
+   where
+      T: PartialEq,
+   {
+      let (left, right) = self.as_slices();
+      left.contains(value) || right.contains(value)
+   }
+
+   /// Logical index (0 = oldest) of the oldest element equal to `value`.
+   pub fn position(&self, value: &T) -> Option<usize>
+   where
+      T: PartialEq,
+   {
+      let (left, right) = self.as_slices();
+      left
+         .iter()
+         .position(|item| item == value)
+         .or_else(|| Some(left.len() + right.iter().position(|item| item == value)?))
+   }
+
+   /// Builds a buffer from an iterator, also returning how many items were
+   /// dropped because they did not fit.
+   pub fn from_iter_counted<I: IntoIterator<Item = T>>(iter: I) -> (Self, usize) {
+      let mut buffer = Self::new();
+      buffer.extend(iter);
+      let dropped = buffer.tail - buffer.len();
+      (buffer, dropped)
+   }
+
    /// Keep only the most-recent `count` elements.
    pub fn truncate_front(&mut self, count: usize) {
       if count >= self.len() {
@@ -149,6 +209,20 @@ impl<'a, T: Default + Copy> Iterator for RingIter<'a, T> {
    }
 }
 
+impl<T: Default + Copy> DoubleEndedIterator for RingIter<'_, T> {
+   fn next_back(&mut self) -> Option<Self::Item> {
+      if let Some((last, rest)) = self.right.split_last() {
+         self.right = rest;
+         Some(last)
+      } else if let Some((last, rest)) = self.left.split_last() {
+         self.left = rest;
+         Some(last)
+      } else {
+         None
+      }
+   }
+}
+
 impl<T: Default + Copy> ExactSizeIterator for RingIter<'_, T> {
    fn len(&self) -> usize {
       self.left.len() + self.right.len()
@@ -164,6 +238,61 @@ impl<'a, T: Default + Copy, const N: usize> IntoIterator for &'a Ring<T, N> {
    }
 }
 
+/// Owning iterator, oldest to newest.
+#[derive(Clone)]
+pub struct RingIntoIter<T: Default + Copy, const N: usize> {
+   ring: Ring<T, N>,
+   front: usize, // Logical index of the next element
+   back: usize,  // Logical index past the last element
+}
+
+impl<T: Default + Copy, const N: usize> Iterator for RingIntoIter<T, N> {
+   type Item = T;
+
+   fn next(&mut self) -> Option<Self::Item> {
+      if self.front == self.back {
+         return None;
+      }
+      let item = self.ring.get(self.front).copied();
+      self.front += 1;
+      item
+   }
+
+   fn size_hint(&self) -> (usize, Option<usize>) {
+      let remaining = self.back - self.front;
+      (remaining, Some(remaining))
+   }
+}
+
+impl<T: Default + Copy, const N: usize> DoubleEndedIterator for RingIntoIter<T, N> {
+   fn next_back(&mut self) -> Option<Self::Item> {
+      if self.front == self.back {
+         return None;
+      }
+      self.back -= 1;
+      self.ring.get(self.back).copied()
+   }
+}
+
+impl<T: Default + Copy, const N: usize> ExactSizeIterator for RingIntoIter<T, N> {
+   fn len(&self) -> usize {
+      self.back - self.front
+   }
+}
+
+impl<T: Default + Copy, const N: usize> IntoIterator for Ring<T, N> {
+   type Item = T;
+   type IntoIter = RingIntoIter<T, N>;
+
+   fn into_iter(self) -> Self::IntoIter {
+      RingIntoIter {
+         back: self.len(),
+         front: 0,
+         ring: self,
+      }
+   }
+}
+
 impl<T: Default + Copy + fmt::Debug, const N: usize> fmt::Debug for Ring<T, N> {
    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
       f.debug_list().entries(self.iter()).finish()
@@ -184,6 +313,113 @@ impl<T: Default + Copy, const N: usize> FromIterator<T> for Ring<T, N> {
    }
 }
 
+/// Ring buffer whose capacity is chosen at runtime.
+///
+/// Heap-backed counterpart of [`Ring`] for histories sized from config.
+#[derive(Clone)]
+pub struct DynRing<T> {
+   data: VecDeque<T>,
+   capacity: usize,
+}
+
+impl<T> DynRing<T> {
+   /// Constructs an empty buffer retaining at most `capacity` elements.
+   pub fn with_capacity(capacity: usize) -> Self {
+      Self {
+         data: VecDeque::with_capacity(capacity),
+         capacity,
+      }
+   }
+
+   /// Maximum number of retained elements.
+   #[inline]
+   pub const fn capacity(&self) -> usize {
+      self.capacity
+   }
+
+   /// Changes the capacity, dropping the oldest elements if it shrinks.
+   pub fn set_capacity(&mut self, capacity: usize) {
+      self.capacity = capacity;
+      self.truncate_front(capacity);
+      self.data.shrink_to(capacity);
+   }
+
+   /// Current number of elements.
+   #[inline]
+   pub fn len(&self) -> usize {
+      self.data.len()
+   }
+
+   /// `true` if the buffer is empty.
+   #[inline]
+   pub fn is_empty(&self) -> bool {
+      self.data.is_empty()
+   }
+
+   /// Push a value to the **back** (newest side) of the buffer.
+   pub fn push(&mut self, value: T) {
+      if self.capacity == 0 {
+         return;
+      }
+      if self.data.len() == self.capacity {
+         self.data.pop_front();
+      }
+      self.data.push_back(value);
+   }
+
+   /// Clears the buffer.
+   pub fn clear(&mut self) {
+      self.data.clear();
+   }
+
+   /// Get read-only access to element at `index` (0 = oldest).
+   #[inline]
+   pub fn get(&self, index: usize) -> Option<&T> {
+      self.data.get(index)
+   }
+
+   /// Newest element.
+   #[inline]
+   pub fn last(&self) -> Option<&T> {
+      self.data.back()
+   }
+
+   /// Iterator from oldest to newest.
+   pub fn iter(&self) -> std::collections::vec_deque::Iter<'_, T> {
+      self.data.iter()
+   }
+
+   /// Keep only the most-recent `count` elements.
+   pub fn truncate_front(&mut self, count: usize) {
+      if let Some(excess) = self.data.len().checked_sub(count) {
+         self.data.drain(..excess);
+      }
+   }
+}
+
+impl<T> Extend<T> for DynRing<T> {
+   fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+      for item in iter {
+         self.push(item);
+      }
+   }
+}
+
+impl<'a, T> IntoIterator for &'a DynRing<T> {
+   type Item = &'a T;
+   type IntoIter = std::collections::vec_deque::Iter<'a, T>;
+
+   fn into_iter(self) -> Self::IntoIter {
+      self.iter()
+   }
+}
+
+impl<T: fmt::Debug> fmt::Debug for DynRing<T> {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      f.debug_list().entries(self.iter()).finish()
+   }
+}
+
 #[cfg(test)]
 mod tests {
    use super::*;
@@ -212,6 +448,31 @@ mod tests {
       assert!(rb.is_empty());
    }
 
+   #[test]
+   fn extend_from_slice_keeps_last_n() {
+      let items: Vec<i32> = (0..100).collect();
+      let mut rb: Ring<i32, 8> = Ring::new();
+      rb.push(-1);
+      rb.extend_from_slice(&items);
+      assert_eq!(rb.len(), 8);
+      assert_eq!(
+         rb.iter().copied().collect::<Vec<_>>(),
+         (92..100).collect::<Vec<_>>()
+      );
+
+      // Short slices wrap around like individual pushes
+      rb.extend_from_slice(&[100, 101, 102]);
+      assert_eq!(
+         rb.iter().copied().collect::<Vec<_>>(),
+         (95..103).collect::<Vec<_>>()
+      );
+
+      // Exactly N items into an empty ring
+      let mut rb: Ring<i32, 4> = Ring::new();
+      rb.extend_from_slice(&[1, 2, 3, 4]);
+      assert_eq!(rb.iter().copied().collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+   }
+
    #[test]
    fn tail_increments_beyond_n() {
       let mut rb: Ring<i32, 4> = Ring::new();
@@ -265,6 +526,18 @@ mod tests {
       assert_eq!(right, &[5]);
    }
 
+   #[test]
+   fn as_slices_exactly_full() {
+      let mut rb: Ring<i32, 4> = Ring::new();
+      for i in 1..=4 {
+         rb.push(i);
+      }
+
+      let (left, right) = rb.as_slices();
+      assert_eq!(left, &[1, 2, 3, 4]);
+      assert!(right.is_empty());
+   }
+
    #[test]
    fn as_slices_empty() {
       let rb: Ring<i32, 5> = Ring::new();
@@ -273,6 +546,108 @@ mod tests {
       assert!(right.is_empty());
    }
 
+   #[test]
+   fn from_iter_counted_reports_dropped() {
+      let (rb, dropped) = Ring::<i32, 4>::from_iter_counted(1..=10);
+      assert_eq!(dropped, 6);
+      assert_eq!(rb.iter().copied().collect::<Vec<_>>(), vec![7, 8, 9, 10]);
+
+      let (rb, dropped) = Ring::<i32, 4>::from_iter_counted(1..=3);
+      assert_eq!(dropped, 0);
+      assert_eq!(rb.len(), 3);
+
+      // Exactly full
+      let (rb, dropped) = Ring::<i32, 4>::from_iter_counted([1, 2, 3, 4]);
+      assert_eq!(dropped, 0);
+      assert_eq!(rb.iter().copied().collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+   }
+
+   #[test]
+   fn iterator_rev_wrapped() {
+      let mut rb: Ring<i32, 4> = Ring::new();
+      for i in 1..=6 {
+         rb.push(i);
+      }
+      assert_eq!(
+         rb.iter().rev().copied().collect::<Vec<_>>(),
+         vec![6, 5, 4, 3]
+      );
+
+      let mut it = rb.iter();
+      assert_eq!(it.next(), Some(&3));
+      assert_eq!(it.next_back(), Some(&6));
+      assert_eq!(it.collect::<Vec<_>>(), vec![&4, &5]);
+   }
+
+   #[test]
+   fn find_bounds_over_wrapped_window() {
+      let mut rb: Ring<(u32, u8), 4> = Ring::new();
+      for (t, level) in [(0, 100), (10, 95), (20, 90), (30, 85), (40, 80), (50, 75)] {
+         rb.push((t, level));
+      } // logical contents: [(20,90), (30,85), (40,80), (50,75)]
+
+      assert_eq!(rb.find_first(|&(t, _)| t >= 30), Some(&(30, 85)));
+      assert_eq!(rb.find_last(|&(t, _)| t <= 40), Some(&(40, 80)));
+      assert_eq!(
+         rb.find_bounds(|&(t, _)| t >= 25),
+         Some((&(30, 85), &(50, 75)))
+      );
+      assert_eq!(
+         rb.find_bounds(|&(t, _)| t == 40),
+         Some((&(40, 80), &(40, 80)))
+      );
+      assert_eq!(rb.find_bounds(|&(t, _)| t < 20), None);
+   }
+
+   #[test]
+   fn contains_and_position() {
+      let mut rb: Ring<i32, 5> = Ring::new();
+      assert!(!rb.contains(&1));
+      assert_eq!(rb.position(&1), None);
+
+      rb.extend([1, 2, 3]);
+      assert!(rb.contains(&2));
+      assert_eq!(rb.position(&1), Some(0));
+      assert_eq!(rb.position(&3), Some(2));
+      assert!(!rb.contains(&4));
+      assert_eq!(rb.position(&4), None);
+
+      rb.extend([4, 5]); // exactly full: [1,2,3,4,5]
+      assert!(rb.contains(&5));
+      assert_eq!(rb.position(&1), Some(0));
+      assert_eq!(rb.position(&5), Some(4));
+
+      rb.extend(6..=7); // logical contents: [3,4,5,6,7], wrapped
+      assert!(!rb.as_slices().1.is_empty());
+      assert!(!rb.contains(&1));
+      assert_eq!(rb.position(&3), Some(0));
+      assert_eq!(rb.position(&6), Some(3));
+      assert_eq!(rb.position(&7), Some(4));
+      assert_eq!(rb.position(&2), None);
+
+      // The oldest match wins
+      rb.push(5); // [4,5,6,7,5]
+      assert_eq!(rb.position(&5), Some(1));
+   }
+
+   #[test]
+   fn into_iter_owned_wrapped() {
+      let mut rb: Ring<i32, 4> = Ring::new();
+      for i in 1..=7 {
+         rb.push(i);
+      }
+      let owned: Vec<i32> = rb.into_iter().collect();
+      assert_eq!(owned, vec![4, 5, 6, 7]);
+
+      let mut it = rb.into_iter();
+      assert_eq!(it.len(), 4);
+      assert_eq!(it.next_back(), Some(7));
+      assert_eq!(it.next(), Some(4));
+      assert_eq!(it.collect::<Vec<_>>(), vec![5, 6]);
+
+      assert_eq!(Ring::<i32, 4>::new().into_iter().next(), None);
+   }
+
    #[test]
    fn iterator_with_slices() {
       let mut rb: Ring<i32, 4> = Ring::new();
@@ -284,4 +659,40 @@ mod tests {
       let collected: Vec<i32> = rb.iter().copied().collect();
       assert_eq!(collected, vec![3, 4, 5, 6]);
    }
+
+   #[test]
+   fn dyn_ring_matches_ring() {
+      let mut fixed: Ring<i32, 4> = Ring::new();
+      let mut dynamic = DynRing::with_capacity(4);
+      for i in 1..=7 {
+         fixed.push(i);
+         dynamic.push(i);
+      }
+      assert_eq!(dynamic.len(), fixed.len());
+      assert_eq!(dynamic.last(), fixed.last());
+      assert!(dynamic.iter().eq(fixed.iter()));
+
+      fixed.truncate_front(2);
+      dynamic.truncate_front(2);
+      assert!(dynamic.iter().eq(fixed.iter()));
+   }
+
+   #[test]
+   fn dyn_ring_set_capacity() {
+      let mut rb = DynRing::with_capacity(3);
+      rb.extend(1..=3);
+
+      // Growing keeps everything and makes room for more
+      rb.set_capacity(5);
+      rb.extend(4..=6);
+      assert_eq!(rb.iter().copied().collect::<Vec<_>>(), vec![2, 3, 4, 5, 6]);
+
+      // Shrinking drops the oldest
+      rb.set_capacity(2);
+      assert_eq!(rb.iter().copied().collect::<Vec<_>>(), vec![5, 6]);
+
+      rb.set_capacity(0);
+      rb.push(7);
+      assert!(rb.is_empty());
+   }
 }
@@ -0,0 +1,61 @@
+//! Minimal `sd_notify` client for running as a systemd service.
+//!
+//! Reports readiness and watchdog keepalives over the datagram socket named
+//! by `NOTIFY_SOCKET`. Without that variable every call is a no-op, so the
+//! service behaves the same when started outside systemd.
+
+use std::{
+   env,
+   ffi::OsStr,
+   io,
+   os::{
+      linux::net::SocketAddrExt,
+      unix::{
+         ffi::OsStrExt,
+         net::{SocketAddr, UnixDatagram},
+      },
+   },
+   time::Duration,
+};
+
+use log::debug;
+
+/// Service startup is complete
+pub const READY: &str = "READY=1";
+/// Service is shutting down
+pub const STOPPING: &str = "STOPPING=1";
+/// Watchdog keepalive
+pub const WATCHDOG: &str = "WATCHDOG=1";
+
+/// Sends a state change to the service manager, if there is one.
+pub fn notify(state: &str) {
+   let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+      return;
+   };
+   if let Err(e) = send(&path, state) {
+      debug!("Failed to notify service manager of {state}: {e}");
+   }
+}
+
+fn send(path: &OsStr, state: &str) -> io::Result<()> {
+   let socket = UnixDatagram::unbound()?;
+   // A leading '@' names a socket in the abstract namespace
+   if let Some(name) = path.as_bytes().strip_prefix(b"@") {
+      socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)?;
+   } else {
+      socket.send_to(state.as_bytes(), path)?;
+   }
+   Ok(())
+}
+
+/// Watchdog timeout requested for this process via `WATCHDOG_USEC`, if any.
+pub fn watchdog_timeout() -> Option<Duration> {
+   env::var_os("NOTIFY_SOCKET")?;
+   if let Ok(pid) = env::var("WATCHDOG_PID")
+      && pid.parse().ok() != Some(std::process::id())
+   {
+      return None;
+   }
+   let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+   (usec > 0).then(|| Duration::from_micros(usec))
+}
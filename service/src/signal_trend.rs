@@ -0,0 +1,134 @@
+//! Classification of where the signal strength is heading.
+//!
+//! Recent RSSI readings are kept in a ring and fitted with a least-squares
+//! line; a slope beyond the threshold means the device is getting closer or
+//! moving away. The threshold is a runtime setting, in dB per reading.
+
+use crossbeam::atomic::AtomicCell;
+use log::debug;
+
+use crate::ringbuf::Ring;
+
+/// Readings kept for the trend, one per connection health check
+pub const RSSI_HISTORY_LEN: usize = 12;
+/// Fewest readings to classify a trend from
+const MIN_SAMPLES: usize = 4;
+
+/// Default slope, in dB per reading, beyond which the signal is trending
+pub const DEFAULT_THRESHOLD: f64 = 0.5;
+
+static THRESHOLD: AtomicCell<f64> = AtomicCell::new(DEFAULT_THRESHOLD);
+
+/// Recent raw RSSI readings, oldest first.
+pub type RssiHistory = Ring<i16, RSSI_HISTORY_LEN>;
+
+/// Direction the signal strength is moving in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum SignalTrend {
+   Approaching,
+   Stable,
+   Leaving,
+}
+
+impl SignalTrend {
+   pub fn to_str(self) -> &'static str {
+      self.into()
+   }
+}
+
+pub fn validate_threshold(threshold: f64) -> Result<f64, String> {
+   if threshold.is_finite() && threshold > 0.0 {
+      Ok(threshold)
+   } else {
+      Err(format!(
+         "signal_trend_threshold must be greater than 0, got {threshold}"
+      ))
+   }
+}
+
+pub fn set_threshold(threshold: f64) -> Result<(), String> {
+   let threshold = validate_threshold(threshold)?;
+   THRESHOLD.store(threshold);
+   debug!("Signal trend threshold set to {threshold} dB per reading");
+   Ok(())
+}
+
+pub fn threshold() -> f64 {
+   THRESHOLD.load()
+}
+
+/// Classifies the readings by their slope, or `None` with too few of them.
+pub fn classify(history: &RssiHistory, threshold: f64) -> Option<SignalTrend> {
+   let n = history.len();
+   if n < MIN_SAMPLES {
+      return None;
+   }
+   // Least-squares slope against the reading index
+   let n_f = n as f64;
+   let mean_x = (n_f - 1.0) / 2.0;
+   let mean_y = history.iter().map(|&y| f64::from(y)).sum::<f64>() / n_f;
+   let (mut num, mut den) = (0.0, 0.0);
+   for (x, &y) in history.iter().enumerate() {
+      let dx = x as f64 - mean_x;
+      num += dx * (f64::from(y) - mean_y);
+      den += dx * dx;
+   }
+   let slope = num / den;
+
+   Some(if slope >= threshold {
+      SignalTrend::Approaching
+   } else if slope <= -threshold {
+      SignalTrend::Leaving
+   } else {
+      SignalTrend::Stable
+   })
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn history(readings: &[i16]) -> RssiHistory {
+      let mut history = RssiHistory::new();
+      history.extend_from_slice(readings);
+      history
+   }
+
+   #[test]
+   fn slope_sets_the_trend() {
+      let rising = history(&[-80, -78, -77, -74, -73, -70, -69, -66]);
+      assert_eq!(classify(&rising, 0.5), Some(SignalTrend::Approaching));
+
+      let falling = history(&[-50, -52, -51, -55, -57, -58, -61, -63]);
+      assert_eq!(classify(&falling, 0.5), Some(SignalTrend::Leaving));
+
+      // Jitter without a direction
+      let flat = history(&[-60, -62, -59, -61, -60, -62, -59, -61]);
+      assert_eq!(classify(&flat, 0.5), Some(SignalTrend::Stable));
+      // A higher threshold tolerates a slow drift
+      assert_eq!(classify(&rising, 3.0), Some(SignalTrend::Stable));
+
+      assert_eq!(classify(&history(&[-80, -70, -60]), 0.5), None);
+   }
+
+   #[test]
+   fn full_window_is_classified() {
+      let readings: Vec<i16> = (0..RSSI_HISTORY_LEN as i16).map(|i| -80 + 2 * i).collect();
+      assert_eq!(
+         classify(&history(&readings), 0.5),
+         Some(SignalTrend::Approaching)
+      );
+   }
+
+   #[test]
+   fn only_the_window_counts() {
+      // An old approach has scrolled out of the window
+      let mut readings = vec![-90, -85, -80, -75];
+      readings.extend([-70; RSSI_HISTORY_LEN]);
+      assert_eq!(
+         classify(&history(&readings), 0.5),
+         Some(SignalTrend::Stable)
+      );
+   }
+}
@@ -0,0 +1,170 @@
+//! Synthetic event injection for UI development without hardware.
+//!
+//! Only compiled with the `testing` feature.
+
+use std::str::FromStr;
+
+use serde_json::Value;
+
+use crate::{
+   airpods::{
+      device::AirPods,
+      protocol::{
+         BatteryInfo, BatteryState, BatteryStatus, BudState, EarDetectionStatus, FeatureId,
+         NoiseControlMode,
+      },
+   },
+   event::{AirPodsEvent, ConnectionState, DisconnectReason},
+};
+
+/// Parses an event description such as `{"type": "noise_mode", "mode": "anc"}`.
+///
+/// Supported types: `connected`, `disconnected` (`reason`),
+/// `connection_state` (`state`), `battery`
+/// (`left`/`right`/`case`/`headphone` as `{level, charging}` or null),
+/// `noise_mode` (`mode`), `ear_detection` (`left`, `right` as a bud state
+/// or in-ear boolean), `name`
+/// (`name`), `feature` (`feature`, `enabled`), `spatial` (`head_tracking`),
+/// `volume` (`volume`) and `error`.
+pub fn parse_event(json: &str) -> Result<AirPodsEvent, String> {
+   let value: Value = serde_json::from_str(json).map_err(|e| format!("Invalid JSON: {e}"))?;
+   let field = |key: &str| value.get(key).filter(|v| !v.is_null());
+   let str_field = |key: &str| {
+      field(key)
+         .and_then(Value::as_str)
+         .ok_or_else(|| format!("Missing or invalid '{key}' field"))
+   };
+   let bool_field = |key: &str| {
+      field(key)
+         .and_then(Value::as_bool)
+         .ok_or_else(|| format!("Missing or invalid '{key}' field"))
+   };
+
+   let event = match str_field("type")? {
+      "connected" => AirPodsEvent::DeviceConnected,
+      "disconnected" => {
+         let reason = match field("reason") {
+            Some(_) => {
+               let reason = str_field("reason")?;
+               DisconnectReason::from_str(reason)
+                  .map_err(|_| format!("Invalid disconnect reason: {reason:?}"))?
+            },
+            None => DisconnectReason::User,
+         };
+         AirPodsEvent::DeviceDisconnected(reason)
+      },
+      "connection_state" => {
+         let state = str_field("state")?;
+         AirPodsEvent::ConnectionStateChanged(
+            ConnectionState::from_str(state)
+               .map_err(|_| format!("Invalid connection state: {state:?}"))?,
+         )
+      },
+      "battery" => AirPodsEvent::BatteryUpdated(BatteryInfo {
+         left: parse_battery_state(field("left"))?,
+         right: parse_battery_state(field("right"))?,
+         case: parse_battery_state(field("case"))?,
+         headphone: parse_battery_state(field("headphone"))?,
+         primary: None,
+      }),
+      "noise_mode" => {
+         let mode = str_field("mode")?;
+         AirPodsEvent::NoiseControlChanged(
+            NoiseControlMode::from_str(mode)
+               .map_err(|_| format!("Invalid noise mode: {mode:?}"))?,
+         )
+      },
+      "ear_detection" => AirPodsEvent::EarDetectionChanged(EarDetectionStatus::from_states(
+         parse_bud_state(field("left"))?,
+         parse_bud_state(field("right"))?,
+      )),
+      "name" => AirPodsEvent::DeviceNameChanged(str_field("name")?.into()),
+      "feature" => {
+         let feature = str_field("feature")?;
+         AirPodsEvent::FeatureChanged(
+            FeatureId::from_str(feature).map_err(|_| format!("Invalid feature: {feature:?}"))?,
+            bool_field("enabled")?,
+         )
+      },
+      "spatial" => AirPodsEvent::SpatialStateChanged(bool_field("head_tracking")?),
+      "volume" => AirPodsEvent::VolumeChanged(
+         field("volume")
+            .and_then(Value::as_u64)
+            .filter(|v| *v <= 100)
+            .ok_or("Volume must be 0-100")? as u8,
+      ),
+      "error" => AirPodsEvent::DeviceError,
+      other => return Err(format!("Unknown event type: {other:?}")),
+   };
+   Ok(event)
+}
+
+/// Accepts either a placement name or an in-ear boolean.
+fn parse_bud_state(value: Option<&Value>) -> Result<BudState, String> {
+   match value {
+      Some(Value::Bool(true)) => Ok(BudState::InEar),
+      Some(Value::Bool(false)) => Ok(BudState::Out),
+      Some(Value::String(state)) => {
+         BudState::from_str(state).map_err(|_| format!("Invalid bud state: {state:?}"))
+      },
+      _ => Err(
+         "Bud state must be \"in_ear\", \"in_case\", \"held\", \"out\" or a boolean".to_string(),
+      ),
+   }
+}
+
+fn parse_battery_state(value: Option<&Value>) -> Result<BatteryState, String> {
+   let Some(value) = value else {
+      return Ok(BatteryState::new());
+   };
+   let level = value
+      .get("level")
+      .and_then(Value::as_u64)
+      .filter(|l| *l <= 100)
+      .ok_or("Battery level must be 0-100")?;
+   let charging = value
+      .get("charging")
+      .and_then(Value::as_bool)
+      .unwrap_or(false);
+   Ok(BatteryState {
+      level: level as u8,
+      status: if charging {
+         BatteryStatus::Charging
+      } else {
+         BatteryStatus::Discharging
+      },
+   })
+}
+
+/// Mirrors the event into the device state so `GetDevices` agrees with the
+/// emitted signals.
+pub fn apply_event(device: &AirPods, event: &AirPodsEvent) {
+   match event {
+      AirPodsEvent::BatteryUpdated(battery) => {
+         device.update_battery_info(*battery);
+      },
+      AirPodsEvent::NoiseControlChanged(mode) => {
+         device.update_noise_mode(*mode);
+      },
+      AirPodsEvent::EarDetectionChanged(status) => {
+         device.update_ear_detection(*status);
+      },
+      AirPodsEvent::DeviceNameChanged(name) => {
+         device.update_name(name.clone());
+      },
+      AirPodsEvent::FeatureChanged(feature, enabled) => {
+         device.set_feature_enabled(*feature, *enabled);
+      },
+      AirPodsEvent::ConnectionStateChanged(state) => {
+         device.update_connection_state(*state);
+      },
+      AirPodsEvent::VolumeChanged(volume) => {
+         device.update_volume(*volume);
+      },
+      AirPodsEvent::DeviceConnected
+      | AirPodsEvent::DeviceDisconnected(_)
+      | AirPodsEvent::DeviceError
+      | AirPodsEvent::SpatialStateChanged(_)
+      | AirPodsEvent::RawFrame(_) => {},
+   }
+}
@@ -0,0 +1,63 @@
+//! Exponential moving-average smoothing of reported values.
+//!
+//! The weights are runtime settings so users can trade responsiveness for
+//! stability: an alpha near 1 follows new readings closely, one near 0
+//! barely moves.
+
+use crossbeam::atomic::AtomicCell;
+use log::debug;
+
+/// Default weight of a new RSSI reading
+pub const DEFAULT_RSSI_ALPHA: f64 = 0.3;
+
+/// Weight of a new RSSI reading
+static RSSI_ALPHA: AtomicCell<f64> = AtomicCell::new(DEFAULT_RSSI_ALPHA);
+
+/// Weight of a new battery time-to-live estimate; 0 picks it automatically
+/// from how much local data the estimate is based on
+static BATTERY_ALPHA: AtomicCell<f64> = AtomicCell::new(0.0);
+
+/// Checks that a smoothing weight is within 0–1.
+pub fn validate_alpha(name: &str, alpha: f64) -> Result<f64, String> {
+   if (0.0..=1.0).contains(&alpha) {
+      Ok(alpha)
+   } else {
+      Err(format!("{name} must be between 0 and 1, got {alpha}"))
+   }
+}
+
+/// Checks an RSSI weight, which unlike the battery one can't be 0.
+pub fn validate_rssi_alpha(alpha: f64) -> Result<f64, String> {
+   let alpha = validate_alpha("rssi_smoothing_alpha", alpha)?;
+   if alpha == 0.0 {
+      return Err("rssi_smoothing_alpha must be greater than 0".to_string());
+   }
+   Ok(alpha)
+}
+
+pub fn set_rssi_alpha(alpha: f64) -> Result<(), String> {
+   let alpha = validate_rssi_alpha(alpha)?;
+   RSSI_ALPHA.store(alpha);
+   debug!("RSSI smoothing alpha set to {alpha}");
+   Ok(())
+}
+
+pub fn rssi_alpha() -> f64 {
+   RSSI_ALPHA.load()
+}
+
+pub fn set_battery_alpha(alpha: f64) -> Result<(), String> {
+   let alpha = validate_alpha("battery_smoothing_alpha", alpha)?;
+   BATTERY_ALPHA.store(alpha);
+   debug!("Battery smoothing alpha set to {alpha}");
+   Ok(())
+}
+
+pub fn battery_alpha() -> f64 {
+   BATTERY_ALPHA.load()
+}
+
+/// Blends a new reading into the previous smoothed value.
+pub fn ema(prev: Option<f64>, value: f64, alpha: f64) -> f64 {
+   prev.map_or(value, |prev| value.mul_add(alpha, prev * (1.0 - alpha)))
+}
@@ -0,0 +1,301 @@
+//! Line-delimited JSON control socket.
+//!
+//! Mirrors the `org.kairpods.manager` methods over a Unix domain socket for
+//! setups without a session bus. Each request is one line such as
+//! `{"id": 1, "method": "GetDevice", "params": ["AA:BB:CC:DD:EE:FF"]}` and is
+//! answered by one line carrying the same `id` and either a `result` or an
+//! `error` with the D-Bus error `name` and a `message`.
+
+use std::{
+   collections::HashMap,
+   fs,
+   os::unix::fs::FileTypeExt,
+   path::{Path, PathBuf},
+   sync::Arc,
+};
+
+use log::{debug, info, warn};
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use tokio::{
+   io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+   net::{UnixListener, UnixStream},
+   task::JoinHandle,
+};
+use zbus::{DBusError, fdo, object_server::SignalEmitter, zvariant};
+
+use crate::{
+   dbus::AirPodsService,
+   error::{AirPodsError, Result},
+};
+
+/// Serves the control socket, sharing the device state of the D-Bus service.
+pub struct SocketServer {
+   service: AirPodsService,
+   /// Emits D-Bus signals for changes made over the socket, when a session
+   /// bus is available
+   emitter: Option<SignalEmitter<'static>>,
+}
+
+impl SocketServer {
+   pub const fn new(service: AirPodsService, emitter: Option<SignalEmitter<'static>>) -> Self {
+      Self { service, emitter }
+   }
+
+   /// Binds the socket, readable and writable only by the current user, and
+   /// starts accepting clients.
+   pub async fn listen(self, path: PathBuf) -> Result<JoinHandle<()>> {
+      if let Ok(metadata) = fs::symlink_metadata(&path) {
+         if !metadata.file_type().is_socket() {
+            // Likely a misconfigured socket_path; never delete the file
+            return Err(AirPodsError::Io(std::io::Error::new(
+               std::io::ErrorKind::AlreadyExists,
+               format!("{} exists and is not a socket", path.display()),
+            )));
+         }
+         if UnixStream::connect(&path).await.is_ok() {
+            return Err(AirPodsError::Io(std::io::Error::new(
+               std::io::ErrorKind::AddrInUse,
+               format!("{} is in use by another process", path.display()),
+            )));
+         }
+         debug!("Removing stale socket {}", path.display());
+         fs::remove_file(&path)?;
+      }
+      if let Some(parent) = path.parent() {
+         fs::create_dir_all(parent)?;
+      }
+
+      // Create the socket as 0600 rather than narrowing it after binding,
+      // which would leave a window where other users could connect
+      // SAFETY: umask has no preconditions and cannot fail
+      let umask = unsafe { libc::umask(0o177) };
+      let listener = UnixListener::bind(&path);
+      // SAFETY: as above
+      unsafe { libc::umask(umask) };
+      let listener = listener?;
+      info!("Control socket listening at {}", path.display());
+
+      let server = Arc::new(self);
+      Ok(tokio::spawn(async move {
+         loop {
+            match listener.accept().await {
+               Ok((stream, _)) => {
+                  let server = server.clone();
+                  tokio::spawn(async move {
+                     if let Err(e) = server.serve_client(stream).await {
+                        debug!("Control socket client error: {e}");
+                     }
+                  });
+               },
+               Err(e) => warn!("Failed to accept control socket client: {e}"),
+            }
+         }
+      }))
+   }
+
+   async fn serve_client(&self, stream: UnixStream) -> std::io::Result<()> {
+      let (reader, mut writer) = stream.into_split();
+      let mut lines = BufReader::new(reader).lines();
+      while let Some(line) = lines.next_line().await? {
+         if line.trim().is_empty() {
+            continue;
+         }
+         let mut response = self.handle_request(&line).await.to_string();
+         response.push('\n');
+         writer.write_all(response.as_bytes()).await?;
+      }
+      Ok(())
+   }
+
+   async fn handle_request(&self, line: &str) -> Value {
+      let request: Value = match serde_json::from_str(line) {
+         Ok(request) => request,
+         Err(e) => {
+            return error_response(
+               Value::Null,
+               &fdo::Error::InvalidArgs(format!("Invalid request: {e}")),
+            );
+         },
+      };
+      let id = request.get("id").cloned().unwrap_or(Value::Null);
+      let Some(method) = request.get("method").and_then(Value::as_str) else {
+         return error_response(id, &fdo::Error::InvalidArgs("Missing 'method'".to_string()));
+      };
+      let params = match request.get("params") {
+         None | Some(Value::Null) => Vec::new(),
+         Some(Value::Array(params)) => params.clone(),
+         Some(_) => {
+            return error_response(
+               id,
+               &fdo::Error::InvalidArgs("'params' must be an array".to_string()),
+            );
+         },
+      };
+
+      debug!("Control socket request: {method}");
+      match self.call(method, &params).await {
+         Ok(result) => json!({ "id": id, "result": result }),
+         Err(e) => error_response(id, &e),
+      }
+   }
+
+   /// Invokes the service method of the same name as on D-Bus.
+   async fn call(&self, method: &str, p: &[Value]) -> fdo::Result<Value> {
+      let s = &self.service;
+      let emitter = self.emitter.as_ref();
+      let result = match method {
+         "GetDevices" => json!(s.get_devices().await?),
+         "GetDevicesFiltered" => json!(s.get_devices_filtered(arg(p, 0)?).await?),
+         "GetDevice" => json!(s.get_device(arg(p, 0)?).await?),
+         "ListFeatures" => json!(s.list_features(arg(p, 0)?).await?),
+         "GetErrorLog" => json!(s.get_error_log(arg(p, 0)?).await?),
+         "GetCaseStatus" => json!(s.get_case_status(arg(p, 0)?).await?),
+         "GetPrimaryBud" => json!(s.get_primary_bud(arg(p, 0)?).await?),
+         "GetEarDetection" => json!(s.get_ear_detection(arg(p, 0)?).await?),
+         "GetFitTest" => json!(s.get_fit_test(arg(p, 0)?).await?),
+         "GetNoiseControl" => json!(s.get_noise_control(arg(p, 0)?).await?),
+         "GetNoiseModes" => json!(s.get_noise_modes(arg(p, 0)?).await?),
+         "GetAllBatteries" => json!(s.get_all_batteries().await?),
+         "GetBatteryHistory" => json!(s.get_battery_history(arg(p, 0)?).await?),
+         "GetBatteryStudySummary" => json!(s.get_battery_study_summary(arg(p, 0)?).await?),
+         "GetConnectionUptime" => json!(s.get_connection_uptime(arg(p, 0)?).await?),
+         "GetBatteryRaw" => json!(s.get_battery_raw(arg(p, 0)?).await?),
+         "GetHandshake" => json!(s.get_handshake(arg(p, 0)?).await?),
+         "GetAdvertisement" => json!(s.get_advertisement(arg(p, 0)?).await?),
+         "Passthrough" => json!(s.passthrough(arg(p, 0)?, arg(p, 1)?).await?),
+         "PassthroughUnchecked" => json!(s.passthrough_unchecked(arg(p, 0)?, arg(p, 1)?).await?),
+         "SendCommand" => {
+            let params = command_params(p.get(2))?;
+            let action: String = arg(p, 1)?;
+            json!(s.run_command(arg(p, 0)?, &action, &params, emitter).await?)
+         },
+         "BroadcastCommand" => {
+            let params = command_params(p.get(1))?;
+            json!(s.run_broadcast_command(arg(p, 0)?, params, emitter).await?)
+         },
+         "SetFeatures" => json!(s.apply_features(arg(p, 0)?, arg(p, 1)?, emitter).await?),
+         "Scan" => json!(s.scan(arg(p, 0)?).await?),
+         "ConnectDevice" => json!(s.connect_device(arg(p, 0)?).await?),
+         "ConnectDeviceSync" => json!(s.connect_device_sync(arg(p, 0)?, arg(p, 1)?).await?),
+         "DisconnectDevice" => json!(s.disconnect_device(arg(p, 0)?).await?),
+         "ReconnectDevice" => json!(s.reconnect_device(arg(p, 0)?).await?),
+         "ResetDevice" => json!(s.reset_device(arg(p, 0)?).await?),
+         "SetEarDetectionSensitivity" => {
+            json!(
+               s.set_ear_detection_sensitivity(arg(p, 0)?, arg(p, 1)?)
+                  .await?
+            )
+         },
+         "StopSound" => json!(s.stop_sound(arg(p, 0)?, arg(p, 1)?).await?),
+         "RefreshBattery" => json!(s.refresh_battery(arg(p, 0)?).await?),
+         "SetBatteryOffset" => {
+            json!(
+               s.set_battery_offset(arg(p, 0)?, arg(p, 1)?, arg(p, 2)?)
+                  .await?
+            )
+         },
+         "SetIgnoreEarSensor" => {
+            json!(
+               s.set_ignore_ear_sensor(arg(p, 0)?, arg(p, 1)?, arg(p, 2)?)
+                  .await?
+            )
+         },
+         "SetAutoPlayPause" => {
+            s.update_auto_play_pause(arg(p, 0)?, emitter).await?;
+            json!(true)
+         },
+         "GetAutoPlayPause" => json!(s.get_auto_play_pause().await?),
+         "SetResumeOnReinsert" => json!(s.set_resume_on_reinsert(arg(p, 0)?).await?),
+         "GetResumeOnReinsert" => json!(s.get_resume_on_reinsert().await?),
+         "SetSingleBudMode" => json!(s.set_single_bud_mode(arg(p, 0)?).await?),
+         "GetSingleBudMode" => json!(s.get_single_bud_mode().await?),
+         "SetIgnoreHeld" => json!(s.set_ignore_held(arg(p, 0)?).await?),
+         "GetIgnoreHeld" => json!(s.get_ignore_held().await?),
+         "SetMediaControlDevice" => json!(s.set_media_control_device(arg(p, 0)?).await?),
+         "GetMediaControlDevice" => json!(s.get_media_control_device().await?),
+         "MediaNext" => json!(s.media_next().await?),
+         "MediaPrevious" => json!(s.media_previous().await?),
+         "MediaPlayPause" => json!(s.media_play_pause().await?),
+         "SetAllowedPlayers" => json!(s.set_allowed_players(arg(p, 0)?).await?),
+         "GetAllowedPlayers" => json!(s.get_allowed_players().await?),
+         "SetRssiSmoothingAlpha" => json!(s.set_rssi_smoothing_alpha(arg(p, 0)?).await?),
+         "GetRssiSmoothingAlpha" => json!(s.get_rssi_smoothing_alpha().await?),
+         "SetBatterySmoothingAlpha" => json!(s.set_battery_smoothing_alpha(arg(p, 0)?).await?),
+         "GetBatterySmoothingAlpha" => json!(s.get_battery_smoothing_alpha().await?),
+         "SetQuietHours" => {
+            json!(
+               s.set_quiet_hours(arg(p, 0)?, arg(p, 1)?, arg(p, 2)?)
+                  .await?
+            )
+         },
+         "GetQuietHours" => json!(s.get_quiet_hours().await?),
+         "Quit" => json!(s.request_quit()),
+         "SetLogFilter" => json!(s.set_log_filter(arg(p, 0)?, arg(p, 1)?).await?),
+         _ => {
+            return Err(fdo::Error::UnknownMethod(format!(
+               "Unknown method: {method}"
+            )));
+         },
+      };
+      Ok(result)
+   }
+}
+
+/// Removes the socket file on shutdown.
+pub fn remove(path: &Path) {
+   if let Err(e) = fs::remove_file(path) {
+      debug!("Failed to remove control socket {}: {e}", path.display());
+   }
+}
+
+fn error_response(id: Value, error: &fdo::Error) -> Value {
+   json!({
+      "id": id,
+      "error": {
+         "name": error.name().as_str(),
+         "message": error.description().unwrap_or_default(),
+      },
+   })
+}
+
+/// Deserializes the positional argument at `index`.
+fn arg<T: DeserializeOwned>(params: &[Value], index: usize) -> fdo::Result<T> {
+   let value = params
+      .get(index)
+      .ok_or_else(|| fdo::Error::InvalidArgs(format!("Missing argument {index}")))?;
+   T::deserialize(value)
+      .map_err(|e| fdo::Error::InvalidArgs(format!("Invalid argument {index}: {e}")))
+}
+
+/// Converts a JSON object into `SendCommand` parameters; numbers that fit
+/// become `u32`, as sent by D-Bus clients.
+fn command_params(value: Option<&Value>) -> fdo::Result<HashMap<String, zvariant::Value<'static>>> {
+   let Some(value) = value else {
+      return Ok(HashMap::new());
+   };
+   let Value::Object(map) = value else {
+      return Err(fdo::Error::InvalidArgs(
+         "Command parameters must be an object".to_string(),
+      ));
+   };
+
+   map.iter()
+      .map(|(key, value)| {
+         let value = match value {
+            Value::String(s) => zvariant::Value::from(s.clone()),
+            Value::Bool(b) => zvariant::Value::from(*b),
+            Value::Number(n) => match n.as_u64().and_then(|n| u32::try_from(n).ok()) {
+               Some(n) => zvariant::Value::from(n),
+               None => zvariant::Value::from(n.as_f64().unwrap_or_default()),
+            },
+            _ => {
+               return Err(fdo::Error::InvalidArgs(format!(
+                  "Unsupported value for '{key}'"
+               )));
+            },
+         };
+         Ok((key.clone(), value))
+      })
+      .collect()
+}